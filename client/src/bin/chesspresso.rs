@@ -1,6 +1,6 @@
 use alloy::{
     network::{EthereumWallet, TransactionBuilder},
-    primitives::Address,
+    primitives::{Address, B256, U256},
     providers::{Provider, ProviderBuilder},
     rpc::types::TransactionRequest,
     signers::local::{coins_bip39::English, MnemonicBuilder},
@@ -9,15 +9,25 @@ use alloy::{
 };
 use anyhow::{ensure, Context};
 use chesspresso_core::{
+    clock::TimeControl,
     db::Db,
-    game::{GameId, San},
-    message::Advance,
+    game::{Color, Game, GameId, GameSetup, San, Variant},
+    message::{Advance, Challenge, ChallengeId, GameFilter, PuzzleId, SeekId, TournamentId},
+    pgn,
+    puzzle::Line,
+    rating::Pool,
+    tournament::Format,
+    wager::{Asset, Stake},
 };
 use chesspresso_indexer::{Indexer, InspectIndexer};
 use clap::{Parser, Subcommand};
 use futures::stream::TryStreamExt;
 use std::path::{Path, PathBuf};
-use std::{env, process::exit};
+use std::{
+    env, fs,
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use url::Url;
 
 sol! {
@@ -119,7 +129,11 @@ enum Command {
     Address,
 
     /// List games.
-    Games,
+    Games {
+        /// List finished games instead of games in progress.
+        #[clap(short, long)]
+        finished: bool,
+    },
 
     /// Show the position for a game.
     Game { id: GameId },
@@ -128,16 +142,264 @@ enum Command {
     Challenge {
         opponent: Address,
         first_move: Option<San>,
+
+        /// Time control, e.g. 5+3 (minutes + increment seconds) or 3d (days per move).
+        #[clap(short, long)]
+        time_control: Option<TimeControl>,
+
+        /// Variant to play, e.g. chess960, atomic or king-of-the-hill.
+        #[clap(short, long)]
+        variant: Option<Variant>,
+
+        /// Chess960 starting position (0-959). Chosen by the rollup if not given.
+        #[clap(long, conflicts_with = "fen")]
+        seed: Option<u16>,
+
+        /// Start the game from a custom position, given in FEN.
+        #[clap(long)]
+        fen: Option<String>,
+
+        /// Amount to wager, in base units (e.g. wei). Your opponent must match it.
+        #[clap(short, long)]
+        stake: Option<U256>,
+
+        /// Asset to wager: ether, or the address of an ERC-20 token.
+        #[clap(short, long, default_value = "ether", requires = "stake")]
+        asset: Asset,
     },
 
+    /// List pending challenges to and from you.
+    Challenges,
+
+    /// Accept a challenge, starting a new game.
+    AcceptChallenge { id: ChallengeId },
+
+    /// Decline a challenge.
+    DeclineChallenge { id: ChallengeId },
+
+    /// Withdraw a challenge you issued.
+    CancelChallenge { id: ChallengeId },
+
+    /// Look for a game against any compatible opponent.
+    Seek {
+        /// Lowest opponent rating to accept.
+        #[clap(long)]
+        min_rating: Option<f64>,
+
+        /// Highest opponent rating to accept.
+        #[clap(long)]
+        max_rating: Option<f64>,
+
+        /// Color to play (white or black).
+        #[clap(short, long)]
+        color: Option<Color>,
+
+        /// Time control, e.g. 5+3 (minutes + increment seconds) or 3d (days per move).
+        #[clap(short, long)]
+        time_control: Option<TimeControl>,
+    },
+
+    /// List open seeks.
+    Seeks,
+
+    /// Withdraw your open seek.
+    CancelSeek { id: SeekId },
+
     /// Make a move.
     Play { id: GameId, san: San },
 
     /// Resign a game.
     Resign { id: GameId },
 
+    /// Abort a game before both players have moved, with no effect on ratings.
+    Abort { id: GameId },
+
+    /// Claim a draw by threefold repetition or the fifty-move rule.
+    ClaimDraw { id: GameId },
+
+    /// Offer your opponent a draw.
+    OfferDraw { id: GameId },
+
+    /// Accept your opponent's draw offer.
+    AcceptDraw { id: GameId },
+
+    /// Decline your opponent's draw offer.
+    DeclineDraw { id: GameId },
+
+    /// List pending draw offers in your games.
+    DrawOffers,
+
+    /// Ask your opponent to let you take back your last move.
+    Takeback { id: GameId },
+
+    /// Accept your opponent's takeback request.
+    AcceptTakeback { id: GameId },
+
+    /// List pending takeback requests in your games.
+    TakebackRequests,
+
+    /// Claim a win because your opponent has run out of time.
+    ClaimTimeout { id: GameId },
+
+    /// Export a game as PGN.
+    Pgn {
+        #[clap(required_unless_present = "all")]
+        id: Option<GameId>,
+
+        /// Export all of your games, including finished games.
+        #[clap(long, conflicts_with = "id")]
+        all: bool,
+    },
+
+    /// Replay games from a PGN file and check them against the indexer.
+    ///
+    /// Each game must include the GameId, White and Black tags. The game hash obtained by replaying
+    /// the moves in the file is compared with the hash of the game reported by the indexer.
+    VerifyPgn { file: PathBuf },
+
     /// Get user stats.
     Stats { user: Option<Address> },
+
+    /// Show how a user's rating has changed with each finished game.
+    History { user: Option<Address> },
+
+    /// Show the highest rated players.
+    Leaderboard {
+        /// Rating pool, e.g. standard-blitz or chess960-correspondence.
+        #[clap(short, long, default_value = "standard-correspondence")]
+        pool: Pool,
+
+        /// Number of players to show.
+        #[clap(short = 'n', long, default_value = "20")]
+        limit: u32,
+
+        /// Only include players who have finished at least this many games.
+        #[clap(short, long, default_value = "5")]
+        min_games: u32,
+
+        /// Only include players whose rating deviation is at most this much.
+        #[clap(short = 'd', long, default_value = "110")]
+        max_deviation: f64,
+    },
+
+    /// Post a puzzle for other players to solve.
+    ///
+    /// Prints the salt of the commitment to the solution, which is needed to reveal it later.
+    PostPuzzle {
+        /// Starting position, in FEN.
+        fen: String,
+
+        /// Solution line, with moves for both sides in SAN.
+        ///
+        /// The solution is not revealed: only a salted commitment to it is posted.
+        #[clap(required = true)]
+        solution: Vec<San>,
+
+        /// Variant the puzzle is played under.
+        #[clap(short, long)]
+        variant: Option<Variant>,
+    },
+
+    /// Reveal the solution of a puzzle you posted.
+    ///
+    /// Solvers can no longer commit to solutions afterwards, and have a limited time to reveal
+    /// theirs. If you never reveal the solution, no collectibles are awarded for the puzzle.
+    RevealPuzzle {
+        id: PuzzleId,
+
+        /// The salt printed when the puzzle was posted.
+        #[clap(short, long)]
+        salt: B256,
+
+        /// Solution line, exactly as posted.
+        #[clap(required = true)]
+        solution: Vec<San>,
+    },
+
+    /// List puzzles.
+    Puzzles,
+
+    /// Commit to a solution of a puzzle, without revealing it.
+    ///
+    /// Solvers are ranked in the order they commit. Prints the salt of the commitment, which is
+    /// needed to reveal the solution with solve-puzzle once the author has revealed theirs.
+    CommitSolution {
+        id: PuzzleId,
+
+        /// Solution line, with moves for both sides in SAN.
+        #[clap(required = true)]
+        solution: Vec<San>,
+    },
+
+    /// Reveal a solution to a puzzle committed to with commit-solution.
+    SolvePuzzle {
+        id: PuzzleId,
+
+        /// The salt printed when the solution was committed to.
+        #[clap(short, long)]
+        salt: B256,
+
+        /// Solution line, exactly as committed to.
+        #[clap(required = true)]
+        solution: Vec<San>,
+    },
+
+    /// List the players who have solved a puzzle.
+    PuzzleSolvers { id: PuzzleId },
+
+    /// Show the top puzzle solvers.
+    PuzzleRankings,
+
+    /// Show your balances of deposited funds.
+    Balances,
+
+    /// Withdraw deposited funds.
+    Withdraw {
+        /// Amount to withdraw, in base units (e.g. wei).
+        amount: U256,
+
+        /// Asset to withdraw: ether, or the address of an ERC-20 token.
+        #[clap(short, long, default_value = "ether")]
+        asset: Asset,
+    },
+
+    /// Organize a tournament.
+    CreateTournament {
+        name: String,
+
+        /// Pairing system: swiss or round-robin.
+        #[clap(short, long, default_value = "swiss")]
+        format: Format,
+
+        /// Number of rounds, for Swiss tournaments. By default, enough to determine a winner.
+        #[clap(short, long)]
+        rounds: Option<u32>,
+
+        /// Time control, e.g. 5+3 (minutes + increment seconds) or 3d (days per move).
+        #[clap(short, long)]
+        time_control: Option<TimeControl>,
+    },
+
+    /// Register for a tournament.
+    JoinTournament { id: TournamentId },
+
+    /// Close registration for a tournament you organized, and start the first round.
+    StartTournament { id: TournamentId },
+
+    /// List tournaments.
+    Tournaments,
+
+    /// Show the pairings and results of a tournament.
+    Pairings {
+        id: TournamentId,
+
+        /// Only show pairings for this round.
+        #[clap(short, long)]
+        round: Option<u32>,
+    },
+
+    /// Show the standings of a tournament.
+    Standings { id: TournamentId },
 }
 
 impl Command {
@@ -151,8 +413,42 @@ impl Command {
     ) -> anyhow::Result<()> {
         match self {
             Self::Address => println!("{address}"),
-            Self::Games => {
-                let games: Vec<_> = db.games(address, None).try_collect().await?;
+            Self::Games { finished: true } => {
+                for game in indexer.games(address, GameFilter::Finished).await? {
+                    let color = if game.white == address {
+                        Color::White
+                    } else {
+                        Color::Black
+                    };
+                    let opponent = if color == Color::White {
+                        game.black
+                    } else {
+                        game.white
+                    };
+                    let result = match &game.result {
+                        Some(result) => format!(
+                            "{} ({})",
+                            result
+                                .score
+                                .map(|score| score.to_string())
+                                .unwrap_or_else(|| "*".into()),
+                            result.termination.as_deref().unwrap_or("abandoned")
+                        ),
+                        None => "*".into(),
+                    };
+                    println!(
+                        "{}. as {color} vs. {opponent}{}: {result}{}",
+                        game.id,
+                        fmt_variant(&game.setup),
+                        fmt_diverged(db.diverged(game.id).await?)
+                    );
+                }
+            }
+            Self::Games { finished: false } => {
+                let games: Vec<_> = db
+                    .games(address, None, GameFilter::Active)
+                    .try_collect()
+                    .await?;
                 for game in games {
                     let id = game.id;
                     let game = db
@@ -169,7 +465,11 @@ impl Command {
                     } else {
                         "their"
                     };
-                    println!("{id}. as {color} vs. {opponent} (move {move_}, {whose} move)",);
+                    println!(
+                        "{id}. as {color} vs. {opponent}{} (move {move_}, {whose} move){}",
+                        fmt_variant(game.setup()),
+                        fmt_diverged(db.diverged(id).await?)
+                    );
                 }
             }
             Self::Game { id } => {
@@ -183,10 +483,30 @@ impl Command {
                     game.ansi_board(color),
                     game.turn()
                 );
+                if let Some(draw) = game.claimable_draw() {
+                    println!("{draw}: either player may claim a draw");
+                }
+                match indexer.clock(*id).await {
+                    Ok(Some(clock)) => {
+                        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                        for color in [Color::White, Color::Black] {
+                            let remaining = clock.remaining(color, game.turn(), now);
+                            println!("{color}: {}", fmt_duration(remaining));
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => eprintln!("unable to load clock: {err:#}"),
+                }
             }
             Self::Challenge {
                 opponent,
                 first_move,
+                time_control,
+                variant,
+                seed,
+                fen,
+                stake,
+                asset,
             } => {
                 advance(
                     opt,
@@ -194,10 +514,111 @@ impl Command {
                     Advance::Challenge {
                         opponent: *opponent,
                         first_move: first_move.as_ref().map(|san| san.to_string()),
+                        time_control: *time_control,
+                        variant: *variant,
+                        seed: *seed,
+                        fen: fen.clone(),
+                        stake: stake.map(|amount| Stake {
+                            asset: *asset,
+                            amount,
+                        }),
+                    },
+                )
+                .await?;
+            }
+            Self::Challenges => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                println!("Incoming:");
+                for challenge in indexer.incoming_challenges(address).await? {
+                    if challenge.expires_at < now {
+                        continue;
+                    }
+                    let color = !challenger_color(&challenge);
+                    let first_move = match &challenge.first_move {
+                        Some(san) => format!(", opening {san}"),
+                        None => String::new(),
+                    };
+                    println!(
+                        "{}. from {} as {color}{first_move}{}{}{}",
+                        challenge.id,
+                        challenge.challenger,
+                        fmt_variant(&challenge.setup),
+                        fmt_time_control(challenge.time_control),
+                        fmt_stake(challenge.stake)
+                    );
+                }
+                println!("Outgoing:");
+                for challenge in indexer.outgoing_challenges(address).await? {
+                    if challenge.expires_at < now {
+                        continue;
+                    }
+                    let color = challenger_color(&challenge);
+                    println!(
+                        "{}. to {} as {color}{}{}{}",
+                        challenge.id,
+                        challenge.opponent,
+                        fmt_variant(&challenge.setup),
+                        fmt_time_control(challenge.time_control),
+                        fmt_stake(challenge.stake)
+                    );
+                }
+            }
+            Self::AcceptChallenge { id } => {
+                advance(opt, provider, Advance::AcceptChallenge { id: *id }).await?;
+            }
+            Self::DeclineChallenge { id } => {
+                advance(opt, provider, Advance::DeclineChallenge { id: *id }).await?;
+            }
+            Self::CancelChallenge { id } => {
+                advance(opt, provider, Advance::CancelChallenge { id: *id }).await?;
+            }
+            Self::Seek {
+                min_rating,
+                max_rating,
+                color,
+                time_control,
+            } => {
+                advance(
+                    opt,
+                    provider,
+                    Advance::Seek {
+                        min_rating: *min_rating,
+                        max_rating: *max_rating,
+                        color: *color,
+                        time_control: *time_control,
                     },
                 )
                 .await?;
             }
+            Self::Seeks => {
+                for seek in indexer.seeks().await? {
+                    let player = if seek.player == address {
+                        "you".to_string()
+                    } else {
+                        seek.player.to_string()
+                    };
+                    let color = match seek.color {
+                        Some(color) => format!(" as {color}"),
+                        None => String::new(),
+                    };
+                    let range = match (seek.min_rating, seek.max_rating) {
+                        (None, None) => String::new(),
+                        (min, max) => format!(
+                            ", rated {}-{}",
+                            min.map(|r| format!("{r:.0}")).unwrap_or_default(),
+                            max.map(|r| format!("{r:.0}")).unwrap_or_default()
+                        ),
+                    };
+                    println!(
+                        "{}. {player}{color}{range}{}",
+                        seek.id,
+                        fmt_time_control(seek.time_control)
+                    );
+                }
+            }
+            Self::CancelSeek { id } => {
+                advance(opt, provider, Advance::CancelSeek { id: *id }).await?;
+            }
             Self::Play { id, san } => {
                 let game = db.game(*id).await?;
                 ensure!(address == game.player(game.turn()), "it is not your turn");
@@ -225,9 +646,431 @@ impl Command {
                 )
                 .await?;
             }
+            Self::Abort { id } => {
+                let game = db.game(*id).await?;
+                ensure!(
+                    game.half_move() < 2,
+                    "game cannot be aborted once both players have moved"
+                );
+                advance(
+                    opt,
+                    provider,
+                    Advance::Abort {
+                        id: *id,
+                        hash: game.hash(),
+                    },
+                )
+                .await?;
+            }
+            Self::ClaimDraw { id } => {
+                let game = db.game(*id).await?;
+                let draw = game
+                    .claimable_draw()
+                    .context("no draw can be claimed in this position")?;
+                println!("claiming draw: {draw}");
+                advance(
+                    opt,
+                    provider,
+                    Advance::ClaimDraw {
+                        id: *id,
+                        hash: game.hash(),
+                    },
+                )
+                .await?;
+            }
+            Self::OfferDraw { id } => {
+                let game = db.game(*id).await?;
+                advance(
+                    opt,
+                    provider,
+                    Advance::OfferDraw {
+                        id: *id,
+                        hash: game.hash(),
+                    },
+                )
+                .await?;
+            }
+            Self::AcceptDraw { id } => {
+                let game = db.game(*id).await?;
+                advance(
+                    opt,
+                    provider,
+                    Advance::AcceptDraw {
+                        id: *id,
+                        hash: game.hash(),
+                    },
+                )
+                .await?;
+            }
+            Self::DeclineDraw { id } => {
+                let game = db.game(*id).await?;
+                advance(
+                    opt,
+                    provider,
+                    Advance::DeclineDraw {
+                        id: *id,
+                        hash: game.hash(),
+                    },
+                )
+                .await?;
+            }
+            Self::DrawOffers => {
+                for offer in indexer.draw_offers(address).await? {
+                    let from = if offer.from == address {
+                        "you".to_string()
+                    } else {
+                        offer.from.to_string()
+                    };
+                    println!("{}. draw offered by {from}", offer.id);
+                }
+            }
+            Self::Takeback { id } => {
+                let game = db.game(*id).await?;
+                advance(
+                    opt,
+                    provider,
+                    Advance::RequestTakeback {
+                        id: *id,
+                        hash: game.hash(),
+                    },
+                )
+                .await?;
+            }
+            Self::AcceptTakeback { id } => {
+                let game = db.game(*id).await?;
+                advance(
+                    opt,
+                    provider,
+                    Advance::AcceptTakeback {
+                        id: *id,
+                        hash: game.hash(),
+                    },
+                )
+                .await?;
+            }
+            Self::TakebackRequests => {
+                for request in indexer.takeback_requests(address).await? {
+                    let from = if request.from == address {
+                        "you".to_string()
+                    } else {
+                        request.from.to_string()
+                    };
+                    println!("{}. takeback requested by {from}", request.id);
+                }
+            }
+            Self::ClaimTimeout { id } => {
+                let game = db.game(*id).await?;
+                advance(
+                    opt,
+                    provider,
+                    Advance::ClaimTimeout {
+                        id: *id,
+                        hash: game.hash(),
+                    },
+                )
+                .await?;
+            }
+            Self::Pgn { id: Some(id), .. } => {
+                print!("{}", indexer.pgn(*id).await?);
+            }
+            Self::Pgn { id: None, .. } => {
+                let games = indexer.games(address, GameFilter::All).await?;
+                let mut pgns = vec![];
+                for game in games {
+                    pgns.push(indexer.pgn(game.id).await?);
+                }
+                print!("{}", pgns.join("\n"));
+            }
+            Self::VerifyPgn { file } => {
+                let pgn =
+                    fs::read_to_string(file).context(format!("reading {}", file.display()))?;
+                let mut mismatches = 0;
+                for pgn in pgn::parse(&pgn)? {
+                    let game = Game::from_pgn(&pgn)?;
+                    let id = game.id();
+                    let state = indexer.state(id).await?;
+                    if state.hash == game.hash() {
+                        println!(
+                            "{id}. OK ({} half moves): {}",
+                            game.half_move(),
+                            game.hash()
+                        );
+                    } else {
+                        mismatches += 1;
+                        println!(
+                            "{id}. MISMATCH: PGN has hash {} after {} half moves, indexer reports {} after {}",
+                            game.hash(),
+                            game.half_move(),
+                            state.hash,
+                            state.half_move,
+                        );
+                    }
+                }
+                ensure!(mismatches == 0, "{mismatches} games did not match");
+            }
             Self::Stats { user } => {
                 let stats = indexer.user_stats(user.unwrap_or(address)).await?;
-                println!("{stats:#?}");
+                // Glicko ratings are 95% likely to be within two deviations of the true strength.
+                for pool in &stats.ratings {
+                    println!(
+                        "{}: {:.0}{} (95% confidence: {:.0} to {:.0}), {} games",
+                        pool.pool,
+                        pool.rating.value,
+                        if pool.provisional { "?" } else { "" },
+                        pool.rating.value - 2. * pool.rating.deviation,
+                        pool.rating.value + 2. * pool.rating.deviation,
+                        pool.games
+                    );
+                }
+                println!("Games: {}", stats.games);
+                println!(
+                    "As white: {} wins, {} losses, {} draws",
+                    stats.white_wins, stats.white_losses, stats.white_draws
+                );
+                println!(
+                    "As black: {} wins, {} losses, {} draws",
+                    stats.black_wins, stats.black_losses, stats.black_draws
+                );
+            }
+            Self::History { user } => {
+                for change in indexer.rating_history(user.unwrap_or(address)).await? {
+                    println!(
+                        "{}. {} vs. {}: {:.0} -> {:.0} ({:+.0}), deviation {:.0}",
+                        change.game,
+                        change.pool,
+                        change.opponent,
+                        change.before.value,
+                        change.after.value,
+                        change.after.value - change.before.value,
+                        change.after.deviation
+                    );
+                }
+            }
+            Self::Leaderboard {
+                pool,
+                limit,
+                min_games,
+                max_deviation,
+            } => {
+                let leaderboard = indexer
+                    .leaderboard(*pool, *limit, *min_games, *max_deviation)
+                    .await?;
+                for (i, entry) in leaderboard.into_iter().enumerate() {
+                    println!(
+                        "{}. {}: {:.0} ± {:.0} ({} games)",
+                        i + 1,
+                        entry.player,
+                        entry.rating.value,
+                        entry.rating.deviation,
+                        entry.games
+                    );
+                }
+            }
+            Self::PostPuzzle {
+                fen,
+                solution,
+                variant,
+            } => {
+                // Check the solution locally, since the dApp only sees it once it is revealed.
+                let setup = GameSetup::new(variant.unwrap_or_default(), Some(fen))?;
+                let salt = B256::random();
+                let solution = Line::play(&setup, &sans(solution))?.commit(address, salt)?;
+                advance(
+                    opt,
+                    provider,
+                    Advance::PostPuzzle {
+                        variant: *variant,
+                        fen: fen.clone(),
+                        solution,
+                    },
+                )
+                .await?;
+                println!("salt: {salt}");
+                println!("Keep the salt and the solution to reveal them with reveal-puzzle.");
+            }
+            Self::RevealPuzzle { id, salt, solution } => {
+                advance(
+                    opt,
+                    provider,
+                    Advance::RevealPuzzle {
+                        id: *id,
+                        salt: *salt,
+                        solution: sans(solution),
+                    },
+                )
+                .await?;
+            }
+            Self::Puzzles => {
+                for puzzle in indexer.puzzles().await? {
+                    let author = if puzzle.author == address {
+                        "you".to_string()
+                    } else {
+                        puzzle.author.to_string()
+                    };
+                    let goal = if puzzle.checkmate {
+                        format!("mate in {}", puzzle.length.div_ceil(2))
+                    } else {
+                        format!("{} moves", puzzle.length)
+                    };
+                    let status = match puzzle.revealed_at {
+                        Some(_) => format!("revealed, solved by {}", puzzle.solvers),
+                        None => "unrevealed".to_string(),
+                    };
+                    println!(
+                        "{}. by {author}{}, {goal}, {status}: {}",
+                        puzzle.id,
+                        fmt_variant(&GameSetup {
+                            fen: None,
+                            ..puzzle.setup.clone()
+                        }),
+                        puzzle.setup.fen.unwrap_or_default()
+                    );
+                }
+            }
+            Self::CommitSolution { id, solution } => {
+                let puzzle = indexer
+                    .puzzles()
+                    .await?
+                    .into_iter()
+                    .find(|puzzle| puzzle.id == *id)
+                    .context(format!("puzzle {id} not found"))?;
+                let salt = B256::random();
+                let commitment =
+                    Line::play(&puzzle.setup, &sans(solution))?.commitment(address, salt);
+                advance(
+                    opt,
+                    provider,
+                    Advance::CommitSolution {
+                        id: *id,
+                        commitment,
+                    },
+                )
+                .await?;
+                println!("salt: {salt}");
+                println!(
+                    "Keep the salt and the solution to reveal them with solve-puzzle once the author reveals theirs."
+                );
+            }
+            Self::SolvePuzzle { id, salt, solution } => {
+                advance(
+                    opt,
+                    provider,
+                    Advance::SolvePuzzle {
+                        id: *id,
+                        salt: *salt,
+                        solution: sans(solution),
+                    },
+                )
+                .await?;
+            }
+            Self::PuzzleSolvers { id } => {
+                for solve in indexer.puzzle_solvers(*id).await? {
+                    println!(
+                        "{}. {} (collectible worth {})",
+                        solve.rank, solve.solver, solve.value
+                    );
+                }
+            }
+            Self::PuzzleRankings => {
+                for (i, ranking) in indexer.puzzle_rankings().await?.into_iter().enumerate() {
+                    println!(
+                        "{}. {}: {} solved, collectibles worth {}",
+                        i + 1,
+                        ranking.solver,
+                        ranking.solved,
+                        ranking.value
+                    );
+                }
+            }
+            Self::Balances => {
+                for balance in indexer.balances(address).await? {
+                    println!("{balance}");
+                }
+            }
+            Self::Withdraw { amount, asset } => {
+                advance(
+                    opt,
+                    provider,
+                    Advance::Withdraw {
+                        stake: Stake {
+                            asset: *asset,
+                            amount: *amount,
+                        },
+                    },
+                )
+                .await?;
+            }
+            Self::CreateTournament {
+                name,
+                format,
+                rounds,
+                time_control,
+            } => {
+                advance(
+                    opt,
+                    provider,
+                    Advance::CreateTournament {
+                        name: name.clone(),
+                        format: *format,
+                        rounds: *rounds,
+                        time_control: *time_control,
+                    },
+                )
+                .await?;
+            }
+            Self::JoinTournament { id } => {
+                advance(opt, provider, Advance::JoinTournament { id: *id }).await?;
+            }
+            Self::StartTournament { id } => {
+                advance(opt, provider, Advance::StartTournament { id: *id }).await?;
+            }
+            Self::Tournaments => {
+                for tournament in indexer.tournaments().await? {
+                    let status = match (tournament.round, tournament.rounds) {
+                        _ if tournament.finished => "finished".to_string(),
+                        (Some(round), Some(rounds)) => format!("round {round} of {rounds}"),
+                        _ => "registration open".to_string(),
+                    };
+                    let time_control = match tournament.time_control {
+                        Some(time_control) => format!(", {time_control}"),
+                        None => String::new(),
+                    };
+                    println!(
+                        "{}. {} ({}{time_control}), {} players, {status}",
+                        tournament.id, tournament.name, tournament.format, tournament.players
+                    );
+                }
+            }
+            Self::Pairings { id, round } => {
+                let mut current = None;
+                for pairing in indexer.pairings(*id, *round).await? {
+                    if current != Some(pairing.round) {
+                        println!("Round {}", pairing.round);
+                        current = Some(pairing.round);
+                    }
+                    let (Some(game), Some(black)) = (pairing.game, pairing.black) else {
+                        println!("  {} (bye)", pairing.white);
+                        continue;
+                    };
+                    let result = pairing
+                        .result
+                        .map(|score| score.to_string())
+                        .unwrap_or_else(|| "*".into());
+                    println!("  {game}. {} vs. {black}: {result}", pairing.white);
+                }
+            }
+            Self::Standings { id } => {
+                for (i, standing) in indexer.standings(*id).await?.into_iter().enumerate() {
+                    println!(
+                        "{}. {} ({:.0}): {} (Buchholz {}, Sonneborn-Berger {})",
+                        i + 1,
+                        standing.player,
+                        standing.rating,
+                        standing.score,
+                        standing.buchholz,
+                        standing.sonneborn_berger
+                    );
+                }
             }
         }
 
@@ -235,6 +1078,64 @@ impl Command {
     }
 }
 
+fn sans(moves: &[San]) -> Vec<String> {
+    moves.iter().map(|san| san.to_string()).collect()
+}
+
+fn fmt_time_control(time_control: Option<TimeControl>) -> String {
+    match time_control {
+        Some(time_control) => format!(" ({time_control})"),
+        None => String::new(),
+    }
+}
+
+fn fmt_stake(stake: Option<Stake>) -> String {
+    match stake {
+        Some(stake) => format!(", wagering {stake}"),
+        None => String::new(),
+    }
+}
+
+/// The color the challenger will play if `challenge` is accepted.
+fn challenger_color(challenge: &Challenge) -> Color {
+    let (turn, _) = challenge.setup.starting_move().unwrap_or((Color::White, 1));
+    if challenge.first_move.is_some() {
+        turn
+    } else {
+        !turn
+    }
+}
+
+fn fmt_variant(setup: &GameSetup) -> String {
+    match (setup.variant, &setup.fen) {
+        (Variant::Standard, None) => String::new(),
+        (Variant::Standard, Some(fen)) => format!(" (from {fen})"),
+        (Variant::Chess960, _) | (_, None) => format!(" ({})", setup.variant),
+        (variant, Some(fen)) => format!(" ({variant} from {fen})"),
+    }
+}
+
+fn fmt_diverged(diverged: bool) -> &'static str {
+    if diverged {
+        " [diverged]"
+    } else {
+        ""
+    }
+}
+
+fn fmt_duration(seconds: u64) -> String {
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    let (hours, seconds) = (seconds / 3600, seconds % 3600);
+    let (minutes, seconds) = (seconds / 60, seconds % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m")
+    } else if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
 async fn advance(
     opt: &Options,
     provider: &impl Provider<Http<Client>>,
@@ -1,9 +1,15 @@
 use alloy::primitives::Address;
+use anyhow::{ensure, Context};
 use chesspresso_core::{
     db::Db,
-    game::{Game, GameId},
+    game::{Game, GameHash, GameId, Move, San},
+    message::GameFilter,
+    pgn::{self, PgnGame},
+    rating::{self, RatingSystem},
+};
+use chesspresso_indexer::{
+    EspressoIndexer, GraphQlIndexer, Indexer, InspectIndexer, MoveUpdate, SubscriptionIndexer,
 };
-use chesspresso_indexer::{Indexer, InspectIndexer};
 use clap::Parser;
 use futures::{future, stream::StreamExt};
 use std::{
@@ -28,6 +34,80 @@ struct Options {
 
     #[clap(short = 'u', long, env = "CHESSPRESSO_NODE_URL")]
     node_url: Url,
+
+    /// Stream new games and moves from a standalone indexer service at this URL, instead of
+    /// polling the node.
+    ///
+    /// If the service is unreachable, streams fall back to polling the node.
+    #[clap(short, long, env = "CHESSPRESSO_INDEXER_URL")]
+    indexer_url: Option<Url>,
+
+    /// How often to poll the node for new games and moves while there is activity, in milliseconds.
+    #[clap(long, env = "CHESSPRESSO_POLLING_INTERVAL", default_value = "500")]
+    polling_interval: u64,
+
+    /// How often to poll the node once it has been idle for a while, in milliseconds.
+    ///
+    /// While nothing is happening, the polling interval doubles after each poll, up to this
+    /// maximum.
+    #[clap(
+        long,
+        env = "CHESSPRESSO_MAX_POLLING_INTERVAL",
+        default_value = "10000"
+    )]
+    max_polling_interval: u64,
+
+    /// Node to refetch games from, by inspecting the dApp state, if the indexer reports moves which
+    /// are inconsistent with the game hash.
+    ///
+    /// Defaults to --node-url.
+    #[clap(long, env = "CHESSPRESSO_FALLBACK_URL")]
+    fallback_url: Option<Url>,
+
+    /// Follow moves in real time through preconfirmations from the Espresso query service at this
+    /// URL.
+    ///
+    /// Preconfirmed moves are replayed optimistically and checked against the finalized state of
+    /// each game as it catches up.
+    #[clap(
+        long,
+        env = "CHESSPRESSO_ESPRESSO_URL",
+        requires_all = ["espresso_namespace", "dapp_address", "chain_id"]
+    )]
+    espresso_url: Option<Url>,
+
+    /// The Espresso namespace in which inputs to the dApp are sequenced.
+    #[clap(long, env = "CHESSPRESSO_ESPRESSO_NAMESPACE")]
+    espresso_namespace: Option<u64>,
+
+    /// The address of the dApp, for recognizing its inputs on Espresso.
+    #[clap(long, env = "CHESSPRESSO_DAPP_ADDRESS")]
+    dapp_address: Option<Address>,
+
+    /// The ID of the chain the rollup settles on, for checking signatures of inputs on Espresso.
+    #[clap(long, env = "CHESSPRESSO_CHAIN_ID")]
+    chain_id: Option<u64>,
+
+    /// Follow games through the node's GraphQL API, using only finalized inputs and notices,
+    /// instead of inspecting the dApp state.
+    #[clap(long, env = "CHESSPRESSO_GRAPHQL")]
+    graphql: bool,
+
+    /// The length of a rating period configured in the dApp, in seconds.
+    ///
+    /// Only used with --graphql, to recompute ratings.
+    #[clap(long, env = "CHESSPRESSO_RATING_PERIOD", default_value_t = rating::DEFAULT_PERIOD)]
+    rating_period: u64,
+
+    /// The Glicko-2 system constant configured in the dApp.
+    ///
+    /// Only used with --graphql, to recompute ratings.
+    #[clap(
+        long,
+        env = "CHESSPRESSO_RATING_SYSTEM_CONSTANT",
+        default_value_t = rating::DEFAULT_SYSTEM_CONSTANT
+    )]
+    rating_system_constant: f64,
 }
 
 #[tokio::main]
@@ -38,71 +118,282 @@ async fn main() -> anyhow::Result<()> {
         .init();
     let opt = Options::parse();
 
-    let db_path = match opt.db {
-        Some(path) => path,
+    let db_path = match &opt.db {
+        Some(path) => path.clone(),
         None => Path::new(&env::var("HOME")?).join(format!(".chesspresso/{}.sqlite", opt.address)),
     };
     let db = Arc::new(Mutex::new(Db::open(&db_path).await?));
 
-    let indexer = InspectIndexer::new(opt.node_url);
+    let polling_interval = Duration::from_millis(opt.polling_interval);
+    let max_polling_interval = Duration::from_millis(opt.max_polling_interval);
+    if opt.graphql {
+        let rating_system = RatingSystem {
+            period: opt.rating_period,
+            system_constant: opt.rating_system_constant,
+        };
+        let indexer = GraphQlIndexer::new(opt.node_url.clone(), rating_system)
+            .await?
+            .with_polling_interval(polling_interval, max_polling_interval);
+        subscribe(indexer, &opt, db).await
+    } else {
+        let indexer = InspectIndexer::new(opt.node_url.clone())
+            .with_polling_interval(polling_interval, max_polling_interval);
+        subscribe(indexer, &opt, db).await
+    }
+}
+
+/// Subscribe to the indexer service if there is one, or else poll `indexer`.
+async fn subscribe(
+    indexer: impl Indexer + Clone + Send + Sync + 'static,
+    opt: &Options,
+    db: Arc<Mutex<Db>>,
+) -> anyhow::Result<()> {
+    match &opt.indexer_url {
+        Some(url) => preconfirm(SubscriptionIndexer::new(url.clone(), indexer), opt, db).await,
+        None => preconfirm(indexer, opt, db).await,
+    }
+}
+
+/// Follow preconfirmations from Espresso if configured, checking them against the finalized state
+/// from `indexer`.
+async fn preconfirm(
+    indexer: impl Indexer + Clone + Send + Sync + 'static,
+    opt: &Options,
+    db: Arc<Mutex<Db>>,
+) -> anyhow::Result<()> {
+    let Some(url) = &opt.espresso_url else {
+        return run(indexer, fallback(opt), db, opt.address).await;
+    };
+    let namespace = opt
+        .espresso_namespace
+        .context("--espresso-namespace is required with --espresso-url")?;
+    let app = opt
+        .dapp_address
+        .context("--dapp-address is required with --espresso-url")?;
+    let chain_id = opt
+        .chain_id
+        .context("--chain-id is required with --espresso-url")?;
+    let indexer = EspressoIndexer::new(url.clone(), namespace, app, chain_id, indexer)
+        .with_polling_interval(
+            Duration::from_millis(opt.polling_interval),
+            Duration::from_millis(opt.max_polling_interval),
+        );
+    run(indexer, fallback(opt), db, opt.address).await
+}
+
+/// The indexer to refetch games from if the main indexer diverges.
+fn fallback(opt: &Options) -> InspectIndexer {
+    let url = opt.fallback_url.as_ref().unwrap_or(&opt.node_url);
+    InspectIndexer::new(url.clone()).with_polling_interval(
+        Duration::from_millis(opt.polling_interval),
+        Duration::from_millis(opt.max_polling_interval),
+    )
+}
 
+/// Run the daemon, following games through `indexer`, and refetching them from `fallback` if
+/// `indexer` reports moves inconsistent with the game hash.
+async fn run(
+    indexer: impl Indexer + Clone + Send + Sync + 'static,
+    fallback: InspectIndexer,
+    db: Arc<Mutex<Db>>,
+    address: Address,
+) -> anyhow::Result<()> {
     // Listen for new moves in the games we already have.
     {
         let mut conn = db.lock().await;
-        let mut games = conn.games(opt.address, None);
+        let mut games = conn.games(address, None, GameFilter::Active);
         while let Some(game) = games.next().await {
-            spawn(listen_moves(indexer.clone(), db.clone(), game?.id));
+            spawn(listen_moves(
+                indexer.clone(),
+                fallback.clone(),
+                db.clone(),
+                address,
+                game?.id,
+            ));
         }
     }
 
     // Listen for new games.
-    spawn(listen_games(indexer.clone(), db.clone(), opt.address));
+    spawn(listen_games(indexer.clone(), fallback, db.clone(), address));
 
     // Block until killed.
     future::pending().await
 }
 
-#[instrument(skip(indexer, db))]
-async fn listen_moves(indexer: impl Indexer, db: Arc<Mutex<Db>>, id: GameId) {
-    let mut game = loop {
-        match db.lock().await.game(id).await {
-            Ok(game) => break game,
-            Err(err) => {
-                tracing::warn!("error loading game: {err:#}");
-                sleep(Duration::from_secs(5)).await;
-            }
+#[instrument(skip(indexer, fallback, db))]
+async fn listen_moves(
+    indexer: impl Indexer,
+    fallback: InspectIndexer,
+    db: Arc<Mutex<Db>>,
+    address: Address,
+    id: GameId,
+) {
+    let (mut game, mut diverged) = loop {
+        let mut db = db.lock().await;
+        match db.game(id).await {
+            Ok(game) => match db.diverged(id).await {
+                Ok(diverged) => break (game, diverged),
+                Err(err) => tracing::warn!("error loading game: {err:#}"),
+            },
+            Err(err) => tracing::warn!("error loading game: {err:#}"),
         }
+        drop(db);
+        sleep(Duration::from_secs(5)).await;
     };
 
-    let mut moves = indexer.moves(id, game.half_move() + 1);
-    while let Some(san) = moves.next().await {
-        tracing::info!(%san, "new move");
+    // Once a game has diverged, we no longer trust the indexer with it.
+    let mut moves = if diverged {
+        fallback.moves(id, game.half_move() + 1).boxed()
+    } else {
+        indexer.moves(id, game.half_move() + 1).boxed()
+    };
+    while let Some(update) = moves.next().await {
+        match update {
+            MoveUpdate::Move(san, hash) => {
+                tracing::info!(%san, ?hash, "new move");
 
-        let m = match game.play_next_move(san.clone()) {
-            Ok(m) => m,
-            Err(err) => {
-                tracing::error!(%san, "game reached invalid state: {err:#}");
-                return;
+                let m = match verify_move(&mut game, san.clone(), hash) {
+                    Ok(m) => m,
+                    Err(err) => {
+                        tracing::error!(%san, "indexer reported invalid move: {err:#}");
+                        if diverged {
+                            tracing::error!("fallback indexer diverged too, giving up on game");
+                            return;
+                        }
+                        diverged = true;
+                        game = refetch(&fallback, &db, id).await;
+                        moves = fallback.moves(id, game.half_move() + 1).boxed();
+                        continue;
+                    }
+                };
+
+                loop {
+                    let mut db = db.lock().await;
+                    let Err(err) = db.record_move(id, m.clone()).await else {
+                        break;
+                    };
+
+                    tracing::warn!(?m, "error saving move: {err:#}");
+                    sleep(Duration::from_secs(5)).await;
+                }
             }
-        };
+            MoveUpdate::TakeBack(half_move) => {
+                if half_move >= game.half_move() {
+                    continue;
+                }
+                tracing::info!(half_move, "moves taken back");
+
+                if let Err(err) = game.take_back(game.half_move() - half_move) {
+                    tracing::error!(half_move, "error taking back moves: {err:#}");
+                    return;
+                }
 
-        loop {
-            let mut db = db.lock().await;
-            let Err(err) = db.record_move(id, m.clone()).await else {
-                break;
-            };
+                loop {
+                    let mut db = db.lock().await;
+                    let Err(err) = db.take_back(id, half_move).await else {
+                        break;
+                    };
 
-            tracing::warn!(?m, "error saving move: {err:#}");
-            sleep(Duration::from_secs(5)).await;
+                    tracing::warn!(half_move, "error saving takeback: {err:#}");
+                    sleep(Duration::from_secs(5)).await;
+                }
+            }
         }
     }
 
     tracing::info!("game over");
+    if diverged {
+        record_result(&fallback, &db, address, id).await;
+    } else {
+        record_result(&indexer, &db, address, id).await;
+    }
+}
+
+/// Record the result of game `id`, which `indexer` reports is over, so it is no longer listed or
+/// followed as an active game.
+async fn record_result(indexer: &impl Indexer, db: &Mutex<Db>, address: Address, id: GameId) {
+    loop {
+        match try_record_result(indexer, db, address, id).await {
+            Ok(()) => return,
+            Err(err) => {
+                tracing::warn!("error recording result: {err:#}");
+                sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+async fn try_record_result(
+    indexer: &impl Indexer,
+    db: &Mutex<Db>,
+    address: Address,
+    id: GameId,
+) -> anyhow::Result<()> {
+    let result = indexer
+        .games(address, GameFilter::Finished)
+        .await?
+        .into_iter()
+        .find(|game| game.id == id)
+        .and_then(|game| game.result)
+        .context("game is not over yet")?;
+    tracing::info!(?result, "game result");
+    db.lock().await.finish_game(id, &result).await
 }
 
-#[instrument(skip(indexer, db))]
+/// Play a move reported by the indexer, checking that it is legal and leads to the game state the
+/// indexer says it does.
+///
+/// A move reported without a hash can't be checked, so it is treated as a divergence too.
+fn verify_move(game: &mut Game, san: San, hash: Option<GameHash>) -> anyhow::Result<Move> {
+    let hash = hash.context("indexer did not report the game hash")?;
+    let m = game.play_next_move(san)?;
+    ensure!(
+        hash == m.hash(),
+        "move leads to state {}, but indexer reported {hash}",
+        m.hash()
+    );
+    Ok(m)
+}
+
+/// Replace our copy of game `id` with the one from `fallback`, marking the game as diverged.
+async fn refetch(fallback: &impl Indexer, db: &Mutex<Db>, id: GameId) -> Game {
+    loop {
+        match try_refetch(fallback, db, id).await {
+            Ok(game) => {
+                tracing::info!(half_move = game.half_move(), "refetched game from fallback");
+                return game;
+            }
+            Err(err) => {
+                tracing::warn!("error refetching game: {err:#}");
+                sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+async fn try_refetch(fallback: &impl Indexer, db: &Mutex<Db>, id: GameId) -> anyhow::Result<Game> {
+    let pgn = fallback.pgn(id).await?;
+    let pgn = pgn::parse(&pgn)?
+        .into_iter()
+        .next()
+        .context("fallback indexer returned no game")?;
+    let moves = pgn.moves.clone();
+    let mut game = Game::from_pgn(&PgnGame {
+        moves: vec![],
+        ..pgn
+    })?;
+    let moves = moves
+        .into_iter()
+        .map(|san| game.play_next_move(san))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    db.lock().await.diverge(id, &moves).await?;
+    Ok(game)
+}
+
+#[instrument(skip(indexer, fallback, db))]
 async fn listen_games(
-    indexer: impl Indexer + Clone + Send + 'static,
+    indexer: impl Indexer + Clone + Send + Sync + 'static,
+    fallback: InspectIndexer,
     db: Arc<Mutex<Db>>,
     address: Address,
 ) {
@@ -119,20 +410,28 @@ async fn listen_games(
     let mut games = indexer.games_with_user(address, after);
     while let Some(game) = games.next().await {
         tracing::info!(?game, "new game");
+        let new_game = match Game::with_setup(game.id, game.white, game.black, game.setup.clone()) {
+            Ok(new_game) => new_game,
+            Err(err) => {
+                tracing::warn!(?game, "invalid game setup: {err:#}");
+                continue;
+            }
+        };
         let id = loop {
-            if let Err(err) = db
-                .lock()
-                .await
-                .insert_game(&Game::new(game.id, game.white, game.black))
-                .await
-            {
+            if let Err(err) = db.lock().await.insert_game(&new_game).await {
                 tracing::warn!(?game, "error saving challenge: {err:#}");
                 sleep(Duration::from_secs(5)).await;
                 continue;
             }
             break game.id;
         };
-        spawn(listen_moves(indexer.clone(), db.clone(), id));
+        spawn(listen_moves(
+            indexer.clone(),
+            fallback.clone(),
+            db.clone(),
+            address,
+            id,
+        ));
     }
 
     tracing::info!("no more challenges");
@@ -0,0 +1,45 @@
+CREATE TABLE tournament (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    -- The address of the player who created the tournament, and who may start it.
+    organizer VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    -- The pairing system: 'round-robin' or 'swiss'.
+    format VARCHAR NOT NULL,
+    -- The number of rounds. This may be NULL until the tournament starts, in which case it is
+    -- chosen based on the number of players.
+    rounds INT,
+    -- The time control for every game in the tournament, in the same format as for `game`.
+    time_base INT,
+    time_increment INT,
+    time_days INT,
+
+    -- The timestamp of the input which created the tournament.
+    created_at INT NOT NULL,
+    -- The round in progress, or NULL if the tournament has not started.
+    round INT,
+    -- The timestamp of the input which ended the last game of the last round.
+    finished_at INT
+);
+
+CREATE TABLE tournament_player (
+    tournament INT NOT NULL REFERENCES tournament (id) ON DELETE CASCADE,
+    player VARCHAR NOT NULL,
+    -- The player's rating when they joined, updated when the tournament starts. Players are seeded
+    -- by this rating.
+    rating REAL NOT NULL,
+
+    PRIMARY KEY (tournament, player)
+);
+
+CREATE TABLE tournament_pairing (
+    tournament INT NOT NULL REFERENCES tournament (id) ON DELETE CASCADE,
+    round INT NOT NULL,
+    white VARCHAR NOT NULL,
+    -- The player with black, or NULL if `white` has a bye.
+    black VARCHAR,
+    -- The game played, or NULL for a bye.
+    game INT REFERENCES game (id)
+);
+
+CREATE INDEX tournament_pairing_game ON tournament_pairing (game);
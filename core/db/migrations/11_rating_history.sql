@@ -0,0 +1,18 @@
+-- The change in a player's rating due to each finished game. Ratings are given on the internal
+-- Glicko-2 scale, like those of the per-pool `rating` table.
+CREATE TABLE rating_history (
+    player VARCHAR NOT NULL,
+    game INT NOT NULL REFERENCES game (id),
+    opponent VARCHAR NOT NULL,
+    -- The timestamp of the input which ended the game.
+    timestamp INT NOT NULL,
+
+    value_before REAL NOT NULL,
+    deviation_before REAL NOT NULL,
+    volatility_before REAL NOT NULL,
+    value_after REAL NOT NULL,
+    deviation_after REAL NOT NULL,
+    volatility_after REAL NOT NULL,
+
+    PRIMARY KEY (player, game)
+);
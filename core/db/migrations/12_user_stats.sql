@@ -0,0 +1,9 @@
+-- Users used to be created with NULL result counters, which stayed NULL when incremented. Recompute
+-- the counters from the results of finished games.
+UPDATE user SET
+    white_wins = (SELECT count(*) FROM game WHERE white = user.address AND result = '1-0'),
+    white_losses = (SELECT count(*) FROM game WHERE white = user.address AND result = '0-1'),
+    white_draws = (SELECT count(*) FROM game WHERE white = user.address AND result = '1/2-1/2'),
+    black_wins = (SELECT count(*) FROM game WHERE black = user.address AND result = '0-1'),
+    black_losses = (SELECT count(*) FROM game WHERE black = user.address AND result = '1-0'),
+    black_draws = (SELECT count(*) FROM game WHERE black = user.address AND result = '1/2-1/2');
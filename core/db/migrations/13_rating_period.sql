@@ -0,0 +1,25 @@
+-- Results of finished games, awaiting the end of their rating period. `score` is the score of
+-- `player`: 1 for a win, 0.5 for a draw or 0 for a loss.
+CREATE TABLE rating_result (
+    player VARCHAR NOT NULL,
+    game INT NOT NULL REFERENCES game (id),
+    opponent VARCHAR NOT NULL,
+    score REAL NOT NULL,
+    -- The rating period in which the game ended.
+    period INT NOT NULL,
+    -- The timestamp of the input which ended the game.
+    timestamp INT NOT NULL,
+
+    PRIMARY KEY (player, game)
+);
+
+-- The rating period as of whose start each user's rating applies. Players who have sat out rating
+-- periods since then have their deviation increased when their rating is read. NULL if the rating
+-- has not been updated since rating periods were introduced, in which case it is taken as current.
+ALTER TABLE user ADD COLUMN rating_period INT;
+
+-- The current rating period, as of the latest input.
+CREATE TABLE rating_clock (
+    period INT NOT NULL
+);
+INSERT INTO rating_clock (period) VALUES (0);
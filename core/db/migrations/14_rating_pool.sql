@@ -0,0 +1,33 @@
+-- The rating pool of each game: its variant and speed, as in `<variant>-<speed>`. Games in different
+-- pools are rated separately. Compute the pools of existing games following `rating::Speed::of`.
+ALTER TABLE game ADD COLUMN pool VARCHAR;
+UPDATE game SET pool = coalesce(variant, 'standard') || '-' || CASE
+    WHEN time_base IS NULL THEN 'correspondence'
+    WHEN time_base + 40 * time_increment < 180 THEN 'bullet'
+    WHEN time_base + 40 * time_increment < 480 THEN 'blitz'
+    WHEN time_base + 40 * time_increment < 1500 THEN 'rapid'
+    ELSE 'classical'
+END;
+
+-- Each player's rating in each pool they have played in, on the internal Glicko-2 scale. Players
+-- without a rating in a pool are unrated. This supersedes the `elo_*` and `rating_period` columns
+-- of `user`.
+CREATE TABLE rating (
+    player VARCHAR NOT NULL,
+    pool VARCHAR NOT NULL,
+
+    value REAL NOT NULL,
+    deviation REAL NOT NULL,
+    volatility REAL NOT NULL,
+    -- The rating period as of whose start the rating applies, as for `user.rating_period`.
+    period INT,
+
+    PRIMARY KEY (player, pool)
+);
+
+-- Carry each player's existing rating over to every pool they have finished a game in.
+INSERT INTO rating (player, pool, value, deviation, volatility, period)
+    SELECT DISTINCT user.address, game.pool, user.elo_value, user.elo_deviation,
+            user.elo_volatility, user.rating_period
+        FROM user JOIN game ON user.address IN (game.white, game.black)
+        WHERE game.result IS NOT NULL AND user.elo_value IS NOT NULL;
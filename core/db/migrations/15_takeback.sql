@@ -0,0 +1,14 @@
+-- The address of the player who has asked to take back their last move in this game, if there is a
+-- pending request. Any move in the game clears the request.
+ALTER TABLE game ADD COLUMN takeback_request VARCHAR;
+
+-- Every takeback, in the order they happened, so that clients following a game can tell when moves
+-- they have already seen were taken back.
+CREATE TABLE takeback (
+    game INT NOT NULL REFERENCES game (id) ON DELETE CASCADE,
+
+    -- The number of half moves remaining after the takeback.
+    half_move INT NOT NULL
+);
+
+CREATE INDEX takeback_game ON takeback (game);
@@ -0,0 +1,6 @@
+-- The cursor of the last input replayed by a GraphQL indexer, so that it can resume where it left
+-- off after a restart. Only used by indexers, and contains at most one row.
+CREATE TABLE graphql_cursor (
+    id INT PRIMARY KEY CHECK (id = 0),
+    cursor VARCHAR NOT NULL
+);
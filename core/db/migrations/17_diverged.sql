@@ -0,0 +1,7 @@
+-- Whether the indexer a client followed this game through reported moves inconsistent with the game
+-- hash, in which case the moves were refetched from a fallback indexer. Only used by clients.
+ALTER TABLE game ADD COLUMN diverged BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The hash of the game state after each move, so that moves can be served along with their hashes
+-- without replaying the game. Moves recorded before this column was added have no hash.
+ALTER TABLE move ADD COLUMN hash VARCHAR;
@@ -0,0 +1,3 @@
+-- The address of the player who has offered a draw in this game, if there is a pending offer. Any
+-- move in the game clears the offer.
+ALTER TABLE game ADD COLUMN draw_offer VARCHAR;
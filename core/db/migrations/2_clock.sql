@@ -0,0 +1,11 @@
+-- The time control, if the game is timed. For real-time games, `time_base` and `time_increment`
+-- are given in seconds. For correspondence games, `time_days` is the number of days per move.
+ALTER TABLE game ADD COLUMN time_base INT;
+ALTER TABLE game ADD COLUMN time_increment INT;
+ALTER TABLE game ADD COLUMN time_days INT;
+
+-- The number of seconds remaining on each player's clock as of `clock_timestamp`, which is the
+-- input timestamp of the last move (or of the start of the game).
+ALTER TABLE game ADD COLUMN white_clock INT;
+ALTER TABLE game ADD COLUMN black_clock INT;
+ALTER TABLE game ADD COLUMN clock_timestamp INT;
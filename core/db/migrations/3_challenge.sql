@@ -0,0 +1,20 @@
+-- Challenges which have been issued but not yet accepted, declined or cancelled.
+CREATE TABLE challenge (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    -- The address of the player issuing the challenge.
+    challenger VARCHAR NOT NULL,
+    -- The address of the player being challenged.
+    opponent VARCHAR NOT NULL,
+
+    -- The challenger's first move in SAN, if the challenger is to play white and move first.
+    first_move VARCHAR,
+
+    -- The time control for the game, in the same format as the corresponding columns of `game`.
+    time_base INT,
+    time_increment INT,
+    time_days INT,
+
+    -- The input timestamp after which the challenge can no longer be accepted.
+    expires_at INT NOT NULL
+);
@@ -0,0 +1,19 @@
+-- Open requests for a game against any compatible opponent.
+CREATE TABLE seek (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    -- The address of the player looking for a game.
+    player VARCHAR NOT NULL UNIQUE,
+
+    -- The range of opponent ratings the player is willing to accept, if restricted.
+    min_rating REAL,
+    max_rating REAL,
+
+    -- The color the player wants to play ('white' or 'black'), if they have a preference.
+    color VARCHAR,
+
+    -- The time control for the game, in the same format as the corresponding columns of `game`.
+    time_base INT,
+    time_increment INT,
+    time_days INT
+);
@@ -0,0 +1,10 @@
+-- Finished games are kept, along with their moves, so that game history remains queryable. A game
+-- is in progress as long as `ended_at` is NULL.
+
+-- The final score in PGN notation ('1-0', '0-1' or '1/2-1/2'), or NULL if the game was abandoned
+-- without a result.
+ALTER TABLE game ADD COLUMN result VARCHAR;
+-- How the game ended, e.g. 'checkmate' or 'resignation'.
+ALTER TABLE game ADD COLUMN termination VARCHAR;
+-- The timestamp of the input which ended the game.
+ALTER TABLE game ADD COLUMN ended_at INT;
@@ -0,0 +1,2 @@
+-- The timestamp of the input which started the game.
+ALTER TABLE game ADD COLUMN created_at INT;
@@ -0,0 +1,8 @@
+-- The variant a game is played under, or NULL for standard chess.
+ALTER TABLE game ADD COLUMN variant VARCHAR;
+-- The starting position of the game in FEN, or NULL for the usual starting position.
+ALTER TABLE game ADD COLUMN start_fen VARCHAR;
+
+-- The variant and starting position of a challenged game, in the same format as for `game`.
+ALTER TABLE challenge ADD COLUMN variant VARCHAR;
+ALTER TABLE challenge ADD COLUMN start_fen VARCHAR;
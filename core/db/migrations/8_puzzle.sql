@@ -0,0 +1,60 @@
+CREATE TABLE puzzle (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    -- The address of the player who posted the puzzle.
+    author VARCHAR NOT NULL,
+
+    -- The variant and starting position of the puzzle, in the same format as for `game`, except
+    -- that the starting position is required.
+    variant VARCHAR,
+    fen VARCHAR NOT NULL,
+
+    -- The author's salted commitment to the solution line, along with its length and whether it
+    -- ends in checkmate. The moves themselves are only stored once the author reveals them, since
+    -- all inputs are public.
+    solution_hash VARCHAR NOT NULL,
+    length INT NOT NULL,
+    checkmate BOOLEAN NOT NULL,
+
+    -- The timestamp of the input which posted the puzzle.
+    created_at INT NOT NULL,
+
+    -- The revealed solution line (in SAN+, separated by spaces), and the timestamp of the input
+    -- which revealed it.
+    solution VARCHAR,
+    revealed_at INT,
+
+    -- Whether collectibles have been awarded to the solvers, at the end of the reveal period.
+    settled BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+-- Solvers' commitments to their solutions. Solvers are ranked by the order of their commitments,
+-- so a solution revealed by someone else cannot be copied for a better rank.
+CREATE TABLE puzzle_commitment (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    puzzle INT NOT NULL REFERENCES puzzle (id) ON DELETE CASCADE,
+    solver VARCHAR NOT NULL,
+
+    -- The hash of the solver's address, a secret salt and their solution.
+    commitment VARCHAR NOT NULL,
+    -- The timestamp of the input which committed to the solution.
+    committed_at INT NOT NULL,
+    -- Whether the solver has revealed a correct solution.
+    solved BOOLEAN NOT NULL DEFAULT FALSE,
+
+    UNIQUE (puzzle, solver)
+);
+
+CREATE TABLE puzzle_solver (
+    puzzle INT NOT NULL REFERENCES puzzle (id) ON DELETE CASCADE,
+    solver VARCHAR NOT NULL,
+
+    -- The order in which the puzzle was solved, starting from 1 for the first solver.
+    rank INT NOT NULL,
+    -- The timestamp of the input which committed to the solution.
+    solved_at INT NOT NULL,
+    -- The value of the collectible awarded for the solve.
+    value INT NOT NULL,
+
+    PRIMARY KEY (puzzle, solver)
+);
@@ -0,0 +1,17 @@
+-- Funds deposited into the dApp and not currently staked in a game or challenge.
+CREATE TABLE balance (
+    address VARCHAR NOT NULL,
+    -- The asset held: "ether", or the address of an ERC-20 token.
+    asset VARCHAR NOT NULL,
+    -- The amount held, in base units, as a decimal string (amounts may not fit in 64 bits).
+    amount VARCHAR NOT NULL,
+
+    PRIMARY KEY (address, asset)
+);
+
+-- The stake wagered by each player, in the same format as the columns of `balance`, or NULL if the
+-- game is not wagered.
+ALTER TABLE challenge ADD COLUMN stake_asset VARCHAR;
+ALTER TABLE challenge ADD COLUMN stake_amount VARCHAR;
+ALTER TABLE game ADD COLUMN stake_asset VARCHAR;
+ALTER TABLE game ADD COLUMN stake_amount VARCHAR;
@@ -0,0 +1,150 @@
+use crate::game::Color;
+use anyhow::{ensure, Context};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// The number of seconds in a day, for correspondence time controls.
+const DAY: u64 = 24 * 60 * 60;
+
+/// The rules governing how much time each player has to make their moves.
+#[derive(Clone, Copy, Debug, Display, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TimeControl {
+    /// Each player starts with `base` seconds and gains `increment` seconds after each move.
+    #[display("{}+{increment}", fmt_minutes(*base))]
+    Clock { base: u64, increment: u64 },
+    /// Each player has `days` days to make each move.
+    #[display("{days}d")]
+    Correspondence { days: u64 },
+}
+
+impl TimeControl {
+    /// The time, in seconds, each player starts with.
+    pub fn initial(&self) -> u64 {
+        match self {
+            Self::Clock { base, .. } => *base,
+            Self::Correspondence { days } => days * DAY,
+        }
+    }
+}
+
+/// Parse a time control.
+///
+/// Real-time controls are given as `<minutes>+<increment seconds>`, e.g. `5+3`, where minutes may
+/// be fractional (`0.5+0`). Correspondence time controls are given as `<days>d`, e.g. `3d`.
+impl FromStr for TimeControl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if let Some(days) = s.strip_suffix('d') {
+            let days = days.parse().context("invalid number of days")?;
+            ensure!(days > 0, "correspondence games must allow at least one day");
+            return Ok(Self::Correspondence { days });
+        }
+
+        let (minutes, increment) = s
+            .split_once('+')
+            .context("time control must be of the form <minutes>+<increment> or <days>d")?;
+        let minutes: f64 = minutes.parse().context("invalid base time")?;
+        ensure!(
+            minutes.is_finite() && minutes > 0.,
+            "base time must be positive"
+        );
+        Ok(Self::Clock {
+            base: (minutes * 60.).round() as u64,
+            increment: increment.parse().context("invalid increment")?,
+        })
+    }
+}
+
+fn fmt_minutes(seconds: u64) -> String {
+    let (minutes, remainder) = (seconds / 60, seconds % 60);
+    if remainder == 0 {
+        minutes.to_string()
+    } else {
+        (seconds as f64 / 60.).to_string()
+    }
+}
+
+/// The state of the clocks in a timed game.
+///
+/// Clocks are driven entirely by the timestamps of rollup inputs. The remaining time for each
+/// player is recorded as of `timestamp`, the time of the most recent move (or the start of the
+/// game). The clock of the player to move has been running since then.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Clock {
+    pub time_control: TimeControl,
+    /// Seconds remaining for white as of `timestamp`.
+    pub white: u64,
+    /// Seconds remaining for black as of `timestamp`.
+    pub black: u64,
+    /// The time at which the clocks were last updated.
+    pub timestamp: u64,
+}
+
+impl Clock {
+    /// Start the clocks for a new game at time `timestamp`.
+    pub fn new(time_control: TimeControl, timestamp: u64) -> Self {
+        Self {
+            time_control,
+            white: time_control.initial(),
+            black: time_control.initial(),
+            timestamp,
+        }
+    }
+
+    /// The time remaining for `color` at time `now`, given that `turn` is to move.
+    pub fn remaining(&self, color: Color, turn: Color, now: u64) -> u64 {
+        let remaining = match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        if color == turn {
+            remaining.saturating_sub(now.saturating_sub(self.timestamp))
+        } else {
+            remaining
+        }
+    }
+
+    /// Whether the player to move (`turn`) has run out of time at time `now`.
+    pub fn flagged(&self, turn: Color, now: u64) -> bool {
+        self.remaining(turn, turn, now) == 0
+    }
+
+    /// Stop the clock of `color` after they make a move at time `now`.
+    ///
+    /// The time elapsed since the last move is debited from `color`'s clock, after which they
+    /// receive their increment (or, for correspondence games, the full time for their next move).
+    /// Fails if `color` has already run out of time.
+    pub fn punch(&mut self, color: Color, now: u64) -> anyhow::Result<()> {
+        ensure!(!self.flagged(color, now), "{color} has run out of time");
+        let remaining = self.remaining(color, color, now);
+        let remaining = match self.time_control {
+            TimeControl::Clock { increment, .. } => remaining + increment,
+            TimeControl::Correspondence { days } => days * DAY,
+        };
+        match color {
+            Color::White => self.white = remaining,
+            Color::Black => self.black = remaining,
+        }
+        self.timestamp = now;
+        Ok(())
+    }
+
+    /// Stop the clock of `color` at time `now` without them having made a move, e.g. because moves
+    /// were taken back and it is now the opponent's turn.
+    ///
+    /// The time elapsed since the last move is debited from `color`'s clock, but they receive no
+    /// increment. Fails if `color` has already run out of time.
+    pub fn stop(&mut self, color: Color, now: u64) -> anyhow::Result<()> {
+        ensure!(!self.flagged(color, now), "{color} has run out of time");
+        let remaining = self.remaining(color, color, now);
+        match color {
+            Color::White => self.white = remaining,
+            Color::Black => self.black = remaining,
+        }
+        self.timestamp = now;
+        Ok(())
+    }
+}
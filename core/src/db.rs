@@ -1,23 +1,32 @@
 use crate::{
-    game::{Game, GameId, Move, Outcome, San},
-    message::{self, UserStats},
-    rating,
+    clock::{Clock, TimeControl},
+    game::{Color, Game, GameHash, GameId, GameSetup, Move, Outcome, San, Variant},
+    message::{self, ChallengeId, GameFilter, PuzzleId, SeekId, TournamentId, UserStats},
+    pgn,
+    puzzle::{self, Commitment, Line},
+    rating::{self, Pool, RatingSystem},
+    tournament::{self, Format},
+    wager::{Asset, Stake},
 };
-use alloy::primitives::Address;
-use anyhow::Context;
+use alloy::primitives::{Address, B256, U256};
+use anyhow::{bail, ensure, Context};
 use derive_more::Into;
 use futures::stream::{Stream, StreamExt, TryStreamExt};
 use glicko2::{GameResult, Glicko2Rating, GlickoRating};
 use sqlx::{
     migrate, query, query_as,
-    sqlite::{Sqlite, SqliteConnectOptions, SqliteConnection},
-    ConnectOptions, Connection, Transaction,
+    sqlite::{Sqlite, SqliteConnectOptions, SqliteConnection, SqliteTransactionManager},
+    ConnectOptions, Connection, Transaction, TransactionManager,
+};
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    path::Path,
 };
-use std::path::Path;
 
 #[derive(Debug)]
 pub struct Db {
     conn: SqliteConnection,
+    rating_system: RatingSystem,
 }
 
 impl Db {
@@ -37,53 +46,1194 @@ impl Db {
     async fn new(opt: SqliteConnectOptions) -> anyhow::Result<Self> {
         let mut conn = opt.connect().await?;
         migrate!("db/migrations").run(&mut conn).await?;
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            rating_system: Default::default(),
+        })
+    }
+
+    /// Configure the rating system used to update players' ratings.
+    pub fn set_rating_system(&mut self, rating_system: RatingSystem) {
+        self.rating_system = rating_system;
+    }
+
+    /// Start a transaction, so that a series of operations can be applied atomically.
+    ///
+    /// Operations which use transactions of their own are nested inside it. The transaction must be
+    /// ended with [`commit`](Self::commit) or [`rollback`](Self::rollback).
+    pub async fn begin(&mut self) -> anyhow::Result<()> {
+        SqliteTransactionManager::begin(&mut self.conn).await?;
+        Ok(())
+    }
+
+    /// Commit the transaction started by [`begin`](Self::begin).
+    pub async fn commit(&mut self) -> anyhow::Result<()> {
+        SqliteTransactionManager::commit(&mut self.conn).await?;
+        Ok(())
+    }
+
+    /// Undo everything since the transaction was started by [`begin`](Self::begin).
+    pub async fn rollback(&mut self) -> anyhow::Result<()> {
+        SqliteTransactionManager::rollback(&mut self.conn).await?;
+        Ok(())
+    }
+
+    pub async fn new_game(
+        &mut self,
+        white: Address,
+        black: Address,
+        setup: GameSetup,
+        clock: Option<Clock>,
+        timestamp: u64,
+    ) -> anyhow::Result<Game> {
+        self.create_game(None, white, black, setup, clock, timestamp)
+            .await
     }
 
-    pub async fn new_game(&mut self, white: Address, black: Address) -> anyhow::Result<Game> {
+    /// Create a game with a known ID, such as when mirroring games created by the dApp.
+    pub async fn new_game_with_id(
+        &mut self,
+        id: GameId,
+        white: Address,
+        black: Address,
+        setup: GameSetup,
+        clock: Option<Clock>,
+        timestamp: u64,
+    ) -> anyhow::Result<Game> {
+        self.create_game(Some(id), white, black, setup, clock, timestamp)
+            .await
+    }
+
+    async fn create_game(
+        &mut self,
+        id: Option<GameId>,
+        white: Address,
+        black: Address,
+        setup: GameSetup,
+        clock: Option<Clock>,
+        timestamp: u64,
+    ) -> anyhow::Result<Game> {
         let mut tx = self.conn.begin().await?;
 
         // Ensure users exist.
-        let unrated = rating::unrated();
         for address in [white, black] {
-            query("INSERT OR IGNORE INTO user (address, elo_value, elo_deviation, elo_volatility) VALUES ($1, $2, $3, $4)")
-                .bind(address.to_string())
-                .bind(unrated.value)
-                .bind(unrated.deviation)
-                .bind(unrated.volatility)
+            query(
+                "INSERT OR IGNORE INTO user
+                    (address, white_wins, white_losses, white_draws, black_wins, black_losses, black_draws)
+                    VALUES ($1, 0, 0, 0, 0, 0, 0)",
+            )
+            .bind(address.to_string())
+            .execute(tx.as_mut())
+            .await?;
+        }
+
+        let pool = Pool::new(setup.variant, clock.map(|clock| clock.time_control));
+        let (variant, start_fen) = setup_columns(&setup);
+        let (id,): (i32,) = query_as(
+            "INSERT INTO game (id, white, black, created_at, variant, start_fen, pool)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                RETURNING id",
+        )
+        .bind(id.map(i32::from))
+        .bind(white.to_string())
+        .bind(black.to_string())
+        .bind(timestamp as i64)
+        .bind(variant)
+        .bind(start_fen)
+        .bind(pool.to_string())
+        .fetch_one(tx.as_mut())
+        .await?;
+        if let Some(clock) = &clock {
+            let (base, increment, days) = time_control_columns(Some(clock.time_control));
+            query("UPDATE game SET (time_base, time_increment, time_days) = ($1, $2, $3) WHERE id = $4")
+                .bind(base)
+                .bind(increment)
+                .bind(days)
+                .bind(id)
                 .execute(tx.as_mut())
                 .await?;
+            update_clock(&mut tx, id.into(), clock).await?;
         }
+        tx.commit().await?;
+
+        tracing::debug!(id, %white, %black, ?setup, "created new game");
+        Game::with_setup(id.into(), white, black, setup)
+    }
+
+    /// Record a pending challenge from `challenger` to `opponent`.
+    pub async fn new_challenge(
+        &mut self,
+        challenger: Address,
+        opponent: Address,
+        first_move: Option<String>,
+        time_control: Option<TimeControl>,
+        setup: &GameSetup,
+        expires_at: u64,
+    ) -> anyhow::Result<ChallengeId> {
+        let (base, increment, days) = time_control_columns(time_control);
+        let (variant, start_fen) = setup_columns(setup);
+        let (id,): (i32,) = query_as(
+            "INSERT INTO challenge
+                (challenger, opponent, first_move, time_base, time_increment, time_days, expires_at,
+                    variant, start_fen)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                RETURNING id",
+        )
+        .bind(challenger.to_string())
+        .bind(opponent.to_string())
+        .bind(first_move)
+        .bind(base)
+        .bind(increment)
+        .bind(days)
+        .bind(expires_at as i64)
+        .bind(variant)
+        .bind(start_fen)
+        .fetch_one(&mut self.conn)
+        .await?;
+
+        tracing::debug!(id, %challenger, %opponent, "created new challenge");
+        Ok(id.into())
+    }
+
+    /// Record the stake escrowed for challenge `id`, which each player will wager.
+    pub async fn set_challenge_stake(
+        &mut self,
+        id: ChallengeId,
+        stake: Stake,
+    ) -> anyhow::Result<()> {
+        let (asset, amount) = stake_columns(Some(stake));
+        query("UPDATE challenge SET (stake_asset, stake_amount) = ($1, $2) WHERE id = $3")
+            .bind(asset)
+            .bind(amount)
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Look up a pending challenge.
+    pub async fn challenge(&mut self, id: ChallengeId) -> anyhow::Result<message::Challenge> {
+        let row = query_as(
+            "SELECT id, challenger, opponent, first_move, time_base, time_increment, time_days, expires_at,
+                    variant, start_fen, stake_asset, stake_amount
+                FROM challenge WHERE id = $1 LIMIT 1",
+        )
+        .bind(i32::from(id))
+        .fetch_optional(&mut self.conn)
+        .await?
+        .context(format!("challenge {id} not found"))?;
+        challenge_from_row(row)
+    }
+
+    /// Remove a challenge which has been accepted, declined or cancelled.
+    pub async fn delete_challenge(&mut self, id: ChallengeId) -> anyhow::Result<()> {
+        query("DELETE FROM challenge WHERE id = $1")
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Remove all challenges which have expired as of `now`.
+    ///
+    /// Stakes escrowed for expired challenges are returned to the challengers.
+    pub async fn expire_challenges(&mut self, now: u64) -> anyhow::Result<()> {
+        let mut tx = self.conn.begin().await?;
+        let stakes: Vec<(String, String, String)> = query_as(
+            "SELECT challenger, stake_asset, stake_amount FROM challenge
+                WHERE expires_at < $1 AND stake_asset IS NOT NULL",
+        )
+        .bind(now as i64)
+        .fetch_all(tx.as_mut())
+        .await?;
+        for (challenger, asset, amount) in stakes {
+            let stake = stake_from_columns(Some(asset), Some(amount))?.unwrap();
+            update_balance(&mut tx, challenger.parse()?, stake.asset, |balance| {
+                balance
+                    .checked_add(stake.amount)
+                    .context("balance overflow")
+            })
+            .await?;
+        }
+        query("DELETE FROM challenge WHERE expires_at < $1")
+            .bind(now as i64)
+            .execute(tx.as_mut())
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Pending challenges issued to `address`.
+    pub fn incoming_challenges(
+        &mut self,
+        address: Address,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::Challenge>> {
+        query_as(
+            "SELECT id, challenger, opponent, first_move, time_base, time_increment, time_days, expires_at,
+                    variant, start_fen, stake_asset, stake_amount
+                FROM challenge WHERE opponent = $1 ORDER BY id",
+        )
+        .bind(address.to_string())
+        .fetch(&mut self.conn)
+        .map(|res| challenge_from_row(res?))
+    }
+
+    /// Pending challenges issued by `address`.
+    pub fn outgoing_challenges(
+        &mut self,
+        address: Address,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::Challenge>> {
+        query_as(
+            "SELECT id, challenger, opponent, first_move, time_base, time_increment, time_days, expires_at,
+                    variant, start_fen, stake_asset, stake_amount
+                FROM challenge WHERE challenger = $1 ORDER BY id",
+        )
+        .bind(address.to_string())
+        .fetch(&mut self.conn)
+        .map(|res| challenge_from_row(res?))
+    }
+
+    /// Record an open seek.
+    ///
+    /// Fails if `player` already has an open seek.
+    pub async fn new_seek(
+        &mut self,
+        player: Address,
+        min_rating: Option<f64>,
+        max_rating: Option<f64>,
+        color: Option<Color>,
+        time_control: Option<TimeControl>,
+    ) -> anyhow::Result<SeekId> {
+        self.ensure_not_seeking(player).await?;
+
+        let (base, increment, days) = time_control_columns(time_control);
+        let (id,): (i32,) = query_as(
+            "INSERT INTO seek
+                (player, min_rating, max_rating, color, time_base, time_increment, time_days)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                RETURNING id",
+        )
+        .bind(player.to_string())
+        .bind(min_rating)
+        .bind(max_rating)
+        .bind(color.map(|color| color.to_string()))
+        .bind(base)
+        .bind(increment)
+        .bind(days)
+        .fetch_one(&mut self.conn)
+        .await?;
+
+        tracing::debug!(id, %player, "created new seek");
+        Ok(id.into())
+    }
+
+    /// Find the oldest open seek which `seek` can be paired with.
+    ///
+    /// Fails if the player posting `seek` already has an open seek, regardless of whether a
+    /// compatible seek exists.
+    pub async fn find_pairing(
+        &mut self,
+        seek: &message::Seek,
+    ) -> anyhow::Result<Option<message::Seek>> {
+        self.ensure_not_seeking(seek.player).await?;
+
+        let pool = Pool::new(Variant::Standard, seek.time_control);
+        let rating = self.rating(seek.player, pool).await?;
+        let seeks: Vec<message::Seek> = self.seeks(None).try_collect().await?;
+        for other in seeks {
+            let other_rating = self.rating(other.player, pool).await?;
+            if seek.compatible(rating, &other, other_rating) {
+                return Ok(Some(other));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn ensure_not_seeking(&mut self, player: Address) -> anyhow::Result<()> {
+        let (seeking,): (bool,) = query_as("SELECT EXISTS (SELECT 1 FROM seek WHERE player = $1)")
+            .bind(player.to_string())
+            .fetch_one(&mut self.conn)
+            .await?;
+        ensure!(!seeking, "{player} already has an open seek");
+        Ok(())
+    }
+
+    /// Look up an open seek.
+    pub async fn seek(&mut self, id: SeekId) -> anyhow::Result<message::Seek> {
+        let row = query_as(
+            "SELECT id, player, min_rating, max_rating, color, time_base, time_increment, time_days
+                FROM seek WHERE id = $1 LIMIT 1",
+        )
+        .bind(i32::from(id))
+        .fetch_optional(&mut self.conn)
+        .await?
+        .context(format!("seek {id} not found"))?;
+        seek_from_row(row)
+    }
+
+    /// Remove a seek which has been paired or cancelled.
+    pub async fn delete_seek(&mut self, id: SeekId) -> anyhow::Result<()> {
+        query("DELETE FROM seek WHERE id = $1")
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Open seeks, oldest first.
+    pub fn seeks(
+        &mut self,
+        after: Option<SeekId>,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::Seek>> {
+        let from = after.map(|id| i32::from(id) + 1).unwrap_or_default();
+        query_as(
+            "SELECT id, player, min_rating, max_rating, color, time_base, time_increment, time_days
+                FROM seek WHERE id >= $1 ORDER BY id",
+        )
+        .bind(from)
+        .fetch(&mut self.conn)
+        .map(|res| seek_from_row(res?))
+    }
+
+    /// The amount of `asset` available to `address`.
+    pub async fn balance(&mut self, address: Address, asset: Asset) -> anyhow::Result<U256> {
+        get_balance(&mut self.conn, address, asset).await
+    }
+
+    /// The balances of all assets held by `address`.
+    pub fn balances(&mut self, address: Address) -> impl '_ + Stream<Item = anyhow::Result<Stake>> {
+        query_as("SELECT asset, amount FROM balance WHERE address = $1 ORDER BY asset")
+            .bind(address.to_string())
+            .fetch(&mut self.conn)
+            .map(|res| {
+                let (asset, amount): (String, String) = res?;
+                Ok(stake_from_columns(Some(asset), Some(amount))?.unwrap())
+            })
+    }
+
+    /// Add funds to the balance of `address`.
+    pub async fn credit(&mut self, address: Address, stake: Stake) -> anyhow::Result<()> {
+        let mut tx = self.conn.begin().await?;
+        update_balance(&mut tx, address, stake.asset, |balance| {
+            balance
+                .checked_add(stake.amount)
+                .context("balance overflow")
+        })
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
 
-        let (id,): (i32,) =
-            query_as("INSERT INTO game (white, black) VALUES ($1, $2) RETURNING id")
-                .bind(white.to_string())
-                .bind(black.to_string())
-                .fetch_one(tx.as_mut())
+    /// Remove funds from the balance of `address`.
+    ///
+    /// Fails if the balance is insufficient.
+    pub async fn debit(&mut self, address: Address, stake: Stake) -> anyhow::Result<()> {
+        let mut tx = self.conn.begin().await?;
+        update_balance(&mut tx, address, stake.asset, |balance| {
+            match balance.checked_sub(stake.amount) {
+                Some(balance) => Ok(balance),
+                None => bail!(
+                    "insufficient balance: {address} has {balance} {}",
+                    stake.asset
+                ),
+            }
+        })
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record the stake wagered by each player in game `id`.
+    pub async fn set_stake(&mut self, id: GameId, stake: Stake) -> anyhow::Result<()> {
+        let (asset, amount) = stake_columns(Some(stake));
+        query("UPDATE game SET (stake_asset, stake_amount) = ($1, $2) WHERE id = $3")
+            .bind(asset)
+            .bind(amount)
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// The stake wagered by each player in game `id`, if the game is wagered.
+    pub async fn stake(&mut self, id: GameId) -> anyhow::Result<Option<Stake>> {
+        let (asset, amount) =
+            query_as("SELECT stake_asset, stake_amount FROM game WHERE id = $1 LIMIT 1")
+                .bind(i32::from(id))
+                .fetch_optional(&mut self.conn)
+                .await?
+                .context(format!("game {id} not found"))?;
+        stake_from_columns(asset, amount)
+    }
+
+    /// Record a new puzzle posted by `author`.
+    pub async fn new_puzzle(
+        &mut self,
+        author: Address,
+        setup: &GameSetup,
+        solution: &Commitment,
+        timestamp: u64,
+    ) -> anyhow::Result<PuzzleId> {
+        let (variant, fen) = setup_columns(setup);
+        let fen = fen.context("puzzle has no starting position")?;
+        let (id,): (i32,) = query_as(
+            "INSERT INTO puzzle (author, variant, fen, solution_hash, length, checkmate, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                RETURNING id",
+        )
+        .bind(author.to_string())
+        .bind(variant)
+        .bind(fen)
+        .bind(solution.hash.to_string())
+        .bind(solution.length)
+        .bind(solution.checkmate)
+        .bind(timestamp as i64)
+        .fetch_one(&mut self.conn)
+        .await?;
+
+        tracing::debug!(id, %author, "created new puzzle");
+        Ok(id.into())
+    }
+
+    /// Look up a puzzle.
+    pub async fn puzzle(&mut self, id: PuzzleId) -> anyhow::Result<message::Puzzle> {
+        let row = query_as(
+            "SELECT id, author, variant, fen, created_at,
+                    (SELECT count(*) FROM puzzle_solver WHERE puzzle_solver.puzzle = puzzle.id),
+                    length, checkmate, revealed_at
+                FROM puzzle WHERE id = $1 LIMIT 1",
+        )
+        .bind(i32::from(id))
+        .fetch_optional(&mut self.conn)
+        .await?
+        .context(format!("puzzle {id} not found"))?;
+        puzzle_from_row(row)
+    }
+
+    /// The author's commitment to the solution of a puzzle.
+    pub async fn puzzle_commitment(&mut self, id: PuzzleId) -> anyhow::Result<Commitment> {
+        let (hash, length, checkmate): (String, u32, bool) =
+            query_as("SELECT solution_hash, length, checkmate FROM puzzle WHERE id = $1 LIMIT 1")
+                .bind(i32::from(id))
+                .fetch_optional(&mut self.conn)
+                .await?
+                .context(format!("puzzle {id} not found"))?;
+        Ok(Commitment {
+            hash: hash.parse()?,
+            length,
+            checkmate,
+        })
+    }
+
+    /// Record the solution of a puzzle, revealed by its author at time `timestamp`.
+    ///
+    /// Once the solution is revealed, no more solutions can be committed to.
+    pub async fn reveal_puzzle(
+        &mut self,
+        id: PuzzleId,
+        solution: &Line,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        query("UPDATE puzzle SET (solution, revealed_at) = ($1, $2) WHERE id = $3")
+            .bind(solution.moves.join(" "))
+            .bind(timestamp as i64)
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// The revealed solution of a puzzle, in SAN+.
+    ///
+    /// Fails if the author has not revealed the solution yet.
+    pub async fn puzzle_solution(&mut self, id: PuzzleId) -> anyhow::Result<Vec<String>> {
+        let (solution,): (Option<String>,) =
+            query_as("SELECT solution FROM puzzle WHERE id = $1 LIMIT 1")
+                .bind(i32::from(id))
+                .fetch_optional(&mut self.conn)
+                .await?
+                .context(format!("puzzle {id} not found"))?;
+        let solution = solution.context(format!("puzzle {id} has not been revealed yet"))?;
+        Ok(solution.split(' ').map(String::from).collect())
+    }
+
+    /// Record `solver`'s commitment to a solution of a puzzle at time `timestamp`.
+    ///
+    /// Fails if `solver` has already committed to a solution.
+    pub async fn commit_solution(
+        &mut self,
+        id: PuzzleId,
+        solver: Address,
+        commitment: B256,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        let (committed,): (bool,) = query_as(
+            "SELECT EXISTS (SELECT 1 FROM puzzle_commitment WHERE puzzle = $1 AND solver = $2)",
+        )
+        .bind(i32::from(id))
+        .bind(solver.to_string())
+        .fetch_one(&mut self.conn)
+        .await?;
+        ensure!(
+            !committed,
+            "{solver} has already committed to a solution of puzzle {id}"
+        );
+
+        query(
+            "INSERT INTO puzzle_commitment (puzzle, solver, commitment, committed_at)
+                VALUES ($1, $2, $3, $4)",
+        )
+        .bind(i32::from(id))
+        .bind(solver.to_string())
+        .bind(commitment.to_string())
+        .bind(timestamp as i64)
+        .execute(&mut self.conn)
+        .await?;
+        Ok(())
+    }
+
+    /// The hash `solver` committed to as their solution of a puzzle.
+    ///
+    /// Fails if `solver` has not committed to a solution, or has already revealed it.
+    pub async fn solution_commitment(
+        &mut self,
+        id: PuzzleId,
+        solver: Address,
+    ) -> anyhow::Result<B256> {
+        let (commitment, solved): (String, bool) = query_as(
+            "SELECT commitment, solved FROM puzzle_commitment
+                WHERE puzzle = $1 AND solver = $2 LIMIT 1",
+        )
+        .bind(i32::from(id))
+        .bind(solver.to_string())
+        .fetch_optional(&mut self.conn)
+        .await?
+        .context(format!(
+            "{solver} has not committed to a solution of puzzle {id}"
+        ))?;
+        ensure!(!solved, "{solver} has already solved puzzle {id}");
+        Ok(commitment.parse()?)
+    }
+
+    /// Record that `solver` revealed a correct solution of a puzzle.
+    ///
+    /// The solve is ranked, and its collectible awarded, when the puzzle is settled.
+    pub async fn solve_puzzle(&mut self, id: PuzzleId, solver: Address) -> anyhow::Result<()> {
+        query("UPDATE puzzle_commitment SET solved = TRUE WHERE puzzle = $1 AND solver = $2")
+            .bind(i32::from(id))
+            .bind(solver.to_string())
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Settle all puzzles whose reveal period has ended as of `now`.
+    ///
+    /// The solvers of each puzzle are ranked in the order they committed to their solutions, and
+    /// awarded collectibles valued according to their rank and the time elapsed between the puzzle
+    /// being posted and their commitment. Returns the new solves.
+    pub async fn settle_puzzles(&mut self, now: u64) -> anyhow::Result<Vec<message::PuzzleSolve>> {
+        let mut tx = self.conn.begin().await?;
+
+        let puzzles: Vec<(i32, i64)> = query_as(
+            "SELECT id, created_at FROM puzzle
+                WHERE NOT settled AND revealed_at IS NOT NULL AND revealed_at + $1 <= $2
+                ORDER BY id",
+        )
+        .bind(puzzle::REVEAL_PERIOD as i64)
+        .bind(now as i64)
+        .fetch_all(tx.as_mut())
+        .await?;
+
+        let mut solves = vec![];
+        for (id, created_at) in puzzles {
+            let solvers: Vec<(String, i64)> = query_as(
+                "SELECT solver, committed_at FROM puzzle_commitment
+                    WHERE puzzle = $1 AND solved ORDER BY id",
+            )
+            .bind(id)
+            .fetch_all(tx.as_mut())
+            .await?;
+            for (i, (solver, committed_at)) in solvers.into_iter().enumerate() {
+                let rank = i as u32 + 1;
+                let value =
+                    puzzle::collectible_value(committed_at.saturating_sub(created_at) as u64, rank);
+                query(
+                    "INSERT INTO puzzle_solver (puzzle, solver, rank, solved_at, value)
+                        VALUES ($1, $2, $3, $4, $5)",
+                )
+                .bind(id)
+                .bind(&solver)
+                .bind(rank)
+                .bind(committed_at)
+                .bind(value as i64)
+                .execute(tx.as_mut())
                 .await?;
+                solves.push(message::PuzzleSolve {
+                    puzzle: id.into(),
+                    solver: solver.parse()?,
+                    rank,
+                    timestamp: committed_at as u64,
+                    value,
+                });
+            }
+
+            query("UPDATE puzzle SET settled = TRUE WHERE id = $1")
+                .bind(id)
+                .execute(tx.as_mut())
+                .await?;
+            tracing::debug!(id, "settled puzzle");
+        }
+
         tx.commit().await?;
+        Ok(solves)
+    }
 
-        tracing::debug!(id, %white, %black, "created new game");
-        Ok(Game::new(id.into(), white, black))
+    /// List puzzles, optionally starting after a given puzzle.
+    pub fn puzzles(
+        &mut self,
+        after: Option<PuzzleId>,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::Puzzle>> {
+        let from = after.map(|id| i32::from(id) + 1).unwrap_or_default();
+        query_as(
+            "SELECT id, author, variant, fen, created_at,
+                    (SELECT count(*) FROM puzzle_solver WHERE puzzle_solver.puzzle = puzzle.id),
+                    length, checkmate, revealed_at
+                FROM puzzle WHERE id >= $1 ORDER BY id",
+        )
+        .bind(from)
+        .fetch(&mut self.conn)
+        .map(|res| puzzle_from_row(res?))
     }
 
-    pub async fn insert_game(&mut self, game: &Game) -> anyhow::Result<()> {
-        query("INSERt INTO game (id, white, black) VALUES ($1, $2, $3)")
-            .bind(i32::from(game.id()))
-            .bind(game.white().to_string())
-            .bind(game.black().to_string())
+    /// The solvers of a puzzle, in the order they solved it.
+    pub fn puzzle_solvers(
+        &mut self,
+        id: PuzzleId,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::PuzzleSolve>> {
+        query_as(
+            "SELECT solver, rank, solved_at, value FROM puzzle_solver
+                WHERE puzzle = $1 ORDER BY rank",
+        )
+        .bind(i32::from(id))
+        .fetch(&mut self.conn)
+        .map(move |res| {
+            let (solver, rank, timestamp, value): (String, u32, i64, i64) = res?;
+            Ok(message::PuzzleSolve {
+                puzzle: id,
+                solver: solver.parse()?,
+                rank,
+                timestamp: timestamp as u64,
+                value: value as u64,
+            })
+        })
+    }
+
+    /// The top `limit` puzzle solvers, by the total value of their collectibles.
+    pub fn puzzle_rankings(
+        &mut self,
+        limit: u32,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::PuzzleRanking>> {
+        query_as(
+            "SELECT solver, count(*), sum(value) FROM puzzle_solver
+                GROUP BY solver
+                ORDER BY sum(value) DESC, count(*) DESC, min(solved_at)
+                LIMIT $1",
+        )
+        .bind(limit)
+        .fetch(&mut self.conn)
+        .map(|res| {
+            let (solver, solved, value): (String, u32, i64) = res?;
+            Ok(message::PuzzleRanking {
+                solver: solver.parse()?,
+                solved,
+                value: value as u64,
+            })
+        })
+    }
+
+    /// Record a new tournament organized by `organizer`.
+    pub async fn new_tournament(
+        &mut self,
+        organizer: Address,
+        name: &str,
+        format: Format,
+        rounds: Option<u32>,
+        time_control: Option<TimeControl>,
+        timestamp: u64,
+    ) -> anyhow::Result<TournamentId> {
+        let (base, increment, days) = time_control_columns(time_control);
+        let (id,): (i32,) = query_as(
+            "INSERT INTO tournament
+                (organizer, name, format, rounds, time_base, time_increment, time_days, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                RETURNING id",
+        )
+        .bind(organizer.to_string())
+        .bind(name)
+        .bind(format.to_string())
+        .bind(rounds)
+        .bind(base)
+        .bind(increment)
+        .bind(days)
+        .bind(timestamp as i64)
+        .fetch_one(&mut self.conn)
+        .await?;
+
+        tracing::debug!(id, %organizer, name, %format, "created new tournament");
+        Ok(id.into())
+    }
+
+    /// Look up a tournament.
+    pub async fn tournament(&mut self, id: TournamentId) -> anyhow::Result<message::Tournament> {
+        let row = query_as(
+            "SELECT id, organizer, name, format, rounds, time_base, time_increment, time_days,
+                    (SELECT count(*) FROM tournament_player WHERE tournament_player.tournament = tournament.id),
+                    round, finished_at
+                FROM tournament WHERE id = $1 LIMIT 1",
+        )
+        .bind(i32::from(id))
+        .fetch_optional(&mut self.conn)
+        .await?
+        .context(format!("tournament {id} not found"))?;
+        tournament_from_row(row)
+    }
+
+    /// List tournaments, optionally starting after a given tournament.
+    pub fn tournaments(
+        &mut self,
+        after: Option<TournamentId>,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::Tournament>> {
+        let from = after.map(|id| i32::from(id) + 1).unwrap_or_default();
+        query_as(
+            "SELECT id, organizer, name, format, rounds, time_base, time_increment, time_days,
+                    (SELECT count(*) FROM tournament_player WHERE tournament_player.tournament = tournament.id),
+                    round, finished_at
+                FROM tournament WHERE id >= $1 ORDER BY id",
+        )
+        .bind(from)
+        .fetch(&mut self.conn)
+        .map(|res| tournament_from_row(res?))
+    }
+
+    /// Register `player` for a tournament.
+    ///
+    /// Fails if the tournament has already started or `player` has already joined.
+    pub async fn join_tournament(
+        &mut self,
+        id: TournamentId,
+        player: Address,
+    ) -> anyhow::Result<()> {
+        let tournament = self.tournament(id).await?;
+        ensure!(
+            tournament.round.is_none(),
+            "tournament {id} has already started"
+        );
+        ensure!(
+            tournament.players < tournament::MAX_PLAYERS,
+            "tournament {id} is full"
+        );
+        let (joined,): (bool,) = query_as(
+            "SELECT EXISTS (SELECT 1 FROM tournament_player WHERE tournament = $1 AND player = $2)",
+        )
+        .bind(i32::from(id))
+        .bind(player.to_string())
+        .fetch_one(&mut self.conn)
+        .await?;
+        ensure!(!joined, "{player} has already joined tournament {id}");
+
+        let pool = Pool::new(Default::default(), tournament.time_control);
+        let rating = self.rating(player, pool).await?;
+        query("INSERT INTO tournament_player (tournament, player, rating) VALUES ($1, $2, $3)")
+            .bind(i32::from(id))
+            .bind(player.to_string())
+            .bind(rating)
             .execute(&mut self.conn)
             .await?;
         Ok(())
     }
 
+    /// The players registered for a tournament, with their ratings, in seeding order.
+    pub async fn tournament_players(
+        &mut self,
+        id: TournamentId,
+    ) -> anyhow::Result<Vec<(Address, f64)>> {
+        let rows: Vec<(String, f64)> = query_as(
+            "SELECT player, rating FROM tournament_player
+                WHERE tournament = $1 ORDER BY rating DESC, rowid",
+        )
+        .bind(i32::from(id))
+        .fetch_all(&mut self.conn)
+        .await?;
+        rows.into_iter()
+            .map(|(player, rating)| Ok((player.parse()?, rating)))
+            .collect()
+    }
+
+    /// Close registration for a tournament which will last for `rounds` rounds.
+    ///
+    /// The players' current ratings are recorded, for seeding. The first round must then be paired
+    /// using [`add_pairing`](Self::add_pairing) and started using
+    /// [`set_tournament_round`](Self::set_tournament_round).
+    pub async fn start_tournament(&mut self, id: TournamentId, rounds: u32) -> anyhow::Result<()> {
+        let tournament = self.tournament(id).await?;
+        let pool = Pool::new(Default::default(), tournament.time_control);
+        for (player, _) in self.tournament_players(id).await? {
+            let rating = self.rating(player, pool).await?;
+            query("UPDATE tournament_player SET rating = $1 WHERE tournament = $2 AND player = $3")
+                .bind(rating)
+                .bind(i32::from(id))
+                .bind(player.to_string())
+                .execute(&mut self.conn)
+                .await?;
+        }
+        query("UPDATE tournament SET rounds = $1 WHERE id = $2")
+            .bind(rounds)
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the round currently in progress in a tournament.
+    pub async fn set_tournament_round(
+        &mut self,
+        id: TournamentId,
+        round: u32,
+    ) -> anyhow::Result<()> {
+        query("UPDATE tournament SET round = $1 WHERE id = $2")
+            .bind(round)
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark a tournament as finished, once every game of its last round is over.
+    pub async fn finish_tournament(
+        &mut self,
+        id: TournamentId,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        query("UPDATE tournament SET finished_at = $1 WHERE id = $2")
+            .bind(timestamp as i64)
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the pairing of `white` and `black` in round `round` of a tournament.
+    ///
+    /// If `black` is [`None`], `white` has a bye and there is no `game`.
+    pub async fn add_pairing(
+        &mut self,
+        id: TournamentId,
+        round: u32,
+        white: Address,
+        black: Option<Address>,
+        game: Option<GameId>,
+    ) -> anyhow::Result<()> {
+        query(
+            "INSERT INTO tournament_pairing (tournament, round, white, black, game)
+                VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(i32::from(id))
+        .bind(round)
+        .bind(white.to_string())
+        .bind(black.map(|black| black.to_string()))
+        .bind(game.map(i32::from))
+        .execute(&mut self.conn)
+        .await?;
+        Ok(())
+    }
+
+    /// The pairings of a tournament, along with the results of the games played, optionally
+    /// restricted to a single round.
+    pub fn pairings(
+        &mut self,
+        id: TournamentId,
+        round: Option<u32>,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::Pairing>> {
+        query_as(
+            "SELECT tournament_pairing.round, tournament_pairing.game, tournament_pairing.white,
+                    tournament_pairing.black, game.result
+                FROM tournament_pairing LEFT JOIN game ON game.id = tournament_pairing.game
+                WHERE tournament_pairing.tournament = $1 AND ($2 IS NULL OR tournament_pairing.round = $2)
+                ORDER BY tournament_pairing.round, tournament_pairing.rowid",
+        )
+        .bind(i32::from(id))
+        .bind(round)
+        .fetch(&mut self.conn)
+        .map(|res| {
+            let (round, game, white, black, result): (
+                u32,
+                Option<i32>,
+                String,
+                Option<String>,
+                Option<String>,
+            ) = res?;
+            Ok(message::Pairing {
+                round,
+                game: game.map(GameId::from),
+                white: white.parse()?,
+                black: black.map(|black| black.parse()).transpose()?,
+                result: result.map(|result| result.parse()).transpose()?,
+            })
+        })
+    }
+
+    /// The current standings of a tournament.
+    pub async fn standings(&mut self, id: TournamentId) -> anyhow::Result<Vec<message::Standing>> {
+        let players = self.tournament_players(id).await?;
+        let pairings: Vec<message::Pairing> = self.pairings(id, None).try_collect().await?;
+        Ok(tournament::standings(&players, &pairings))
+    }
+
+    /// The tournament in which game `id` is being played, if any.
+    pub async fn game_tournament(&mut self, id: GameId) -> anyhow::Result<Option<TournamentId>> {
+        let tournament: Option<(i32,)> =
+            query_as("SELECT tournament FROM tournament_pairing WHERE game = $1 LIMIT 1")
+                .bind(i32::from(id))
+                .fetch_optional(&mut self.conn)
+                .await?;
+        Ok(tournament.map(|(id,)| id.into()))
+    }
+
+    /// Whether every game in round `round` of a tournament is over.
+    pub async fn round_over(&mut self, id: TournamentId, round: u32) -> anyhow::Result<bool> {
+        let (active,): (i64,) = query_as(
+            "SELECT count(*) FROM tournament_pairing JOIN game ON game.id = tournament_pairing.game
+                WHERE tournament_pairing.tournament = $1 AND tournament_pairing.round = $2
+                    AND game.ended_at IS NULL",
+        )
+        .bind(i32::from(id))
+        .bind(round)
+        .fetch_one(&mut self.conn)
+        .await?;
+        Ok(active == 0)
+    }
+
+    /// The current rating of `address` in `pool`, or the default rating for a player with no games
+    /// in the pool.
+    pub async fn rating(&mut self, address: Address, pool: Pool) -> anyhow::Result<f64> {
+        let rating = query_as(
+            "SELECT value, deviation, volatility,
+                    (SELECT period FROM rating_clock) - coalesce(period, (SELECT period FROM rating_clock))
+                FROM rating WHERE player = $1 AND pool = $2 LIMIT 1",
+        )
+        .bind(address.to_string())
+        .bind(pool.to_string())
+        .fetch_optional(&mut self.conn)
+        .await?
+        .map(|(value, deviation, volatility, inactive): (f64, f64, f64, i64)| {
+            self.rating_system.decay(
+                Glicko2Rating {
+                    value,
+                    deviation,
+                    volatility,
+                },
+                inactive.max(0) as u64,
+            )
+        })
+        .unwrap_or_else(rating::unrated);
+        Ok(GlickoRating::from(rating).value)
+    }
+
+    /// Apply the results of every rating period which is over as of `now`.
+    ///
+    /// This should be called with the timestamp of each input, before processing it. The results of
+    /// all games finished during a rating period are applied together, each rated against the
+    /// opponent's rating as of the start of the period. All games in a period thus share the same
+    /// before and after ratings in the players' rating histories.
+    pub async fn update_ratings(&mut self, now: u64) -> anyhow::Result<()> {
+        let system = self.rating_system;
+        let current = system.period_of(now);
+        let mut tx = self.conn.begin().await?;
+
+        let periods: Vec<(i64,)> =
+            query_as("SELECT DISTINCT period FROM rating_result WHERE period < $1 ORDER BY period")
+                .bind(current as i64)
+                .fetch_all(tx.as_mut())
+                .await?;
+        for (period,) in periods {
+            let period = period as u64;
+            let results: Vec<(String, i32, String, f64, i64, String)> = query_as(
+                "SELECT player, game, opponent, score, timestamp, game.pool
+                    FROM rating_result JOIN game ON game.id = rating_result.game
+                    WHERE period = $1 ORDER BY player, game",
+            )
+            .bind(period as i64)
+            .fetch_all(tx.as_mut())
+            .await?;
+
+            // Look up everyone's rating as of the start of the period, before updating any.
+            let mut ratings = BTreeMap::new();
+            let mut games: BTreeMap<(Address, Pool), Vec<_>> = BTreeMap::new();
+            for (player, game, opponent, score, timestamp, pool) in results {
+                let player: Address = player.parse()?;
+                let opponent: Address = opponent.parse()?;
+                let pool: Pool = pool.parse()?;
+                for address in [player, opponent] {
+                    if let Entry::Vacant(entry) = ratings.entry((address, pool)) {
+                        entry.insert(get_rating(&mut tx, &system, address, pool, period).await?);
+                    }
+                }
+                games.entry((player, pool)).or_default().push((
+                    GameId::from(game),
+                    opponent,
+                    score,
+                    timestamp as u64,
+                ));
+            }
+
+            for ((player, pool), games) in games {
+                let before = ratings[&(player, pool)];
+                let results: Vec<GameResult> = games
+                    .iter()
+                    .map(|&(_, opponent, score, _)| {
+                        let opponent = ratings[&(opponent, pool)];
+                        if score >= 1. {
+                            GameResult::win(opponent)
+                        } else if score <= 0. {
+                            GameResult::loss(opponent)
+                        } else {
+                            GameResult::draw(opponent)
+                        }
+                    })
+                    .collect();
+                let after = system.update(before, &results);
+                for (game, opponent, _, timestamp) in games {
+                    record_rating_change(
+                        &mut tx,
+                        game,
+                        (player, opponent),
+                        before,
+                        after,
+                        timestamp,
+                    )
+                    .await?;
+                }
+                set_rating(&mut tx, player, pool, after, period + 1).await?;
+            }
+        }
+
+        query("DELETE FROM rating_result WHERE period < $1")
+            .bind(current as i64)
+            .execute(tx.as_mut())
+            .await?;
+        query("UPDATE rating_clock SET period = max(period, $1)")
+            .bind(current as i64)
+            .execute(tx.as_mut())
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The changes in the rating of `address` due to each of their finished games, in order.
+    pub fn rating_history(
+        &mut self,
+        address: Address,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::RatingChange>> {
+        query_as(
+            "SELECT game, game.pool, opponent, timestamp,
+                    value_before, deviation_before, volatility_before,
+                    value_after, deviation_after, volatility_after
+                FROM rating_history JOIN game ON game.id = rating_history.game
+                WHERE player = $1 ORDER BY timestamp, game",
+        )
+        .bind(address.to_string())
+        .fetch(&mut self.conn)
+        .map(|res| rating_change_from_row(res?))
+    }
+
+    /// The `limit` highest rated players in `pool`.
+    ///
+    /// Only players who have finished at least `min_games` games in the pool, and whose rating deviation (on the
+    /// Glicko scale) is at most `max_deviation`, are included, so that players whose ratings are
+    /// still uncertain do not crowd the top of the leaderboard.
+    pub fn leaderboard(
+        &mut self,
+        pool: Pool,
+        limit: u32,
+        min_games: u32,
+        max_deviation: f64,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::LeaderboardEntry>> {
+        // Compare squared deviations, including the growth in deviation for each rating period a
+        // player has sat out.
+        let max_variance = rating::glicko2_deviation(max_deviation).powi(2);
+        let unrated_variance = rating::unrated().deviation.powi(2);
+        let system = self.rating_system;
+        query_as(
+            "SELECT player, value, deviation, volatility, inactive, games FROM (
+                SELECT player, value, deviation, volatility,
+                    max((SELECT period FROM rating_clock) - coalesce(period, (SELECT period FROM rating_clock)), 0)
+                        AS inactive,
+                    (SELECT count(*) FROM game
+                        WHERE rating.player IN (game.white, game.black) AND game.pool = rating.pool
+                            AND game.result IS NOT NULL)
+                        AS games
+                FROM rating WHERE pool = $5
+            )
+            WHERE games >= $2
+                AND min(deviation * deviation + inactive * volatility * volatility, $4) <= $1
+            ORDER BY value DESC, games DESC
+            LIMIT $3",
+        )
+        .bind(max_variance)
+        .bind(min_games)
+        .bind(limit)
+        .bind(unrated_variance)
+        .bind(pool.to_string())
+        .fetch(&mut self.conn)
+        .map(move |res| {
+            let (player, value, deviation, volatility, inactive, games): (
+                String,
+                f64,
+                f64,
+                f64,
+                i64,
+                u32,
+            ) = res?;
+            let rating = system.decay(
+                Glicko2Rating {
+                    value,
+                    deviation,
+                    volatility,
+                },
+                inactive as u64,
+            );
+            Ok(message::LeaderboardEntry {
+                player: player.parse()?,
+                rating: rating::glicko(rating),
+                games,
+            })
+        })
+    }
+
+    pub async fn insert_game(&mut self, game: &Game) -> anyhow::Result<()> {
+        let (variant, start_fen) = setup_columns(game.setup());
+        query(
+            "INSERT INTO game (id, white, black, variant, start_fen) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(i32::from(game.id()))
+        .bind(game.white().to_string())
+        .bind(game.black().to_string())
+        .bind(variant)
+        .bind(start_fen)
+        .execute(&mut self.conn)
+        .await?;
+        Ok(())
+    }
+
     pub async fn game(&mut self, id: GameId) -> anyhow::Result<Game> {
-        let (white, black): (String, String) =
-            query_as("SELECT white, black FROM game WHERE id = $1 LIMIT 1")
+        let (white, black, variant, start_fen): (String, String, Option<String>, Option<String>) =
+            query_as("SELECT white, black, variant, start_fen FROM game WHERE id = $1 LIMIT 1")
                 .bind(i32::from(id))
                 .fetch_optional(&mut self.conn)
                 .await?
                 .context(format!("game {id} not found"))?;
+        let setup = setup_from_columns(variant, start_fen)?;
         let moves =
             query_as::<_, (String,)>("SELECT san FROM move WHERE game = $1 ORDER BY half_move")
                 .bind(i32::from(id))
@@ -94,61 +1244,278 @@ impl Db {
                 })
                 .try_collect::<Vec<_>>()
                 .await?;
-        Game::from_moves(id, white.parse()?, black.parse()?, moves)
+        Game::from_moves(id, white.parse()?, black.parse()?, setup, moves)
+    }
+
+    /// Load a game which is still in progress.
+    ///
+    /// Fails if the game does not exist or is already over.
+    pub async fn active_game(&mut self, id: GameId) -> anyhow::Result<Game> {
+        ensure!(!self.game_over(id).await?, "game {id} is already over");
+        self.game(id).await
+    }
+
+    /// Whether game `id` is over.
+    pub async fn game_over(&mut self, id: GameId) -> anyhow::Result<bool> {
+        let (ended_at,): (Option<i64>,) =
+            query_as("SELECT ended_at FROM game WHERE id = $1 LIMIT 1")
+                .bind(i32::from(id))
+                .fetch_optional(&mut self.conn)
+                .await?
+                .context(format!("game {id} not found"))?;
+        Ok(ended_at.is_some())
+    }
+
+    pub async fn game_notation(&mut self, id: GameId) -> anyhow::Result<String> {
+        let (variant, start_fen): (Option<String>, Option<String>) =
+            query_as("SELECT variant, start_fen FROM game WHERE id = $1 LIMIT 1")
+                .bind(i32::from(id))
+                .fetch_optional(&mut self.conn)
+                .await?
+                .context(format!("game {id} not found"))?;
+        let (mut turn, mut i) = setup_from_columns(variant, start_fen)?.starting_move()?;
+        let moves =
+            query_as::<_, (String,)>("SELECT san FROM move WHERE game = $1 ORDER BY half_move")
+                .bind(i32::from(id))
+                .fetch_all(&mut self.conn)
+                .await?;
+
+        let mut notation = String::new();
+        for (n, (san,)) in moves.into_iter().enumerate() {
+            notation = match turn {
+                Color::White => format!("{notation}{i}.{san} "),
+                Color::Black if n == 0 => format!("{notation}{i}...{san} "),
+                Color::Black => format!("{notation}{san} "),
+            };
+            if turn == Color::Black {
+                i += 1;
+            }
+            turn = !turn;
+        }
+        Ok(notation)
+    }
+
+    pub async fn record_move(&mut self, id: GameId, m: Move) -> anyhow::Result<()> {
+        let mut tx = self.conn.begin().await?;
+        query("INSERT INTO move (game, half_move, san, hash) VALUES ($1, $2, $3, $4)")
+            .bind(i32::from(id))
+            .bind(m.half_move() as i32)
+            .bind(m.san())
+            .bind(m.hash().to_string())
+            .execute(tx.as_mut())
+            .await?;
+
+        // Making a move implicitly declines any pending draw offer or takeback request.
+        query("UPDATE game SET (draw_offer, takeback_request) = (NULL, NULL) WHERE id = $1")
+            .bind(i32::from(id))
+            .execute(tx.as_mut())
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The state of the clocks in game `id`, if it is timed.
+    pub async fn clock(&mut self, id: GameId) -> anyhow::Result<Option<Clock>> {
+        let (base, increment, days, white, black, timestamp): ClockRow = query_as(
+            "SELECT time_base, time_increment, time_days, white_clock, black_clock, clock_timestamp
+                FROM game WHERE id = $1 LIMIT 1",
+        )
+        .bind(i32::from(id))
+        .fetch_optional(&mut self.conn)
+        .await?
+        .context(format!("game {id} not found"))?;
+        let Some(time_control) = time_control_from_columns(base, increment, days) else {
+            return Ok(None);
+        };
+        Ok(Some(Clock {
+            time_control,
+            white: white.context("timed game is missing white clock")? as u64,
+            black: black.context("timed game is missing black clock")? as u64,
+            timestamp: timestamp.context("timed game is missing clock timestamp")? as u64,
+        }))
+    }
+
+    /// Update the state of the clocks in game `id`.
+    pub async fn set_clock(&mut self, id: GameId, clock: &Clock) -> anyhow::Result<()> {
+        let mut tx = self.conn.begin().await?;
+        update_clock(&mut tx, id, clock).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The player who has offered a draw in game `id`, if there is a pending offer.
+    pub async fn draw_offer(&mut self, id: GameId) -> anyhow::Result<Option<Address>> {
+        let (offer,): (Option<String>,) =
+            query_as("SELECT draw_offer FROM game WHERE id = $1 LIMIT 1")
+                .bind(i32::from(id))
+                .fetch_optional(&mut self.conn)
+                .await?
+                .context(format!("game {id} not found"))?;
+        Ok(offer.map(|address| address.parse()).transpose()?)
+    }
+
+    /// Record a draw offer from `player`, or clear the pending offer if `player` is [`None`].
+    pub async fn set_draw_offer(
+        &mut self,
+        id: GameId,
+        player: Option<Address>,
+    ) -> anyhow::Result<()> {
+        query("UPDATE game SET draw_offer = $1 WHERE id = $2")
+            .bind(player.map(|address| address.to_string()))
+            .bind(i32::from(id))
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Pending draw offers in games involving `address`, made by either player.
+    pub fn draw_offers(
+        &mut self,
+        address: Address,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::DrawOffer>> {
+        query_as(
+            "SELECT id, draw_offer FROM game
+                WHERE draw_offer IS NOT NULL AND $1 IN (white, black)
+                ORDER BY id",
+        )
+        .bind(address.to_string())
+        .fetch(&mut self.conn)
+        .map(|res| {
+            let (id, from): (i32, String) = res?;
+            Ok(message::DrawOffer {
+                id: id.into(),
+                from: from.parse()?,
+            })
+        })
     }
 
-    pub async fn game_notation(&mut self, id: GameId) -> anyhow::Result<String> {
-        let mut moves =
-            query_as::<_, (String,)>("SELECT san FROM move WHERE game = $1 ORDER BY half_move")
+    /// The player who has asked to take back their last move in game `id`, if there is a pending
+    /// request.
+    pub async fn takeback_request(&mut self, id: GameId) -> anyhow::Result<Option<Address>> {
+        let (request,): (Option<String>,) =
+            query_as("SELECT takeback_request FROM game WHERE id = $1 LIMIT 1")
                 .bind(i32::from(id))
-                .fetch_all(&mut self.conn)
+                .fetch_optional(&mut self.conn)
                 .await?
-                .into_iter();
-
-        let mut i = 1;
-        let mut notation = String::new();
-        while let Some((white_move,)) = moves.next() {
-            notation = format!("{notation}{i}.{white_move} ");
-            if let Some((black_move,)) = moves.next() {
-                notation = format!("{notation}{black_move} ");
-            } else {
-                break;
-            }
-            i += 1;
-        }
-        Ok(notation)
+                .context(format!("game {id} not found"))?;
+        Ok(request.map(|address| address.parse()).transpose()?)
     }
 
-    pub async fn record_move(&mut self, id: GameId, m: Move) -> anyhow::Result<()> {
-        query("INSERT INTO move (game, half_move, san) VALUES ($1, $2, $3)")
+    /// Record a takeback request from `player`, or clear the pending request if `player` is
+    /// [`None`].
+    pub async fn set_takeback_request(
+        &mut self,
+        id: GameId,
+        player: Option<Address>,
+    ) -> anyhow::Result<()> {
+        query("UPDATE game SET takeback_request = $1 WHERE id = $2")
+            .bind(player.map(|address| address.to_string()))
             .bind(i32::from(id))
-            .bind(m.half_move() as i32)
-            .bind(m.san())
             .execute(&mut self.conn)
             .await?;
         Ok(())
     }
 
-    pub async fn end_game(&mut self, game: &Game, outcome: Option<Outcome>) -> anyhow::Result<()> {
+    /// Pending takeback requests in games involving `address`, made by either player.
+    pub fn takeback_requests(
+        &mut self,
+        address: Address,
+    ) -> impl '_ + Stream<Item = anyhow::Result<message::TakebackRequest>> {
+        query_as(
+            "SELECT id, takeback_request FROM game
+                WHERE takeback_request IS NOT NULL AND $1 IN (white, black)
+                ORDER BY id",
+        )
+        .bind(address.to_string())
+        .fetch(&mut self.conn)
+        .map(|res| {
+            let (id, from): (i32, String) = res?;
+            Ok(message::TakebackRequest {
+                id: id.into(),
+                from: from.parse()?,
+            })
+        })
+    }
+
+    /// Take back moves in game `id`, leaving only the first `half_move` half moves.
+    ///
+    /// Any pending draw offer or takeback request is cleared.
+    pub async fn take_back(&mut self, id: GameId, half_move: u16) -> anyhow::Result<()> {
+        let mut tx = self.conn.begin().await?;
+        query("DELETE FROM move WHERE game = $1 AND half_move > $2")
+            .bind(i32::from(id))
+            .bind(half_move)
+            .execute(tx.as_mut())
+            .await?;
+        query("INSERT INTO takeback (game, half_move) VALUES ($1, $2)")
+            .bind(i32::from(id))
+            .bind(half_move)
+            .execute(tx.as_mut())
+            .await?;
+        query("UPDATE game SET (draw_offer, takeback_request) = (NULL, NULL) WHERE id = $1")
+            .bind(i32::from(id))
+            .execute(tx.as_mut())
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Takebacks in game `id`, skipping the first `seen`.
+    ///
+    /// Returns the total number of takebacks in the game, along with the fewest half moves left
+    /// by any of the takebacks after the first `seen`. A client which knows about `seen` takebacks
+    /// can trust the moves it has seen up to this point, and must discard any after it.
+    pub async fn takebacks(&mut self, id: GameId, seen: u32) -> anyhow::Result<(u32, Option<u16>)> {
+        let (total,): (u32,) = query_as("SELECT count(*) FROM takeback WHERE game = $1")
+            .bind(i32::from(id))
+            .fetch_one(&mut self.conn)
+            .await?;
+        let (min,): (Option<u16>,) = query_as(
+            "SELECT min(half_move) FROM
+                (SELECT half_move FROM takeback WHERE game = $1 ORDER BY rowid LIMIT -1 OFFSET $2)",
+        )
+        .bind(i32::from(id))
+        .bind(seen)
+        .fetch_one(&mut self.conn)
+        .await?;
+        Ok((total, min))
+    }
+
+    /// Mark a game as finished at time `timestamp`.
+    ///
+    /// If `outcome` is given, the players' statistics are updated accordingly, and the result is
+    /// recorded to update their ratings at the end of the rating period (see
+    /// [`update_ratings`](Self::update_ratings)). If it is [`None`], the game is abandoned with no
+    /// result. Either way, the game and its moves are kept.
+    pub async fn end_game(
+        &mut self,
+        game: &Game,
+        outcome: Option<Outcome>,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
         let mut tx = self.conn.begin().await?;
 
+        let score = outcome.as_ref().map(|outcome| game.score(outcome));
+        let termination = outcome.as_ref().map(|outcome| outcome.termination());
+        query(
+            "UPDATE game SET (result, termination, ended_at, draw_offer, takeback_request)
+                    = ($1, $2, $3, NULL, NULL)
+                WHERE id = $4",
+        )
+        .bind(score.map(|score| score.to_string()))
+        .bind(termination)
+        .bind(timestamp as i64)
+        .bind(i32::from(game.id()))
+        .execute(tx.as_mut())
+        .await?;
+
         if let Some(outcome) = outcome {
+            // Ratings are updated once the rating period is over.
+            let period = self.rating_system.period_of(timestamp);
             if let Some((winner, loser)) = outcome.winner_loser() {
-                let winner_current_elo = get_elo(&mut tx, winner).await?;
-                let loser_current_elo = get_elo(&mut tx, loser).await?;
-
-                set_elo(
-                    &mut tx,
-                    winner,
-                    rating::update(winner_current_elo, GameResult::win(loser_current_elo)),
-                )
-                .await?;
-                set_elo(
-                    &mut tx,
-                    loser,
-                    rating::update(loser_current_elo, GameResult::loss(winner_current_elo)),
-                )
-                .await?;
+                record_result(&mut tx, game.id(), (winner, loser), 1., period, timestamp).await?;
+                record_result(&mut tx, game.id(), (loser, winner), 0., period, timestamp).await?;
 
                 if winner == game.white() {
                     query("UPDATE user SET white_wins = white_wins + 1 WHERE address = $1")
@@ -173,21 +1540,8 @@ impl Db {
                 let white = game.white();
                 let black = game.black();
 
-                let white_current_elo = get_elo(&mut tx, white).await?;
-                let black_current_elo = get_elo(&mut tx, black).await?;
-
-                set_elo(
-                    &mut tx,
-                    white,
-                    rating::update(white_current_elo, GameResult::draw(black_current_elo)),
-                )
-                .await?;
-                set_elo(
-                    &mut tx,
-                    black,
-                    rating::update(black_current_elo, GameResult::draw(white_current_elo)),
-                )
-                .await?;
+                record_result(&mut tx, game.id(), (white, black), 0.5, period, timestamp).await?;
+                record_result(&mut tx, game.id(), (black, white), 0.5, period, timestamp).await?;
 
                 query("UPDATE user SET white_draws = white_draws + 1 WHERE address = $1")
                     .bind(white.to_string())
@@ -200,35 +1554,75 @@ impl Db {
             }
         }
 
-        query("DELETE FROM game WHERE id = $1")
-            .bind(i32::from(game.id()))
-            .execute(tx.as_mut())
-            .await?;
-
         tx.commit().await?;
         Ok(())
     }
 
+    /// Record the result of a game as reported by an indexer.
+    ///
+    /// Unlike [`end_game`](Self::end_game), this only marks the game as finished, without updating
+    /// statistics or ratings. Clients use it to keep their local copy of a game up to date.
+    pub async fn finish_game(
+        &mut self,
+        id: GameId,
+        result: &message::GameResult,
+    ) -> anyhow::Result<()> {
+        query(
+            "UPDATE game SET (result, termination, ended_at, draw_offer, takeback_request)
+                    = ($1, $2, $3, NULL, NULL)
+                WHERE id = $4",
+        )
+        .bind(result.score.map(|score| score.to_string()))
+        .bind(&result.termination)
+        .bind(result.timestamp as i64)
+        .bind(i32::from(id))
+        .execute(&mut self.conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Render a game as PGN.
+    pub async fn pgn(&mut self, id: GameId) -> anyhow::Result<String> {
+        let game = self.game_info(id).await?;
+        let moves: Vec<String> = self.moves(id, 0).try_collect().await?;
+        Ok(pgn::export(&game, moves))
+    }
+
+    /// Look up the players, time control and result of a game.
+    pub async fn game_info(&mut self, id: GameId) -> anyhow::Result<message::Game> {
+        let row = query_as(
+            "SELECT id, white, black, time_base, time_increment, time_days, result, termination, ended_at, created_at,
+                    variant, start_fen, stake_asset, stake_amount
+                FROM game WHERE id = $1 LIMIT 1",
+        )
+        .bind(i32::from(id))
+        .fetch_optional(&mut self.conn)
+        .await?
+        .context(format!("game {id} not found"))?;
+        game_from_row(row)
+    }
+
     pub fn games(
         &mut self,
         address: Address,
         after: Option<GameId>,
+        filter: GameFilter,
     ) -> impl '_ + Stream<Item = anyhow::Result<message::Game>> {
         let from = after.map(|id| i32::from(id) + 1).unwrap_or_default();
         query_as(
-            "SELECT id, white, black FROM game WHERE id >= $1 AND $2 IN (white, black) ORDER BY id",
+            "SELECT id, white, black, time_base, time_increment, time_days, result, termination, ended_at, created_at,
+                    variant, start_fen, stake_asset, stake_amount
+                FROM game
+                WHERE id >= $1 AND $2 IN (white, black)
+                    AND ($3 OR (ended_at IS NULL) = $4)
+                ORDER BY id",
         )
         .bind(from)
         .bind(address.to_string())
+        .bind(filter == GameFilter::All)
+        .bind(filter == GameFilter::Active)
         .fetch(&mut self.conn)
-        .map(|res| {
-            let (id, white, black): (i32, String, String) = res?;
-            Ok(message::Game {
-                id: id.into(),
-                white: white.parse()?,
-                black: black.parse()?,
-            })
-        })
+        .map(|res| game_from_row(res?))
     }
 
     pub fn moves(
@@ -246,6 +1640,93 @@ impl Db {
             })
     }
 
+    /// The moves of game `id` starting from half move `from`, each with the hash of the game state
+    /// after the move.
+    pub async fn moves_with_hashes(
+        &mut self,
+        id: GameId,
+        from: u16,
+    ) -> anyhow::Result<Vec<(String, GameHash)>> {
+        let rows: Vec<(String, Option<String>)> = query_as(
+            "SELECT san, hash FROM move WHERE game = $1 AND half_move >= $2 ORDER BY half_move",
+        )
+        .bind(i32::from(id))
+        .bind(from)
+        .fetch_all(&mut self.conn)
+        .await?;
+        if let Some(hashed) = rows
+            .iter()
+            .map(|(san, hash)| Some((san.clone(), hash.as_ref()?.parse().ok()?)))
+            .collect()
+        {
+            return Ok(hashed);
+        }
+
+        // Moves recorded before hashes were stored have to be replayed from the start of the game.
+        let info = self.game_info(id).await?;
+        let moves: Vec<String> = self.moves(id, 0).try_collect().await?;
+        let mut game = Game::with_setup(id, info.white, info.black, info.setup)?;
+        let mut hashed = vec![];
+        for san in moves {
+            let m = game.play_next_move(san.parse()?)?;
+            if m.half_move() >= from {
+                hashed.push((san, m.hash()));
+            }
+        }
+        Ok(hashed)
+    }
+
+    /// Whether our copy of game `id` diverged from the indexer it was followed through.
+    pub async fn diverged(&mut self, id: GameId) -> anyhow::Result<bool> {
+        let diverged = query_as::<_, (bool,)>("SELECT diverged FROM game WHERE id = $1 LIMIT 1")
+            .bind(i32::from(id))
+            .fetch_optional(&mut self.conn)
+            .await?;
+        Ok(diverged.is_some_and(|(diverged,)| diverged))
+    }
+
+    /// Mark game `id` as diverged from the indexer it was followed through, replacing its moves
+    /// with `moves` from a trusted source.
+    pub async fn diverge(&mut self, id: GameId, moves: &[Move]) -> anyhow::Result<()> {
+        let mut tx = self.conn.begin().await?;
+        query("DELETE FROM move WHERE game = $1")
+            .bind(i32::from(id))
+            .execute(tx.as_mut())
+            .await?;
+        for m in moves {
+            query("INSERT INTO move (game, half_move, san, hash) VALUES ($1, $2, $3, $4)")
+                .bind(i32::from(id))
+                .bind(m.half_move() as i32)
+                .bind(m.san())
+                .bind(m.hash().to_string())
+                .execute(tx.as_mut())
+                .await?;
+        }
+        query("UPDATE game SET diverged = TRUE WHERE id = $1")
+            .bind(i32::from(id))
+            .execute(tx.as_mut())
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The cursor of the last input replayed by a GraphQL indexer using this database, if any.
+    pub async fn graphql_cursor(&mut self) -> anyhow::Result<Option<String>> {
+        let cursor = query_as::<_, (String,)>("SELECT cursor FROM graphql_cursor WHERE id = 0")
+            .fetch_optional(&mut self.conn)
+            .await?;
+        Ok(cursor.map(|(cursor,)| cursor))
+    }
+
+    /// Record the cursor of the last input replayed by a GraphQL indexer using this database.
+    pub async fn set_graphql_cursor(&mut self, cursor: &str) -> anyhow::Result<()> {
+        query("INSERT OR REPLACE INTO graphql_cursor (id, cursor) VALUES (0, $1)")
+            .bind(cursor)
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
     pub async fn max_game(&mut self) -> anyhow::Result<Option<GameId>> {
         let (Some(id),): (Option<i32>,) = query_as("SELECT max(id) FROM game")
             .fetch_one(&mut self.conn)
@@ -259,9 +1740,6 @@ impl Db {
     pub async fn user_stats(&mut self, address: Address) -> anyhow::Result<UserStats> {
         let query = "
             SELECT
-                elo_value,
-                elo_deviation,
-                elo_volatility,
                 white_wins,
                 white_losses,
                 white_draws,
@@ -269,30 +1747,55 @@ impl Db {
                 black_losses,
                 black_draws
             FROM user WHERE address = $1 LIMIT 1";
-        let (
-            elo_value,
-            elo_deviation,
-            elo_volatility,
-            white_wins,
-            white_losses,
-            white_draws,
-            black_wins,
-            black_losses,
-            black_draws,
-        ): (f64, f64, f64, i32, i32, i32, i32, i32, i32) = query_as(query)
+        let (white_wins, white_losses, white_draws, black_wins, black_losses, black_draws): (
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+        ) = query_as(query)
             .bind(address.to_string())
             .fetch_optional(&mut self.conn)
             .await?
             .context(format!("unknown user {address}"))?;
+        let games =
+            white_wins + white_losses + white_draws + black_wins + black_losses + black_draws;
 
-        let elo = GlickoRating::from(Glicko2Rating {
-            value: elo_value,
-            deviation: elo_deviation,
-            volatility: elo_volatility,
-        });
+        let rows: Vec<(String, f64, f64, f64, i64, u32)> = query_as(
+            "SELECT pool, value, deviation, volatility,
+                    (SELECT period FROM rating_clock) - coalesce(period, (SELECT period FROM rating_clock)),
+                    (SELECT count(*) FROM game
+                        WHERE rating.player IN (game.white, game.black) AND game.pool = rating.pool
+                            AND game.result IS NOT NULL)
+                FROM rating WHERE player = $1 ORDER BY pool",
+        )
+        .bind(address.to_string())
+        .fetch_all(&mut self.conn)
+        .await?;
+        let ratings = rows
+            .into_iter()
+            .map(|(pool, value, deviation, volatility, inactive, games)| {
+                let rating = rating::glicko(self.rating_system.decay(
+                    Glicko2Rating {
+                        value,
+                        deviation,
+                        volatility,
+                    },
+                    inactive.max(0) as u64,
+                ));
+                Ok(message::PoolRating {
+                    pool: pool.parse()?,
+                    rating,
+                    games,
+                    provisional: rating::provisional(&rating),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
 
         Ok(UserStats {
-            elo: elo.value,
+            ratings,
+            games: games as u32,
             white_wins: white_wins as u16,
             white_losses: white_losses as u16,
             white_draws: white_draws as u16,
@@ -303,34 +1806,684 @@ impl Db {
     }
 }
 
-async fn get_elo<'c>(
+type GameRow = (
+    i32,
+    String,
+    String,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+fn game_from_row(
+    (
+        id,
+        white,
+        black,
+        base,
+        increment,
+        days,
+        score,
+        termination,
+        ended_at,
+        created_at,
+        variant,
+        start_fen,
+        stake_asset,
+        stake_amount,
+    ): GameRow,
+) -> anyhow::Result<message::Game> {
+    let result = match ended_at {
+        Some(timestamp) => Some(message::GameResult {
+            score: score.map(|score| score.parse()).transpose()?,
+            termination,
+            timestamp: timestamp as u64,
+        }),
+        None => None,
+    };
+    Ok(message::Game {
+        id: id.into(),
+        white: white.parse()?,
+        black: black.parse()?,
+        time_control: time_control_from_columns(base, increment, days),
+        result,
+        created_at: created_at.map(|timestamp| timestamp as u64),
+        setup: setup_from_columns(variant, start_fen)?,
+        stake: stake_from_columns(stake_asset, stake_amount)?,
+    })
+}
+type RatingHistoryRow = (i32, String, String, i64, f64, f64, f64, f64, f64, f64);
+
+fn rating_change_from_row(
+    (
+        game,
+        pool,
+        opponent,
+        timestamp,
+        value_before,
+        deviation_before,
+        volatility_before,
+        value_after,
+        deviation_after,
+        volatility_after,
+    ): RatingHistoryRow,
+) -> anyhow::Result<message::RatingChange> {
+    Ok(message::RatingChange {
+        game: game.into(),
+        pool: pool.parse()?,
+        opponent: opponent.parse()?,
+        timestamp: timestamp as u64,
+        before: rating::glicko(Glicko2Rating {
+            value: value_before,
+            deviation: deviation_before,
+            volatility: volatility_before,
+        }),
+        after: rating::glicko(Glicko2Rating {
+            value: value_after,
+            deviation: deviation_after,
+            volatility: volatility_after,
+        }),
+    })
+}
+
+type ClockRow = (
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+);
+
+type ChallengeRow = (
+    i32,
+    String,
+    String,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    i64,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+fn challenge_from_row(
+    (
+        id,
+        challenger,
+        opponent,
+        first_move,
+        base,
+        increment,
+        days,
+        expires_at,
+        variant,
+        start_fen,
+        stake_asset,
+        stake_amount,
+    ): ChallengeRow,
+) -> anyhow::Result<message::Challenge> {
+    Ok(message::Challenge {
+        id: id.into(),
+        challenger: challenger.parse()?,
+        opponent: opponent.parse()?,
+        first_move,
+        time_control: time_control_from_columns(base, increment, days),
+        setup: setup_from_columns(variant, start_fen)?,
+        stake: stake_from_columns(stake_asset, stake_amount)?,
+        expires_at: expires_at as u64,
+    })
+}
+
+type SeekRow = (
+    i32,
+    String,
+    Option<f64>,
+    Option<f64>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+);
+
+fn seek_from_row(
+    (id, player, min_rating, max_rating, color, base, increment, days): SeekRow,
+) -> anyhow::Result<message::Seek> {
+    Ok(message::Seek {
+        id: id.into(),
+        player: player.parse()?,
+        min_rating,
+        max_rating,
+        color: color.map(|color| color.parse()).transpose()?,
+        time_control: time_control_from_columns(base, increment, days),
+    })
+}
+
+type PuzzleRow = (
+    i32,
+    String,
+    Option<String>,
+    String,
+    i64,
+    u32,
+    u32,
+    bool,
+    Option<i64>,
+);
+
+fn puzzle_from_row(
+    (id, author, variant, fen, created_at, solvers, length, checkmate, revealed_at): PuzzleRow,
+) -> anyhow::Result<message::Puzzle> {
+    Ok(message::Puzzle {
+        id: id.into(),
+        author: author.parse()?,
+        setup: setup_from_columns(variant, Some(fen))?,
+        created_at: created_at as u64,
+        solvers,
+        length,
+        checkmate,
+        revealed_at: revealed_at.map(|timestamp| timestamp as u64),
+    })
+}
+
+type TournamentRow = (
+    i32,
+    String,
+    String,
+    String,
+    Option<u32>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    u32,
+    Option<u32>,
+    Option<i64>,
+);
+
+fn tournament_from_row(
+    (id, organizer, name, format, rounds, base, increment, days, players, round, finished_at): TournamentRow,
+) -> anyhow::Result<message::Tournament> {
+    Ok(message::Tournament {
+        id: id.into(),
+        organizer: organizer.parse()?,
+        name,
+        format: format.parse()?,
+        rounds,
+        time_control: time_control_from_columns(base, increment, days),
+        players,
+        round,
+        finished: finished_at.is_some(),
+    })
+}
+
+/// Convert a time control to the `(time_base, time_increment, time_days)` columns of `game`.
+fn time_control_columns(
+    time_control: Option<TimeControl>,
+) -> (Option<i64>, Option<i64>, Option<i64>) {
+    match time_control {
+        Some(TimeControl::Clock { base, increment }) => {
+            (Some(base as i64), Some(increment as i64), None)
+        }
+        Some(TimeControl::Correspondence { days }) => (None, None, Some(days as i64)),
+        None => (None, None, None),
+    }
+}
+
+/// Convert the `(time_base, time_increment, time_days)` columns of `game` to a time control.
+fn time_control_from_columns(
+    base: Option<i64>,
+    increment: Option<i64>,
+    days: Option<i64>,
+) -> Option<TimeControl> {
+    match (base, increment, days) {
+        (Some(base), Some(increment), _) => Some(TimeControl::Clock {
+            base: base as u64,
+            increment: increment as u64,
+        }),
+        (_, _, Some(days)) => Some(TimeControl::Correspondence { days: days as u64 }),
+        _ => None,
+    }
+}
+
+/// Convert a game setup to the `(variant, start_fen)` columns of `game`.
+fn setup_columns(setup: &GameSetup) -> (Option<String>, Option<String>) {
+    let variant = (setup.variant != Default::default()).then(|| setup.variant.to_string());
+    (variant, setup.fen.clone())
+}
+
+/// Convert the `(variant, start_fen)` columns of `game` to a game setup.
+fn setup_from_columns(
+    variant: Option<String>,
+    start_fen: Option<String>,
+) -> anyhow::Result<GameSetup> {
+    Ok(GameSetup {
+        variant: variant
+            .map(|variant| variant.parse())
+            .transpose()?
+            .unwrap_or_default(),
+        fen: start_fen,
+    })
+}
+
+/// Convert a stake to the `(stake_asset, stake_amount)` columns of `game` or `challenge`.
+fn stake_columns(stake: Option<Stake>) -> (Option<String>, Option<String>) {
+    match stake {
+        Some(stake) => (
+            Some(stake.asset.to_string()),
+            Some(stake.amount.to_string()),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Convert the `(stake_asset, stake_amount)` columns of `game` or `challenge` to a stake.
+fn stake_from_columns(
+    asset: Option<String>,
+    amount: Option<String>,
+) -> anyhow::Result<Option<Stake>> {
+    match (asset, amount) {
+        (Some(asset), Some(amount)) => Ok(Some(Stake {
+            asset: asset.parse()?,
+            amount: amount.parse()?,
+        })),
+        _ => Ok(None),
+    }
+}
+
+async fn get_balance(
+    conn: &mut SqliteConnection,
+    address: Address,
+    asset: Asset,
+) -> anyhow::Result<U256> {
+    let amount: Option<(String,)> =
+        query_as("SELECT amount FROM balance WHERE address = $1 AND asset = $2 LIMIT 1")
+            .bind(address.to_string())
+            .bind(asset.to_string())
+            .fetch_optional(conn)
+            .await?;
+    match amount {
+        Some((amount,)) => Ok(amount.parse()?),
+        None => Ok(U256::ZERO),
+    }
+}
+
+async fn update_balance<'c>(
+    tx: &mut Transaction<'c, Sqlite>,
+    address: Address,
+    asset: Asset,
+    f: impl FnOnce(U256) -> anyhow::Result<U256>,
+) -> anyhow::Result<()> {
+    let balance = f(get_balance(tx, address, asset).await?)?;
+    query("INSERT OR REPLACE INTO balance (address, asset, amount) VALUES ($1, $2, $3)")
+        .bind(address.to_string())
+        .bind(asset.to_string())
+        .bind(balance.to_string())
+        .execute(tx.as_mut())
+        .await?;
+    Ok(())
+}
+
+async fn update_clock<'c>(
+    tx: &mut Transaction<'c, Sqlite>,
+    id: GameId,
+    clock: &Clock,
+) -> anyhow::Result<()> {
+    query(
+        "UPDATE game SET (white_clock, black_clock, clock_timestamp) = ($1, $2, $3) WHERE id = $4",
+    )
+    .bind(clock.white as i64)
+    .bind(clock.black as i64)
+    .bind(clock.timestamp as i64)
+    .bind(i32::from(id))
+    .execute(tx.as_mut())
+    .await?;
+    Ok(())
+}
+
+/// The rating of `address` in `pool` as of the start of rating period `period`.
+async fn get_rating<'c>(
     tx: &mut Transaction<'c, Sqlite>,
+    system: &RatingSystem,
     address: Address,
+    pool: Pool,
+    period: u64,
 ) -> anyhow::Result<Glicko2Rating> {
-    let (value, deviation, volatility) = query_as(
-        "SELECT elo_value, elo_deviation, elo_volatility FROM user WHERE address = $1 LIMIT 1",
+    let row: Option<(f64, f64, f64, Option<i64>)> = query_as(
+        "SELECT value, deviation, volatility, period
+            FROM rating WHERE player = $1 AND pool = $2 LIMIT 1",
     )
     .bind(address.to_string())
-    .fetch_one(tx.as_mut())
+    .bind(pool.to_string())
+    .fetch_optional(tx.as_mut())
     .await?;
-    Ok(Glicko2Rating {
+    let Some((value, deviation, volatility, rating_period)) = row else {
+        return Ok(rating::unrated());
+    };
+    let rating = Glicko2Rating {
         value,
         deviation,
         volatility,
-    })
+    };
+    let inactive = rating_period.map_or(0, |rating_period| {
+        period.saturating_sub(rating_period as u64)
+    });
+    Ok(system.decay(rating, inactive))
+}
+
+/// Record the score of `player` in a game against `opponent`, to be applied to their rating once
+/// rating period `period` is over.
+async fn record_result<'c>(
+    tx: &mut Transaction<'c, Sqlite>,
+    game: GameId,
+    (player, opponent): (Address, Address),
+    score: f64,
+    period: u64,
+    timestamp: u64,
+) -> anyhow::Result<()> {
+    query(
+        "INSERT INTO rating_result (player, game, opponent, score, period, timestamp)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(player.to_string())
+    .bind(i32::from(game))
+    .bind(opponent.to_string())
+    .bind(score)
+    .bind(period as i64)
+    .bind(timestamp as i64)
+    .execute(tx.as_mut())
+    .await?;
+    Ok(())
+}
+
+/// Record the change in the rating of `player` due to a game against `opponent` in their rating
+/// history.
+async fn record_rating_change<'c>(
+    tx: &mut Transaction<'c, Sqlite>,
+    game: GameId,
+    (player, opponent): (Address, Address),
+    before: Glicko2Rating,
+    after: Glicko2Rating,
+    timestamp: u64,
+) -> anyhow::Result<()> {
+    query(
+        "INSERT INTO rating_history (player, game, opponent, timestamp,
+                value_before, deviation_before, volatility_before,
+                value_after, deviation_after, volatility_after)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+    )
+    .bind(player.to_string())
+    .bind(i32::from(game))
+    .bind(opponent.to_string())
+    .bind(timestamp as i64)
+    .bind(before.value)
+    .bind(before.deviation)
+    .bind(before.volatility)
+    .bind(after.value)
+    .bind(after.deviation)
+    .bind(after.volatility)
+    .execute(tx.as_mut())
+    .await?;
+    Ok(())
 }
 
-async fn set_elo<'c>(
+/// Set the rating of `address` in `pool` as of the start of rating period `period`.
+async fn set_rating<'c>(
     tx: &mut Transaction<'c, Sqlite>,
     address: Address,
-    elo: Glicko2Rating,
+    pool: Pool,
+    rating: Glicko2Rating,
+    period: u64,
 ) -> anyhow::Result<()> {
-    query("UPDATE user SET (elo_value, elo_deviation, elo_volatility) = ($1, $2, $3) WHERE address = $4")
-        .bind(elo.value)
-        .bind(elo.deviation)
-        .bind(elo.volatility)
-        .bind(address.to_string())
-        .execute(tx.as_mut())
-        .await?;
+    query(
+        "INSERT OR REPLACE INTO rating (player, pool, value, deviation, volatility, period)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(address.to_string())
+    .bind(pool.to_string())
+    .bind(rating.value)
+    .bind(rating.deviation)
+    .bind(rating.volatility)
+    .bind(period as i64)
+    .execute(tx.as_mut())
+    .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Score;
+
+    #[tokio::test]
+    async fn test_finished_game_kept() {
+        let mut db = Db::memory().await.unwrap();
+        let white = Address::repeat_byte(1);
+        let black = Address::repeat_byte(2);
+        let game = db
+            .new_game(white, black, GameSetup::default(), None, 0)
+            .await
+            .unwrap();
+        let outcome = Outcome::Resignation {
+            winner: white,
+            loser: black,
+        };
+        db.end_game(&game, Some(outcome), 10).await.unwrap();
+
+        assert!(db.game_over(game.id()).await.unwrap());
+        let err = db.active_game(game.id()).await.unwrap_err();
+        assert!(err.to_string().contains("already over"), "{err}");
+
+        let active: Vec<_> = db
+            .games(white, None, GameFilter::Active)
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(active.is_empty());
+        let finished: Vec<_> = db
+            .games(white, None, GameFilter::Finished)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(finished.len(), 1);
+        let result = finished[0].result.as_ref().unwrap();
+        assert_eq!(result.score, Some(Score::WhiteWins));
+        assert_eq!(result.termination.as_deref(), Some("resignation"));
+        assert_eq!(result.timestamp, 10);
+    }
+
+    #[tokio::test]
+    async fn test_new_user_stats() {
+        let mut db = Db::memory().await.unwrap();
+        let white = Address::repeat_byte(1);
+        let black = Address::repeat_byte(2);
+        db.new_game(white, black, GameSetup::default(), None, 0)
+            .await
+            .unwrap();
+
+        let stats = db.user_stats(white).await.unwrap();
+        assert_eq!(stats.games, 0);
+        assert_eq!(stats.white_wins, 0);
+        assert_eq!(stats.black_draws, 0);
+        assert!(stats.ratings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_settle_puzzle() {
+        let mut db = Db::memory().await.unwrap();
+        let author = Address::repeat_byte(1);
+        let salt = B256::repeat_byte(1);
+        let setup = GameSetup::new(
+            Variant::Standard,
+            Some("7k/6pp/8/8/8/8/5PPP/3RR1K1 w - - 0 1"),
+        )
+        .unwrap();
+        let solution = Line::play(&setup, &["Re8".into()]).unwrap();
+        let id = db
+            .new_puzzle(author, &setup, &solution.commit(author, salt).unwrap(), 0)
+            .await
+            .unwrap();
+
+        // Solvers are ranked in the order they commit, not the order they reveal.
+        let first = Address::repeat_byte(2);
+        let second = Address::repeat_byte(3);
+        let wrong = Address::repeat_byte(4);
+        for (i, solver) in [first, second, wrong].into_iter().enumerate() {
+            db.commit_solution(id, solver, B256::repeat_byte(i as u8), 10 * i as u64)
+                .await
+                .unwrap();
+        }
+        db.commit_solution(id, first, B256::ZERO, 100)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            db.solution_commitment(id, second).await.unwrap(),
+            B256::repeat_byte(1)
+        );
+        db.puzzle_solution(id).await.unwrap_err();
+
+        db.reveal_puzzle(id, &solution, 1000).await.unwrap();
+        assert_eq!(db.puzzle_solution(id).await.unwrap(), ["Re8#"]);
+        assert_eq!(db.puzzle(id).await.unwrap().revealed_at, Some(1000));
+        db.solve_puzzle(id, second).await.unwrap();
+        db.solve_puzzle(id, first).await.unwrap();
+        db.solution_commitment(id, first).await.unwrap_err();
+
+        // Nothing is settled until the reveal period is over.
+        let end = 1000 + puzzle::REVEAL_PERIOD;
+        assert!(db.settle_puzzles(end - 1).await.unwrap().is_empty());
+        let solves = db.settle_puzzles(end).await.unwrap();
+        let ranks: Vec<_> = solves
+            .iter()
+            .map(|solve| (solve.solver, solve.rank, solve.timestamp))
+            .collect();
+        assert_eq!(ranks, [(first, 1, 0), (second, 2, 10)]);
+        assert!(solves[0].value > solves[1].value);
+        assert!(db.settle_puzzles(end + 1).await.unwrap().is_empty());
+        assert_eq!(db.puzzle(id).await.unwrap().solvers, 2);
+    }
+
+    #[tokio::test]
+    async fn test_seek_already_open() {
+        let mut db = Db::memory().await.unwrap();
+        let player = Address::repeat_byte(1);
+        let other = Address::repeat_byte(2);
+        let blitz = TimeControl::Clock {
+            base: 300,
+            increment: 0,
+        };
+
+        // An older seek from another player, compatible with the new seek.
+        db.new_seek(other, None, None, None, None).await.unwrap();
+        // The player's own open seek, which is not compatible with the new seek.
+        db.new_seek(player, None, None, None, Some(blitz))
+            .await
+            .unwrap();
+
+        let seek = message::Seek {
+            id: 0.into(),
+            player,
+            min_rating: None,
+            max_rating: None,
+            color: None,
+            time_control: None,
+        };
+        let err = db.find_pairing(&seek).await.unwrap_err();
+        assert!(
+            err.to_string().contains("already has an open seek"),
+            "{err}"
+        );
+        let err = db
+            .new_seek(player, None, None, None, None)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("already has an open seek"),
+            "{err}"
+        );
+
+        // Once the old seek is cancelled, the player is paired with the oldest compatible seek.
+        let own = db.seeks(None).try_collect::<Vec<_>>().await.unwrap()[1].id;
+        db.delete_seek(own).await.unwrap();
+        let pairing = db.find_pairing(&seek).await.unwrap().unwrap();
+        assert_eq!(pairing.player, other);
+    }
+
+    #[tokio::test]
+    async fn test_rating_period() {
+        let mut db = Db::memory().await.unwrap();
+        db.set_rating_system(RatingSystem {
+            period: 100,
+            ..Default::default()
+        });
+        let pool = Pool::new(Default::default(), None);
+        let winner = Address::repeat_byte(1);
+        let loser = Address::repeat_byte(2);
+        for timestamp in [10, 20] {
+            let game = db
+                .new_game(winner, loser, GameSetup::default(), None, timestamp)
+                .await
+                .unwrap();
+            let outcome = game.timeout(Color::Black);
+            db.end_game(&game, Some(outcome), timestamp + 1)
+                .await
+                .unwrap();
+        }
+
+        // Ratings don't change until the period is over.
+        db.update_ratings(99).await.unwrap();
+        assert_eq!(db.rating(winner, pool).await.unwrap(), 1500.);
+
+        // Both games are then rated together, against the opponent's rating at the start of the
+        // period.
+        db.update_ratings(100).await.unwrap();
+        let results = [GameResult::win(rating::unrated()); 2];
+        let expected =
+            GlickoRating::from(RatingSystem::default().update(rating::unrated(), &results));
+        assert_eq!(db.rating(winner, pool).await.unwrap(), expected.value);
+        assert!(db.rating(loser, pool).await.unwrap() < 1500.);
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let mut db = Db::memory().await.unwrap();
+        let address = Address::repeat_byte(1);
+        let stake = Stake {
+            asset: Asset::Ether,
+            amount: U256::from(1),
+        };
+
+        // Operations with transactions of their own are undone along with the outer transaction.
+        db.begin().await.unwrap();
+        db.credit(address, stake).await.unwrap();
+        db.rollback().await.unwrap();
+        assert_eq!(db.balance(address, Asset::Ether).await.unwrap(), U256::ZERO);
+
+        db.begin().await.unwrap();
+        db.credit(address, stake).await.unwrap();
+        db.commit().await.unwrap();
+        assert_eq!(
+            db.balance(address, Asset::Ether).await.unwrap(),
+            stake.amount
+        );
+    }
+
+    #[tokio::test]
+    async fn test_graphql_cursor() {
+        let mut db = Db::memory().await.unwrap();
+        assert_eq!(db.graphql_cursor().await.unwrap(), None);
+        db.set_graphql_cursor("1").await.unwrap();
+        db.set_graphql_cursor("2").await.unwrap();
+        assert_eq!(db.graphql_cursor().await.unwrap().as_deref(), Some("2"));
+    }
+}
@@ -1,18 +1,21 @@
+use crate::pgn::PgnGame;
 use alloy::primitives::{keccak256, Address, FixedBytes};
 use ansi_term::Style;
-use anyhow::{ensure, Context};
+use anyhow::{anyhow, bail, ensure, Context};
 use derive_more::{AsRef, Display, From, FromStr, Into};
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 use shakmaty::{
+    fen::{Epd, Fen},
     san::{SanPlus, Suffix},
-    Chess, File, Position, Rank, Square,
+    variant::VariantPosition,
+    CastlingMode, EnPassantMode, File, Position, Rank, Square,
 };
 
 pub use shakmaty::{san::San, Color};
 
 #[derive(
-    Clone, Copy, Deserialize, Serialize, Debug, Display, From, FromStr, Into, PartialEq, Eq,
+    Clone, Copy, Deserialize, Serialize, Debug, Display, From, FromStr, Into, PartialEq, Eq, Hash,
 )]
 #[display("{_0}")]
 #[serde(transparent)]
@@ -27,32 +30,280 @@ pub struct GameId(i32);
 /// where the intended game state does not match that actual game state, so that a player cannot be
 /// tricked into making an uninteded move.
 #[derive(
-    Clone, Copy, Debug, Display, FromStr, Deserialize, Serialize, AsRef, Into, PartialEq, Eq,
+    Clone, Copy, Debug, Display, FromStr, Deserialize, Serialize, AsRef, Into, PartialEq, Eq, Hash,
 )]
 #[display("{_0}")]
 #[serde(transparent)]
 pub struct GameHash(FixedBytes<32>);
 
+/// The rules of chess a game is played under.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Display,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Variant {
+    #[default]
+    #[display("standard")]
+    Standard,
+    #[display("chess960")]
+    Chess960,
+    #[display("atomic")]
+    Atomic,
+    #[display("antichess")]
+    Antichess,
+    #[display("king-of-the-hill")]
+    KingOfTheHill,
+    #[display("three-check")]
+    ThreeCheck,
+    #[display("crazyhouse")]
+    Crazyhouse,
+    #[display("racing-kings")]
+    RacingKings,
+    #[display("horde")]
+    Horde,
+}
+
+impl Variant {
+    /// The name of the variant as used in the PGN `Variant` tag.
+    pub fn pgn_name(&self) -> &'static str {
+        match self {
+            Self::Standard => "Standard",
+            Self::Chess960 => "Chess960",
+            Self::Atomic => "Atomic",
+            Self::Antichess => "Antichess",
+            Self::KingOfTheHill => "King of the Hill",
+            Self::ThreeCheck => "Three-check",
+            Self::Crazyhouse => "Crazyhouse",
+            Self::RacingKings => "Racing Kings",
+            Self::Horde => "Horde",
+        }
+    }
+
+    fn shakmaty(&self) -> shakmaty::variant::Variant {
+        match self {
+            Self::Standard | Self::Chess960 => shakmaty::variant::Variant::Chess,
+            Self::Atomic => shakmaty::variant::Variant::Atomic,
+            Self::Antichess => shakmaty::variant::Variant::Antichess,
+            Self::KingOfTheHill => shakmaty::variant::Variant::KingOfTheHill,
+            Self::ThreeCheck => shakmaty::variant::Variant::ThreeCheck,
+            Self::Crazyhouse => shakmaty::variant::Variant::Crazyhouse,
+            Self::RacingKings => shakmaty::variant::Variant::RacingKings,
+            Self::Horde => shakmaty::variant::Variant::Horde,
+        }
+    }
+
+    fn castling_mode(&self) -> CastlingMode {
+        match self {
+            Self::Chess960 => CastlingMode::Chess960,
+            _ => CastlingMode::Standard,
+        }
+    }
+}
+
+/// Parse a variant.
+///
+/// Both our own names (`king-of-the-hill`) and PGN names (`King of the Hill`) are accepted,
+/// case-insensitively.
+impl FromStr for Variant {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let name = s
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect::<String>()
+            .to_lowercase();
+        Ok(match name.as_str() {
+            "standard" | "chess" => Self::Standard,
+            "chess960" | "fischerandom" | "fischerrandom" => Self::Chess960,
+            "atomic" => Self::Atomic,
+            "antichess" => Self::Antichess,
+            "kingofthehill" => Self::KingOfTheHill,
+            "threecheck" | "3check" => Self::ThreeCheck,
+            "crazyhouse" => Self::Crazyhouse,
+            "racingkings" => Self::RacingKings,
+            "horde" => Self::Horde,
+            _ => bail!("unknown variant {s}"),
+        })
+    }
+}
+
+/// The variant and starting position of a game.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GameSetup {
+    pub variant: Variant,
+    /// The starting position in FEN, or [`None`] for the usual starting position of the variant.
+    pub fen: Option<String>,
+}
+
+impl GameSetup {
+    /// A game of `variant`, starting from `fen` if given.
+    ///
+    /// The starting position is validated according to the rules of the variant, and the FEN is
+    /// normalized, so that the same position always has the same representation (and thus the same
+    /// initial [`GameHash`]).
+    pub fn new(variant: Variant, fen: Option<&str>) -> anyhow::Result<Self> {
+        let mut setup = Self {
+            variant,
+            fen: fen.map(String::from),
+        };
+        let position = setup.position()?;
+        setup.fen = fen.map(|_| Fen::from_position(position, EnPassantMode::Legal).to_string());
+        Ok(setup)
+    }
+
+    /// A Chess960 game, starting from the position with Scharnagl number `seed` (modulo 960).
+    pub fn chess960(seed: u16) -> Self {
+        Self {
+            variant: Variant::Chess960,
+            fen: Some(chess960_fen(seed % 960)),
+        }
+    }
+
+    /// The side to move and the move number in the starting position.
+    pub fn starting_move(&self) -> anyhow::Result<(Color, u32)> {
+        let position = self.position()?;
+        Ok((position.turn(), position.fullmoves().get()))
+    }
+
+    /// The starting position.
+    fn position(&self) -> anyhow::Result<VariantPosition> {
+        let Some(fen) = &self.fen else {
+            ensure!(
+                self.variant != Variant::Chess960,
+                "Chess960 games require a starting position"
+            );
+            return Ok(VariantPosition::new(self.variant.shakmaty()));
+        };
+        let fen: Fen = fen.parse().context("invalid FEN")?;
+        VariantPosition::from_setup(
+            self.variant.shakmaty(),
+            fen.into_setup(),
+            self.variant.castling_mode(),
+        )
+        .map_err(|err| anyhow!("invalid starting position: {err}"))
+    }
+}
+
+/// The FEN of the Chess960 starting position with Scharnagl number `n` (0-959).
+fn chess960_fen(mut n: u16) -> String {
+    let mut rank = [None; 8];
+
+    // Place the bishops on squares of opposite colors.
+    rank[(n % 4 * 2 + 1) as usize] = Some('b');
+    n /= 4;
+    rank[(n % 4 * 2) as usize] = Some('b');
+    n /= 4;
+
+    // Place the queen and the knights on the remaining squares.
+    let mut place = |piece, mut i: u16| {
+        for square in rank.iter_mut() {
+            if square.is_none() {
+                if i == 0 {
+                    *square = Some(piece);
+                    return;
+                }
+                i -= 1;
+            }
+        }
+    };
+    place('q', n % 6);
+    n /= 6;
+    const KNIGHTS: [(u16, u16); 10] = [
+        (0, 1),
+        (0, 2),
+        (0, 3),
+        (0, 4),
+        (1, 2),
+        (1, 3),
+        (1, 4),
+        (2, 3),
+        (2, 4),
+        (3, 4),
+    ];
+    let (first, second) = KNIGHTS[n as usize];
+    // Place the second knight first, so that placing the first doesn't shift its index.
+    place('n', second);
+    place('n', first);
+
+    // The king goes between the rooks on the last three squares.
+    for piece in ['r', 'k', 'r'] {
+        place(piece, 0);
+    }
+
+    let black: String = rank.iter().map(|piece| piece.unwrap()).collect();
+    let white = black.to_uppercase();
+    format!("{black}/pppppppp/8/8/8/8/PPPPPPPP/{white} w KQkq - 0 1")
+}
+
 #[derive(Clone, Debug, Display)]
 pub enum Outcome {
     #[display("{winner} defeats {loser} by checkmate")]
     Checkmate { winner: Address, loser: Address },
     #[display("{winner} wins by resignation")]
     Resignation { winner: Address, loser: Address },
+    #[display("{winner} wins on time")]
+    Timeout { winner: Address, loser: Address },
     #[display("the game ends in a draw due to stalemate")]
     Stalemate,
     #[display("the game ends in a draw due to insufficient material")]
     InsufficientMaterial,
+    #[display("{winner} defeats {loser} by the rules of the variant")]
+    VariantWin { winner: Address, loser: Address },
+    #[display("the game ends in a draw by the rules of the variant")]
+    VariantDraw,
+    #[display("the game is drawn by threefold repetition")]
+    ThreefoldRepetition,
+    #[display("the game is drawn by the fifty-move rule")]
+    FiftyMoveRule,
+    #[display("the game ends in a draw due to fivefold repetition")]
+    FivefoldRepetition,
+    #[display("the game ends in a draw due to the seventy-five-move rule")]
+    SeventyFiveMoveRule,
+    #[display("the game ends in a draw: time has run out, but the opponent cannot checkmate")]
+    TimeoutVsInsufficientMaterial,
     #[display("the game is drawn")]
     Draw,
 }
 
 impl Outcome {
+    /// A short description of how the game ended, suitable for the PGN `Termination` tag.
+    pub fn termination(&self) -> &'static str {
+        match self {
+            Self::Checkmate { .. } => "checkmate",
+            Self::Resignation { .. } => "resignation",
+            Self::Timeout { .. } => "timeout",
+            Self::Stalemate => "stalemate",
+            Self::InsufficientMaterial => "insufficient material",
+            Self::VariantWin { .. } => "variant win",
+            Self::VariantDraw => "variant draw",
+            Self::ThreefoldRepetition => "threefold repetition",
+            Self::FiftyMoveRule => "fifty-move rule",
+            Self::FivefoldRepetition => "fivefold repetition",
+            Self::SeventyFiveMoveRule => "seventy-five-move rule",
+            Self::TimeoutVsInsufficientMaterial => "timeout vs. insufficient material",
+            Self::Draw => "agreement",
+        }
+    }
+
     /// The addresses of the winning and losing players, if the outcome is decisive.
     pub fn winner_loser(&self) -> Option<(Address, Address)> {
         match self {
             Self::Checkmate { winner, loser } => Some((*winner, *loser)),
             Self::Resignation { winner, loser } => Some((*winner, *loser)),
+            Self::Timeout { winner, loser } => Some((*winner, *loser)),
+            Self::VariantWin { winner, loser } => Some((*winner, *loser)),
             _ => None,
         }
     }
@@ -66,33 +317,109 @@ impl Outcome {
     }
 }
 
+/// The number of half moves without a capture or pawn move after which either player may claim a
+/// draw.
+const FIFTY_MOVE_RULE: u32 = 100;
+
+/// The number of half moves without a capture or pawn move after which the game is automatically
+/// drawn.
+const SEVENTY_FIVE_MOVE_RULE: u32 = 150;
+
+/// The final score of a game, in PGN notation.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq)]
+pub enum Score {
+    #[display("1-0")]
+    WhiteWins,
+    #[display("0-1")]
+    BlackWins,
+    #[display("1/2-1/2")]
+    Draw,
+}
+
+impl FromStr for Score {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "1-0" => Ok(Self::WhiteWins),
+            "0-1" => Ok(Self::BlackWins),
+            "1/2-1/2" => Ok(Self::Draw),
+            _ => bail!("invalid score {s}"),
+        }
+    }
+}
+
+impl Serialize for Score {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.to_string().serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Score {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?.parse().map_err(D::Error::custom)
+    }
+}
+
 #[derive(Debug)]
 pub struct Game {
     white: Address,
     black: Address,
-    position: Chess,
+    setup: GameSetup,
+    position: VariantPosition,
     half_move: u16,
     id: GameId,
     hash: GameHash,
+    /// Positions reached since the last irreversible move, for detecting repetitions.
+    ///
+    /// Positions are keyed by EPD (FEN without move counters), which captures exactly the
+    /// information relevant to repetition: piece placement, side to move, castling rights and
+    /// legal en passant squares.
+    history: Vec<String>,
+    /// The moves played so far, for replaying the game when moves are taken back.
+    moves: Vec<San>,
 }
 
 impl Game {
-    /// Construct a new game in the starting position.
+    /// Construct a new game of standard chess in the starting position.
     pub fn new(id: GameId, white: Address, black: Address) -> Self {
-        // Construct the hash of the initial game state.
+        // The standard setup is always valid.
+        Self::with_setup(id, white, black, GameSetup::default()).unwrap()
+    }
+
+    /// Construct a new game with the given variant and starting position.
+    pub fn with_setup(
+        id: GameId,
+        white: Address,
+        black: Address,
+        setup: GameSetup,
+    ) -> anyhow::Result<Self> {
+        let position = setup.position()?;
+
+        // Construct the hash of the initial game state. Standard games from the usual starting
+        // position are identified by ID and players alone.
         let mut bytes = id.0.to_le_bytes().to_vec();
         bytes.extend(white.0);
         bytes.extend(black.0);
+        if setup != GameSetup::default() {
+            bytes.extend(setup.variant.to_string().as_bytes());
+            let fen = Fen::from_position(position.clone(), EnPassantMode::Legal);
+            bytes.extend(fen.to_string().as_bytes());
+        }
         let hash = GameHash(keccak256(bytes));
 
-        Self {
+        let history = vec![position_key(&position)];
+        Ok(Self {
             white,
             black,
-            position: Default::default(),
+            setup,
+            position,
             half_move: 0,
             id,
             hash,
-        }
+            history,
+            moves: vec![],
+        })
     }
 
     /// Construct the game state resulting from the given moves (in SAN+ notation).
@@ -100,15 +427,46 @@ impl Game {
         id: GameId,
         white: Address,
         black: Address,
+        setup: GameSetup,
         moves: impl IntoIterator<Item = San>,
     ) -> anyhow::Result<Self> {
-        let mut game = Self::new(id, white, black);
+        let mut game = Self::with_setup(id, white, black, setup)?;
         for san in moves {
             game.play_next_move(san)?;
         }
         Ok(game)
     }
 
+    /// Reconstruct a game from PGN.
+    ///
+    /// The `GameId`, `White` and `Black` tags are required, since they determine the initial game
+    /// hash, as are the `Variant` and `FEN` tags for games which are not standard chess from the
+    /// usual starting position. The moves of the main line are replayed from the starting
+    /// position, and must all be legal.
+    pub fn from_pgn(pgn: &PgnGame) -> anyhow::Result<Self> {
+        let id = pgn
+            .tag("GameId")
+            .context("missing GameId tag")?
+            .parse()
+            .context("invalid GameId tag")?;
+        let white = pgn
+            .tag("White")
+            .context("missing White tag")?
+            .parse()
+            .context("invalid White tag: must be an address")?;
+        let black = pgn
+            .tag("Black")
+            .context("missing Black tag")?
+            .parse()
+            .context("invalid Black tag: must be an address")?;
+        let variant = match pgn.tag("Variant") {
+            Some(variant) => variant.parse()?,
+            None => Variant::Standard,
+        };
+        let setup = GameSetup::new(variant, pgn.tag("FEN"))?;
+        Self::from_moves(id, white, black, setup, pgn.moves.iter().cloned())
+    }
+
     /// The ID of the game.
     pub fn id(&self) -> GameId {
         self.id
@@ -119,6 +477,11 @@ impl Game {
         self.hash
     }
 
+    /// The variant and starting position of the game.
+    pub fn setup(&self) -> &GameSetup {
+        &self.setup
+    }
+
     /// The player controlling white.
     pub fn white(&self) -> Address {
         self.white
@@ -130,22 +493,99 @@ impl Game {
     }
 
     /// The outcome of the game, if it is over.
+    ///
+    /// This includes draws by fivefold repetition and the seventy-five-move rule, which end the
+    /// game automatically, but not draws which must be claimed by a player (see
+    /// [`claimable_draw`](Self::claimable_draw)).
     pub fn outcome(&self) -> Option<Outcome> {
-        Some(match self.position.outcome()? {
-            shakmaty::Outcome::Decisive { winner } => Outcome::Checkmate {
-                winner: self.player(winner),
-                loser: self.player(!winner),
-            },
-            shakmaty::Outcome::Draw => {
-                if self.position.is_stalemate() {
-                    Outcome::Stalemate
-                } else if self.position.is_insufficient_material() {
-                    Outcome::InsufficientMaterial
-                } else {
-                    Outcome::Draw
+        if let Some(outcome) = self.position.outcome() {
+            return Some(match outcome {
+                shakmaty::Outcome::Decisive { winner } => {
+                    let winner_loser = (self.player(winner), self.player(!winner));
+                    if self.position.is_checkmate() {
+                        Outcome::Checkmate {
+                            winner: winner_loser.0,
+                            loser: winner_loser.1,
+                        }
+                    } else {
+                        Outcome::VariantWin {
+                            winner: winner_loser.0,
+                            loser: winner_loser.1,
+                        }
+                    }
+                }
+                shakmaty::Outcome::Draw => {
+                    if self.position.is_variant_end() {
+                        Outcome::VariantDraw
+                    } else if self.position.is_stalemate() {
+                        Outcome::Stalemate
+                    } else if self.position.is_insufficient_material() {
+                        Outcome::InsufficientMaterial
+                    } else {
+                        Outcome::Draw
+                    }
                 }
+            });
+        }
+
+        if self.repetitions() >= 5 {
+            Some(Outcome::FivefoldRepetition)
+        } else if self.position.halfmoves() >= SEVENTY_FIVE_MOVE_RULE {
+            Some(Outcome::SeventyFiveMoveRule)
+        } else {
+            None
+        }
+    }
+
+    /// A draw which either player may claim in the current position, if any.
+    ///
+    /// This is the case when the current position has occurred at least three times, or when
+    /// fifty moves have been made by each side without a capture or pawn move.
+    pub fn claimable_draw(&self) -> Option<Outcome> {
+        if self.repetitions() >= 3 {
+            Some(Outcome::ThreefoldRepetition)
+        } else if self.position.halfmoves() >= FIFTY_MOVE_RULE {
+            Some(Outcome::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+
+    /// The outcome of the game if `color` runs out of time.
+    ///
+    /// This is normally a win for the opponent, unless the opponent could not possibly checkmate,
+    /// in which case it is a draw.
+    pub fn timeout(&self, color: Color) -> Outcome {
+        if self.has_insufficient_material(!color) {
+            Outcome::TimeoutVsInsufficientMaterial
+        } else {
+            Outcome::Timeout {
+                winner: self.player(!color),
+                loser: self.player(color),
             }
-        })
+        }
+    }
+
+    /// The final score if the game ends with `outcome`.
+    pub fn score(&self, outcome: &Outcome) -> Score {
+        match outcome.winner_loser() {
+            Some((winner, _)) if winner == self.white => Score::WhiteWins,
+            Some(_) => Score::BlackWins,
+            None => Score::Draw,
+        }
+    }
+
+    /// Whether `color` lacks sufficient material to ever deliver checkmate.
+    pub fn has_insufficient_material(&self, color: Color) -> bool {
+        self.position.has_insufficient_material(color)
+    }
+
+    /// The number of times the current position has occurred, including the current occurrence.
+    pub fn repetitions(&self) -> usize {
+        let Some(current) = self.history.last() else {
+            return 0;
+        };
+        self.history.iter().filter(|key| *key == current).count()
     }
 
     /// A human-readable text representation of the current board state.
@@ -231,6 +671,14 @@ impl Game {
         let m = san.to_move(&self.position)?;
         self.position = std::mem::take(&mut self.position).play(&m)?;
         self.half_move += 1;
+        self.moves.push(san.clone());
+
+        // Record the new position. An irreversible move (capture or pawn move) resets the halfmove
+        // clock and means no earlier position can ever be repeated, so we can forget them.
+        if self.position.halfmoves() == 0 {
+            self.history.clear();
+        }
+        self.history.push(position_key(&self.position));
 
         // Construct the canonical notation for the move.
         let suffix = if self.position.is_checkmate() {
@@ -250,13 +698,53 @@ impl Game {
         Ok(Move {
             san: notation,
             half_move: self.half_move,
+            hash: self.hash,
         })
     }
 
+    /// The number of half moves to take back at the request of the player with `color`: their last
+    /// move, along with the opponent's reply if there is one.
+    pub fn takeback_len(&self, color: Color) -> u16 {
+        if self.turn() == color {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Take back the last `count` half moves.
+    ///
+    /// The game is replayed from the starting position without the taken back moves, so the
+    /// position, the repetition history and the chained game hash all return to what they were
+    /// before those moves were played.
+    pub fn take_back(&mut self, count: u16) -> anyhow::Result<()> {
+        ensure!(
+            count <= self.half_move,
+            "cannot take back {count} moves, only {} have been played",
+            self.half_move
+        );
+        // Only replace the game once the replay succeeds, so a failure leaves it untouched.
+        let moves = self.moves[..(self.half_move - count) as usize]
+            .iter()
+            .cloned();
+        *self = Self::from_moves(self.id, self.white, self.black, self.setup.clone(), moves)?;
+        Ok(())
+    }
+
     pub fn half_move(&self) -> u16 {
         self.half_move
     }
 
+    /// The moves played so far.
+    pub fn moves(&self) -> &[San] {
+        &self.moves
+    }
+
+    /// The current position in FEN.
+    pub fn fen(&self) -> String {
+        Fen::from_position(self.position.clone(), EnPassantMode::Legal).to_string()
+    }
+
     pub fn full_move(&self) -> u16 {
         self.half_move / 2
     }
@@ -270,6 +758,7 @@ impl Game {
 pub struct Move {
     san: SanPlus,
     half_move: u16,
+    hash: GameHash,
 }
 
 impl Move {
@@ -280,6 +769,15 @@ impl Move {
     pub fn san(&self) -> String {
         self.san.to_string()
     }
+
+    /// The hash of the game state after this move.
+    pub fn hash(&self) -> GameHash {
+        self.hash
+    }
+}
+
+fn position_key(position: &VariantPosition) -> String {
+    Epd::from_position(position.clone(), EnPassantMode::Legal).to_string()
 }
 
 fn ansi_color(color: Color) -> ansi_term::Color {
@@ -288,3 +786,152 @@ fn ansi_color(color: Color) -> ansi_term::Color {
         Color::Black => ansi_term::Color::Black,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn game() -> Game {
+        Game::new(GameId(1), Address::repeat_byte(1), Address::repeat_byte(2))
+    }
+
+    /// A game starting from `fen`.
+    fn game_from(fen: &str) -> Game {
+        let setup = GameSetup::new(Variant::Standard, Some(fen)).unwrap();
+        Game::with_setup(
+            GameId(1),
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            setup,
+        )
+        .unwrap()
+    }
+
+    fn play(game: &mut Game, moves: &str) {
+        for san in moves.split_whitespace() {
+            game.play_next_move(san.parse().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_chess960_fen() {
+        // The standard starting position is one of the Chess960 positions.
+        assert_eq!(
+            chess960_fen(518),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert!(chess960_fen(0).starts_with("bbqnnrkr/"));
+        assert!(chess960_fen(959).starts_with("rkrnnqbb/"));
+    }
+
+    #[test]
+    fn test_chess960_positions() {
+        let mut ranks = std::collections::HashSet::new();
+        for n in 0..960 {
+            let setup = GameSetup::chess960(n);
+            setup.position().unwrap();
+            let fen = setup.fen.unwrap();
+            let rank = fen.split('/').next().unwrap().to_string();
+
+            // The bishops are on squares of opposite colors, and the king is between the rooks.
+            let squares = |piece| rank.match_indices(piece).map(|(i, _)| i);
+            let bishops: Vec<_> = squares('b').collect();
+            assert_eq!(bishops.len(), 2);
+            assert_ne!(bishops[0] % 2, bishops[1] % 2);
+            let rooks: Vec<_> = squares('r').collect();
+            let king = squares('k').next().unwrap();
+            assert!(rooks[0] < king && king < rooks[1]);
+            ranks.insert(rank);
+        }
+        assert_eq!(ranks.len(), 960);
+        assert_eq!(GameSetup::chess960(960 + 518), GameSetup::chess960(518));
+    }
+
+    #[test]
+    fn test_threefold_repetition() {
+        let mut game = game();
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(game.repetitions(), 2);
+        assert!(game.claimable_draw().is_none());
+
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(game.repetitions(), 3);
+        assert!(matches!(
+            game.claimable_draw(),
+            Some(Outcome::ThreefoldRepetition)
+        ));
+        assert!(game.outcome().is_none());
+
+        // Moving on to a new position ends the repetition.
+        play(&mut game, "Nf3 Nc6");
+        assert_eq!(game.repetitions(), 1);
+        assert!(game.claimable_draw().is_none());
+    }
+
+    #[test]
+    fn test_fivefold_repetition() {
+        let mut game = game();
+        for _ in 0..3 {
+            play(&mut game, "Nf3 Nf6 Ng1 Ng8");
+        }
+        assert_eq!(game.repetitions(), 4);
+        assert!(game.outcome().is_none());
+
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(game.repetitions(), 5);
+        assert!(matches!(game.outcome(), Some(Outcome::FivefoldRepetition)));
+    }
+
+    #[test]
+    fn test_repetition_requires_same_castling_rights() {
+        let mut game = game_from("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1");
+        // The kings return to their squares, but have lost the right to castle.
+        play(&mut game, "Kf1 Kf8 Ke1 Ke8");
+        assert_eq!(game.repetitions(), 1);
+        play(&mut game, "Kf1 Kf8 Ke1 Ke8 Kf1 Kf8 Ke1 Ke8");
+        assert_eq!(game.repetitions(), 3);
+    }
+
+    #[test]
+    fn test_irreversible_move_resets_repetitions() {
+        let mut game = game();
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8 e4 e5 Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(game.repetitions(), 2);
+    }
+
+    #[test]
+    fn test_repetition_after_takeback() {
+        let mut game = game();
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8 Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(game.repetitions(), 3);
+        game.take_back(2).unwrap();
+        assert_eq!(game.repetitions(), 2);
+        assert!(game.claimable_draw().is_none());
+    }
+
+    #[test]
+    fn test_fifty_move_rule() {
+        let mut game = game_from("4k3/8/8/8/8/8/4P3/4K2R w - - 98 80");
+        play(&mut game, "Rh2");
+        assert!(game.claimable_draw().is_none());
+        play(&mut game, "Kd8");
+        assert!(matches!(
+            game.claimable_draw(),
+            Some(Outcome::FiftyMoveRule)
+        ));
+        assert!(game.outcome().is_none());
+
+        // A pawn move resets the count.
+        play(&mut game, "e4");
+        assert!(game.claimable_draw().is_none());
+    }
+
+    #[test]
+    fn test_seventy_five_move_rule() {
+        let mut game = game_from("4k3/8/8/8/8/8/4P3/4K2R w - - 148 80");
+        play(&mut game, "Rh2");
+        assert!(game.outcome().is_none());
+        play(&mut game, "Kd8");
+        assert!(matches!(game.outcome(), Some(Outcome::SeventyFiveMoveRule)));
+    }
+}
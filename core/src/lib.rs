@@ -1,6 +1,11 @@
+pub mod clock;
 pub mod db;
 pub mod game;
 pub mod message;
 pub mod notice;
-
-mod rating;
+pub mod pgn;
+pub mod puzzle;
+pub mod rating;
+pub mod tournament;
+pub mod voucher;
+pub mod wager;
@@ -1,22 +1,99 @@
-use crate::game::{GameHash, GameId};
-use alloy::primitives::Address;
-use serde::{Deserialize, Serialize};
+use crate::{
+    clock::{Clock, TimeControl},
+    game::{Color, GameHash, GameId, GameSetup, Score, Variant},
+    puzzle::Commitment,
+    rating::Pool,
+    tournament::Format,
+    wager::Stake,
+};
+use alloy::primitives::{Address, B256};
+use anyhow::bail;
+use derive_more::{Display, From, FromStr, Into};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(
+    Clone, Copy, Deserialize, Serialize, Debug, Display, From, FromStr, Into, PartialEq, Eq,
+)]
+#[display("{_0}")]
+#[serde(transparent)]
+pub struct ChallengeId(i32);
+
+#[derive(
+    Clone, Copy, Deserialize, Serialize, Debug, Display, From, FromStr, Into, PartialEq, Eq,
+)]
+#[display("{_0}")]
+#[serde(transparent)]
+pub struct SeekId(i32);
+
+#[derive(
+    Clone, Copy, Deserialize, Serialize, Debug, Display, From, FromStr, Into, PartialEq, Eq,
+)]
+#[display("{_0}")]
+#[serde(transparent)]
+pub struct PuzzleId(i32);
+
+#[derive(
+    Clone, Copy, Deserialize, Serialize, Debug, Display, From, FromStr, Into, PartialEq, Eq,
+)]
+#[display("{_0}")]
+#[serde(transparent)]
+pub struct TournamentId(i32);
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Advance {
     /// Challenge an opponent to a game.
     ///
-    /// If provided, `first_move` (in SAN notation) will be executed immediately, and the challenger
-    /// plays as white. Otherwise, the challenger plays as black, and it is up to the opponent to
-    /// make the first move (implicitly accepting the challenge).
+    /// The challenge remains pending until the opponent accepts or declines it, the challenger
+    /// cancels it, or it expires. No game exists until the challenge is accepted.
+    ///
+    /// If provided, `first_move` (in SAN notation) will be executed as soon as the challenge is
+    /// accepted, and the challenger plays the side to move in the starting position (white, unless
+    /// a custom starting position says otherwise). Otherwise, the challenger plays the other side.
+    ///
+    /// If `time_control` is given, the game is timed, and each player's clock is debited according
+    /// to the timestamps of the inputs containing their moves.
+    ///
+    /// `variant` selects the rules of the game (standard chess by default). If `fen` is given, the
+    /// game starts from that position, which must be legal under the rules of the variant.
+    /// Otherwise, Chess960 games start from the position numbered `seed` (0-959); if no seed is
+    /// given, one is derived deterministically from the challenge input.
     ///
-    /// Once created, a challenge manifests as a notice posted to the base layer listing the players
-    /// and game ID.
+    /// If `stake` is given, the challenger's stake is escrowed from their balance until the
+    /// challenge is accepted, when the opponent must match it, or returned if it is not. The winner
+    /// of the game is paid both stakes; in a draw, each player's stake is refunded.
     Challenge {
         opponent: Address,
         first_move: Option<String>,
+        time_control: Option<TimeControl>,
+        variant: Option<Variant>,
+        seed: Option<u16>,
+        fen: Option<String>,
+        stake: Option<Stake>,
     },
+    /// Accept a challenge, starting a new game.
+    AcceptChallenge { id: ChallengeId },
+    /// Decline a challenge.
+    DeclineChallenge { id: ChallengeId },
+    /// Withdraw a challenge before the opponent has responded.
+    CancelChallenge { id: ChallengeId },
+    /// Look for a game against any compatible opponent.
+    ///
+    /// If another player has an open seek compatible with this one, the two are paired into a new
+    /// game immediately. Otherwise, the seek remains open until a compatible seek arrives or the
+    /// player cancels it. Each player may have only one open seek at a time.
+    ///
+    /// Two seeks are compatible if they have the same time control, if each player's rating falls
+    /// within the range acceptable to the other, and if their color preferences do not conflict.
+    Seek {
+        min_rating: Option<f64>,
+        max_rating: Option<f64>,
+        #[serde(default, with = "serde_color")]
+        color: Option<Color>,
+        time_control: Option<TimeControl>,
+    },
+    /// Withdraw an open seek.
+    CancelSeek { id: SeekId },
     /// Make a move in an existing game.
     Move {
         id: GameId,
@@ -25,6 +102,90 @@ pub enum Advance {
     },
     /// Resign a game.
     Resign { id: GameId, hash: GameHash },
+    /// Abort a game which has barely started.
+    ///
+    /// Either player may abort a game until both players have made a move. The game ends without a
+    /// result, so it has no effect on either player's rating, and any stakes are refunded.
+    Abort { id: GameId, hash: GameHash },
+    /// Claim a draw by threefold repetition or the fifty-move rule.
+    ///
+    /// Either player may claim a draw once the position has occurred three times, or once fifty
+    /// moves have been made by each side without a capture or pawn move.
+    ClaimDraw { id: GameId, hash: GameHash },
+    /// Offer the opponent a draw.
+    ///
+    /// The offer remains pending until the opponent accepts or declines it, or until either player
+    /// makes a move.
+    OfferDraw { id: GameId, hash: GameHash },
+    /// Accept the opponent's pending draw offer, ending the game in a draw.
+    AcceptDraw { id: GameId, hash: GameHash },
+    /// Decline the opponent's pending draw offer.
+    DeclineDraw { id: GameId, hash: GameHash },
+    /// Ask the opponent for permission to take back your last move.
+    ///
+    /// If the opponent has already replied to that move, their reply is taken back as well. The
+    /// request remains pending until the opponent accepts it, or until either player makes a move.
+    RequestTakeback { id: GameId, hash: GameHash },
+    /// Accept the opponent's pending takeback request.
+    AcceptTakeback { id: GameId, hash: GameHash },
+    /// Claim a win because the opponent has run out of time.
+    ClaimTimeout { id: GameId, hash: GameHash },
+    /// Post a puzzle for other players to solve.
+    ///
+    /// The puzzle starts from `fen` (under the rules of `variant`, standard chess by default), and
+    /// is solved by playing the line of moves for both sides committed to by `solution`. Since all
+    /// inputs are publicly visible onchain, only a salted [`Commitment`] to the solution is posted
+    /// at first. The author reveals the moves later with [`RevealPuzzle`](Self::RevealPuzzle).
+    PostPuzzle {
+        variant: Option<Variant>,
+        fen: String,
+        solution: Commitment,
+    },
+    /// Commit to a solution of a puzzle, without revealing it.
+    ///
+    /// `commitment` is the [hash](crate::puzzle::commitment) of the solver's address, a secret
+    /// salt and the solution. Solvers are ranked in the order they commit, and each player can
+    /// commit once per puzzle, until the author reveals the solution.
+    CommitSolution { id: PuzzleId, commitment: B256 },
+    /// Reveal the solution of a puzzle, along with the salt of the author's commitment.
+    ///
+    /// No more solutions can be committed to afterwards, and solvers have
+    /// [`REVEAL_PERIOD`](crate::puzzle::REVEAL_PERIOD) seconds to reveal theirs. If the author never
+    /// reveals the solution, no collectibles are awarded for the puzzle.
+    RevealPuzzle {
+        id: PuzzleId,
+        salt: B256,
+        solution: Vec<String>,
+    },
+    /// Reveal a solution to a puzzle committed to earlier with
+    /// [`CommitSolution`](Self::CommitSolution).
+    ///
+    /// When the reveal period ends, the first [`COLLECTIBLES`](crate::puzzle::COLLECTIBLES)
+    /// players to commit to a correct solution are awarded collectibles, whose value depends on how
+    /// long the puzzle went unsolved.
+    SolvePuzzle {
+        id: PuzzleId,
+        salt: B256,
+        solution: Vec<String>,
+    },
+    /// Withdraw funds from the sender's balance, through a voucher.
+    Withdraw { stake: Stake },
+    /// Organize a tournament.
+    ///
+    /// Players may join until the organizer starts the tournament. Swiss tournaments last for
+    /// `rounds` rounds, or enough to determine a winner if not given; round robin tournaments last
+    /// until every player has played every other. Each round starts automatically once every game
+    /// of the previous round is over.
+    CreateTournament {
+        name: String,
+        format: Format,
+        rounds: Option<u32>,
+        time_control: Option<TimeControl>,
+    },
+    /// Register for a tournament which has not yet started.
+    JoinTournament { id: TournamentId },
+    /// Start a tournament, pairing the first round. Only the organizer may start a tournament.
+    StartTournament { id: TournamentId },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -37,14 +198,90 @@ pub enum Report {
         notation: String,
     },
 
+    /// Notification that a game was aborted.
+    ///
+    /// This is also the response to /inspect/moves for a game which has been aborted, since no more
+    /// moves will ever be played in it.
+    Aborted { id: GameId },
+
     /// Response to /inspect/games
+    ///
+    /// The request has the form `games/<address>[/<after>][/<filter>]`, where `after` is a game ID
+    /// and `filter` is one of `all` (the default), `active` or `finished`.
     Games { games: Vec<Game> },
 
     /// Response to /inspect/moves
-    Moves { moves: Vec<String> },
+    ///
+    /// The request has the form `moves/<id>/<from>[/<takebacks>]`, listing the moves from half move
+    /// `from` onwards. A client following the game passes the number of takebacks it already
+    /// knows about as `takebacks`. If any moves before `from` have since been taken back,
+    /// `taken_back_to` is the number of half moves the client should keep, and `moves` starts
+    /// right after them. Once the game is over, `over` is set and no more moves will be played.
+    Moves {
+        moves: Vec<String>,
+        /// The hash of the game state after each of `moves`, so clients can check that the moves
+        /// lead to the state they expect. Clients treat a move without a hash as unverifiable.
+        #[serde(default)]
+        hashes: Vec<GameHash>,
+        /// The total number of takebacks in the game.
+        #[serde(default)]
+        takebacks: u32,
+        #[serde(default)]
+        taken_back_to: Option<u16>,
+        #[serde(default)]
+        over: bool,
+    },
 
     /// Response to /inspect/stats
     UserStats { stats: UserStats },
+
+    /// Response to /inspect/draw-offers
+    DrawOffers { offers: Vec<DrawOffer> },
+
+    /// Response to /inspect/takeback-requests
+    TakebackRequests { requests: Vec<TakebackRequest> },
+
+    /// Response to /inspect/clock
+    Clock { clock: Option<Clock> },
+
+    /// Response to /inspect/challenges
+    Challenges { challenges: Vec<Challenge> },
+
+    /// Response to /inspect/seeks
+    Seeks { seeks: Vec<Seek> },
+
+    /// Response to /inspect/pgn
+    Pgn { pgn: String },
+
+    /// Response to /inspect/state
+    State { state: GameState },
+
+    /// Response to /inspect/puzzles
+    Puzzles { puzzles: Vec<Puzzle> },
+
+    /// Response to /inspect/puzzle-solvers
+    PuzzleSolvers { solvers: Vec<PuzzleSolve> },
+
+    /// Response to /inspect/puzzle-rankings
+    PuzzleRankings { rankings: Vec<PuzzleRanking> },
+
+    /// Response to /inspect/balances
+    Balances { balances: Vec<Stake> },
+
+    /// Response to /inspect/tournaments
+    Tournaments { tournaments: Vec<Tournament> },
+
+    /// Response to /inspect/pairings
+    Pairings { pairings: Vec<Pairing> },
+
+    /// Response to /inspect/standings
+    Standings { standings: Vec<Standing> },
+
+    /// Response to /inspect/rating-history
+    RatingHistory { history: Vec<RatingChange> },
+
+    /// Response to /inspect/leaderboard
+    Leaderboard { leaderboard: Vec<LeaderboardEntry> },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -52,11 +289,257 @@ pub struct Game {
     pub id: GameId,
     pub white: Address,
     pub black: Address,
+    pub time_control: Option<TimeControl>,
+    /// How the game ended, if it is over.
+    pub result: Option<GameResult>,
+    /// The timestamp of the input which started the game, if known.
+    pub created_at: Option<u64>,
+    /// The variant and starting position of the game.
+    #[serde(default)]
+    pub setup: GameSetup,
+    /// The amount wagered by each player, if the game is wagered.
+    pub stake: Option<Stake>,
+}
+
+/// A summary of the current state of a game.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct GameState {
+    pub id: GameId,
+    /// The hash of the current game state.
+    pub hash: GameHash,
+    /// The number of half moves played so far.
+    pub half_move: u16,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GameResult {
+    /// The final score, or [`None`] if the game was abandoned without a result.
+    pub score: Option<Score>,
+    /// How the game ended, e.g. "checkmate" or "resignation".
+    pub termination: Option<String>,
+    /// The timestamp of the input which ended the game.
+    pub timestamp: u64,
+}
+
+/// Which games to include when listing a player's games.
+#[derive(Clone, Copy, Debug, Default, Display, PartialEq, Eq)]
+pub enum GameFilter {
+    #[default]
+    #[display("all")]
+    All,
+    #[display("active")]
+    Active,
+    #[display("finished")]
+    Finished,
+}
+
+impl FromStr for GameFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "all" => Ok(Self::All),
+            "active" => Ok(Self::Active),
+            "finished" => Ok(Self::Finished),
+            _ => bail!("invalid game filter {s}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Challenge {
+    pub id: ChallengeId,
+    pub challenger: Address,
+    pub opponent: Address,
+    /// The challenger's first move, if the challenger is to play the side which moves first.
+    pub first_move: Option<String>,
+    pub time_control: Option<TimeControl>,
+    /// The variant and starting position of the challenged game.
+    #[serde(default)]
+    pub setup: GameSetup,
+    /// The amount wagered by each player, if the game is wagered.
+    pub stake: Option<Stake>,
+    /// The timestamp after which the challenge can no longer be accepted.
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Seek {
+    pub id: SeekId,
+    pub player: Address,
+    /// The lowest opponent rating the player will accept, if restricted.
+    pub min_rating: Option<f64>,
+    /// The highest opponent rating the player will accept, if restricted.
+    pub max_rating: Option<f64>,
+    /// The color the player wants to play, if they have a preference.
+    #[serde(default, with = "serde_color")]
+    pub color: Option<Color>,
+    pub time_control: Option<TimeControl>,
+}
+
+impl Seek {
+    /// Whether the player would accept an opponent with the given `rating`.
+    pub fn accepts_rating(&self, rating: f64) -> bool {
+        self.min_rating.is_none_or(|min| rating >= min)
+            && self.max_rating.is_none_or(|max| rating <= max)
+    }
+
+    /// Whether this seek, from a player rated `rating`, can be paired with `other`, from a player
+    /// rated `other_rating`.
+    pub fn compatible(&self, rating: f64, other: &Seek, other_rating: f64) -> bool {
+        self.player != other.player
+            && self.time_control == other.time_control
+            && self.accepts_rating(other_rating)
+            && other.accepts_rating(rating)
+            && (self.color.is_none() || self.color != other.color)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Puzzle {
+    pub id: PuzzleId,
+    pub author: Address,
+    /// The variant and starting position of the puzzle.
+    pub setup: GameSetup,
+    /// The timestamp of the input which posted the puzzle.
+    pub created_at: u64,
+    /// The number of players who have solved the puzzle so far.
+    pub solvers: u32,
+    /// The number of moves in the solution, for both sides.
+    pub length: u32,
+    /// Whether the solution ends in checkmate.
+    pub checkmate: bool,
+    /// The timestamp of the input in which the author revealed the solution, if they have.
+    ///
+    /// Solvers can reveal their own solutions for [`REVEAL_PERIOD`](crate::puzzle::REVEAL_PERIOD)
+    /// seconds afterwards, at which point the puzzle is settled.
+    pub revealed_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PuzzleSolve {
+    pub puzzle: PuzzleId,
+    pub solver: Address,
+    /// The order in which the puzzle was solved, starting from 1 for the first solver.
+    pub rank: u32,
+    /// The timestamp of the input which committed to the solution.
+    pub timestamp: u64,
+    /// The value of the collectible awarded, or 0 if the solver was too late for a collectible.
+    pub value: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PuzzleRanking {
+    pub solver: Address,
+    /// The number of puzzles solved.
+    pub solved: u32,
+    /// The total value of the collectibles awarded.
+    pub value: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Tournament {
+    pub id: TournamentId,
+    pub organizer: Address,
+    pub name: String,
+    pub format: Format,
+    /// The number of rounds, if requested or once the tournament has started.
+    pub rounds: Option<u32>,
+    pub time_control: Option<TimeControl>,
+    /// The number of registered players.
+    pub players: u32,
+    /// The round in progress, or [`None`] if the tournament has not started.
+    pub round: Option<u32>,
+    /// Whether every round has been played.
+    pub finished: bool,
+}
+
+/// The pairing of two players, or a bye for one player, in a round of a tournament.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Pairing {
+    pub round: u32,
+    /// The game played, or [`None`] for a bye.
+    pub game: Option<GameId>,
+    pub white: Address,
+    /// The player with black, or [`None`] if `white` has a bye.
+    pub black: Option<Address>,
+    /// The result of the game, if it is over.
+    pub result: Option<Score>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Standing {
+    pub player: Address,
+    /// The player's rating when the tournament started.
+    pub rating: f64,
+    pub score: f64,
+    pub buchholz: f64,
+    pub sonneborn_berger: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DrawOffer {
+    /// The game in which a draw is offered.
+    pub id: GameId,
+    /// The player offering the draw.
+    pub from: Address,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TakebackRequest {
+    /// The game in which a takeback is requested.
+    pub id: GameId,
+    /// The player asking to take back their last move.
+    pub from: Address,
+}
+
+/// A Glicko-2 rating, on the Glicko scale (where new players are rated 1500 ± 350).
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Rating {
+    pub value: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+/// The change in a player's rating due to a finished game.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RatingChange {
+    pub game: GameId,
+    pub pool: Pool,
+    pub opponent: Address,
+    /// The timestamp of the input which ended the game.
+    pub timestamp: u64,
+    pub before: Rating,
+    pub after: Rating,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LeaderboardEntry {
+    pub player: Address,
+    pub rating: Rating,
+    /// The number of finished games the player has played.
+    pub games: u32,
+}
+
+/// A player's rating in a rating pool.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PoolRating {
+    pub pool: Pool,
+    /// The rating, whose deviation means the player's true strength is within two deviations of
+    /// the rating value with 95% confidence.
+    pub rating: Rating,
+    /// The number of finished games the player has played in the pool.
+    pub games: u32,
+    /// Whether the rating is still too uncertain to be meaningful.
+    pub provisional: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct UserStats {
-    pub elo: f64,
+    /// The player's rating in each pool they have played in.
+    pub ratings: Vec<PoolRating>,
+    /// The number of finished games the player has played.
+    pub games: u32,
 
     pub white_wins: u16,
     pub white_losses: u16,
@@ -82,3 +565,19 @@ pub enum Status {
     Accept,
     Reject,
 }
+
+/// Serialization for optional colors as `"white"` or `"black"`.
+mod serde_color {
+    use super::*;
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(color: &Option<Color>, s: S) -> Result<S::Ok, S::Error> {
+        color.map(|color| color.to_string()).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Color>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|color| color.parse().map_err(D::Error::custom))
+            .transpose()
+    }
+}
@@ -4,6 +4,25 @@ use serde::{Deserialize, Serialize};
 sol! {
     #![sol(alloy_sol_types = alloy::sol_types)]
 
+    /// A new game has started.
+    ///
+    /// `variant` and `fen` give the setup of the game, with an empty `fen` for the usual starting
+    /// position of the variant, and `timeControl` is empty for untimed games. If the game is
+    /// wagered, each player staked `stakeAmount` of `stakeAsset`, with the zero address standing for
+    /// ether. `firstMove`, if not empty, is the move played by the challenger as the game started.
+    #[derive(Debug, Deserialize, Serialize)]
+    event GameStarted(
+        int32 id,
+        address white,
+        address black,
+        string variant,
+        string fen,
+        string timeControl,
+        address stakeAsset,
+        uint256 stakeAmount,
+        string firstMove,
+    );
+
     #[derive(Debug, Deserialize, Serialize)]
     event Victory(
         int32 id,
@@ -12,4 +31,12 @@ sol! {
         string message,
         string notation,
     );
+
+    #[derive(Debug, Deserialize, Serialize)]
+    event PuzzleSolved(
+        int32 id,
+        address solver,
+        uint32 rank,
+        uint64 value,
+    );
 }
@@ -0,0 +1,398 @@
+//! Portable Game Notation.
+
+use crate::{
+    clock::TimeControl,
+    game::{Color, San, Variant},
+    message::Game,
+};
+use anyhow::{anyhow, ensure, Context};
+use shakmaty::san::SanPlus;
+use std::fmt::Write;
+
+/// The maximum length of a line of movetext, as recommended by the PGN standard.
+const LINE_LENGTH: usize = 79;
+
+/// Render a game, along with its moves (in SAN+), as PGN.
+///
+/// The output includes the Seven Tag Roster, with dates derived from the input timestamp which
+/// started the game, followed by supplemental tags describing the game ID, time control and
+/// termination, and, for games which are not standard chess from the usual starting position, the
+/// variant and starting FEN.
+pub fn export(game: &Game, moves: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    let result = match &game.result {
+        Some(result) => result
+            .score
+            .map(|score| score.to_string())
+            .unwrap_or_else(|| "*".into()),
+        None => "*".into(),
+    };
+    let (date, time) = match game.created_at {
+        Some(timestamp) => (format_date(timestamp), format_time(timestamp)),
+        None => ("????.??.??".into(), "??:??:??".into()),
+    };
+    let time_control = match game.time_control {
+        Some(TimeControl::Clock { base, increment }) => format!("{base}+{increment}"),
+        Some(TimeControl::Correspondence { days }) => format!("1/{}", days * 24 * 60 * 60),
+        None => "-".into(),
+    };
+    let termination = match &game.result {
+        Some(result) if result.score.is_none() => "abandoned",
+        Some(result) if result.termination.as_deref() == Some("timeout") => "time forfeit",
+        Some(_) => "normal",
+        None => "unterminated",
+    };
+
+    let mut pgn = String::new();
+    for (tag, value) in [
+        ("Event", "Chesspresso game"),
+        ("Site", "Chesspresso"),
+        ("Date", &date),
+        ("Round", "-"),
+        ("White", &game.white.to_string()),
+        ("Black", &game.black.to_string()),
+        ("Result", &result),
+        ("GameId", &game.id.to_string()),
+        ("UTCDate", &date),
+        ("UTCTime", &time),
+        ("TimeControl", &time_control),
+        ("Termination", termination),
+    ] {
+        writeln!(pgn, "[{tag} \"{}\"]", escape(value)).unwrap();
+    }
+    if game.setup.variant != Variant::Standard {
+        writeln!(pgn, "[Variant \"{}\"]", game.setup.variant.pgn_name()).unwrap();
+    }
+    if let Some(fen) = &game.setup.fen {
+        writeln!(pgn, "[SetUp \"1\"]").unwrap();
+        writeln!(pgn, "[FEN \"{}\"]", escape(fen)).unwrap();
+    }
+    pgn.push('\n');
+
+    // Lay out the movetext, wrapping lines which would get too long.
+    let (mut turn, mut number) = game.setup.starting_move().unwrap_or((Color::White, 1));
+    let mut tokens = vec![];
+    for (i, san) in moves.into_iter().enumerate() {
+        match turn {
+            Color::White => tokens.push(format!("{number}.")),
+            // A game starting with black to move begins with an ellipsis.
+            Color::Black if i == 0 => tokens.push(format!("{number}...")),
+            Color::Black => {}
+        }
+        tokens.push(san.as_ref().to_string());
+        if turn == Color::Black {
+            number += 1;
+        }
+        turn = !turn;
+    }
+    tokens.push(result);
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+            writeln!(pgn, "{line}").unwrap();
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    writeln!(pgn, "{line}").unwrap();
+
+    pgn
+}
+
+/// Escape a PGN tag value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Format a Unix timestamp as a PGN date (`YYYY.MM.DD`, UTC).
+fn format_date(timestamp: u64) -> String {
+    // Convert days since the Unix epoch to a civil date, following
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+/// Format the time of day of a Unix timestamp as a PGN time (`HH:MM:SS`, UTC).
+fn format_time(timestamp: u64) -> String {
+    let seconds = timestamp % 86400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+/// A game parsed from PGN.
+#[derive(Clone, Debug, Default)]
+pub struct PgnGame {
+    /// Tag pairs, in the order they appear.
+    pub tags: Vec<(String, String)>,
+    /// The moves of the main line.
+    pub moves: Vec<San>,
+}
+
+impl PgnGame {
+    /// The value of the tag `name`, if present.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parse a PGN database, which may contain any number of games.
+///
+/// Comments, variations, numeric annotation glyphs and move suffix annotations (like `!?`) are
+/// ignored; only the tags and main line are kept. Move numbers may be separated from the following
+/// move by a space (`1. e4`) or not (`1.e4`), so the notation included in Chesspresso notices can
+/// be parsed as well.
+pub fn parse(pgn: &str) -> anyhow::Result<Vec<PgnGame>> {
+    let mut games = vec![];
+    let mut game = PgnGame::default();
+    let mut in_movetext = false;
+    let mut chars = pgn.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '[' => {
+                // A tag after movetext starts a new game.
+                if in_movetext {
+                    games.push(std::mem::take(&mut game));
+                    in_movetext = false;
+                }
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '"') {
+                    name.push(c);
+                }
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                ensure!(chars.next() == Some('"'), "malformed tag {name}");
+                let mut value = String::new();
+                loop {
+                    match chars.next().context(format!("unterminated tag {name}"))? {
+                        '\\' => value.push(chars.next().context("unterminated escape")?),
+                        '"' => break,
+                        c => value.push(c),
+                    }
+                }
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                ensure!(chars.next() == Some(']'), "malformed tag {name}");
+                game.tags.push((name, value));
+            }
+            '{' => {
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' => {
+                // Skip variations, which may be nested.
+                let mut depth = 1;
+                for c in chars.by_ref() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                ensure!(depth == 0, "unterminated variation");
+            }
+            c => {
+                in_movetext = true;
+                let mut token = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"{}();[".contains(*c))
+                {
+                    token.push(c);
+                }
+
+                if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+                    // A game termination marker ends the current game.
+                    games.push(std::mem::take(&mut game));
+                    in_movetext = false;
+                    continue;
+                }
+                if token.starts_with('$') {
+                    continue;
+                }
+
+                // Strip move numbers, which may be attached to the move itself (`1.e4`). SAN never
+                // contains a `.`, so anything up to the last `.` is a move number.
+                let token = token.rsplit('.').next().unwrap_or_default();
+                let token = token.trim_end_matches(['!', '?']);
+                // Some tools write castling with zeros.
+                let token = token.replace('0', "O");
+                if token.is_empty() {
+                    continue;
+                }
+                let san: SanPlus = token
+                    .parse()
+                    .map_err(|err| anyhow!("invalid move {token}: {err}"))?;
+                game.moves.push(san.san);
+            }
+        }
+    }
+
+    if in_movetext || !game.tags.is_empty() {
+        games.push(game);
+    }
+    Ok(games)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        game::{self, GameSetup, Score},
+        message::GameResult,
+    };
+    use alloy::primitives::Address;
+
+    fn summary(setup: GameSetup, result: Option<GameResult>) -> Game {
+        Game {
+            id: 7.into(),
+            white: Address::repeat_byte(1),
+            black: Address::repeat_byte(2),
+            time_control: Some(TimeControl::Clock {
+                base: 180,
+                increment: 2,
+            }),
+            result,
+            created_at: Some(1_700_000_000),
+            setup,
+            stake: None,
+        }
+    }
+
+    /// Export `moves`, parse the result and replay it.
+    fn round_trip(summary: &Game, moves: &str) -> (PgnGame, game::Game) {
+        let pgn = export(summary, moves.split_whitespace());
+        let mut games = parse(&pgn).unwrap();
+        assert_eq!(games.len(), 1);
+        let parsed = games.pop().unwrap();
+        let replayed = game::Game::from_pgn(&parsed).unwrap();
+        (parsed, replayed)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let result = GameResult {
+            score: Some(Score::WhiteWins),
+            termination: Some("checkmate".into()),
+            timestamp: 1_700_000_100,
+        };
+        let summary = summary(GameSetup::default(), Some(result));
+        let moves = "e4 e5 Bc4 Nc6 Qh5 Nf6 Qxf7#";
+        let (parsed, replayed) = round_trip(&summary, moves);
+
+        assert_eq!(
+            parsed.tag("White"),
+            Some(summary.white.to_string().as_str())
+        );
+        assert_eq!(
+            parsed.tag("Black"),
+            Some(summary.black.to_string().as_str())
+        );
+        assert_eq!(parsed.tag("GameId"), Some("7"));
+        assert_eq!(parsed.tag("Result"), Some("1-0"));
+        assert_eq!(parsed.tag("Date"), Some("2023.11.14"));
+        assert_eq!(parsed.tag("UTCTime"), Some("22:13:20"));
+        assert_eq!(parsed.tag("TimeControl"), Some("180+2"));
+        assert_eq!(parsed.tag("Termination"), Some("normal"));
+        assert_eq!(parsed.tag("Variant"), None);
+        assert_eq!(parsed.tag("FEN"), None);
+
+        let sans: Vec<San> = moves
+            .split_whitespace()
+            .map(|san| san.parse::<SanPlus>().unwrap().san)
+            .collect();
+        let expected = game::Game::from_moves(
+            summary.id,
+            summary.white,
+            summary.black,
+            GameSetup::default(),
+            sans.clone(),
+        )
+        .unwrap();
+        assert_eq!(parsed.moves, sans);
+        assert_eq!(replayed.hash(), expected.hash());
+        assert!(replayed.outcome().is_some());
+    }
+
+    #[test]
+    fn test_round_trip_setup() {
+        // A Chess960 game starting with black to move.
+        let setup = GameSetup::new(
+            Variant::Chess960,
+            Some("bqnbrknr/pppppppp/8/8/4P3/8/PPPP1PPP/BQNBRKNR b KQkq - 0 1"),
+        )
+        .unwrap();
+        let summary = summary(setup.clone(), None);
+        let pgn = export(&summary, ["e5", "Nf3"]);
+        assert!(pgn.contains("\n1... e5 2. Nf3 *\n"));
+
+        let (parsed, replayed) = round_trip(&summary, "e5 Nf3");
+        assert_eq!(parsed.tag("Variant"), Some("Chess960"));
+        assert_eq!(parsed.tag("Result"), Some("*"));
+        assert_eq!(parsed.tag("Termination"), Some("unterminated"));
+        assert_eq!(replayed.setup(), &setup);
+        assert_eq!(replayed.half_move(), 2);
+    }
+
+    #[test]
+    fn test_line_wrapping() {
+        let summary = summary(GameSetup::default(), None);
+        let moves = "Nf3 Nf6 Ng1 Ng8 ".repeat(20);
+        let pgn = export(&summary, moves.split_whitespace());
+        let movetext = pgn.split("\n\n").nth(1).unwrap();
+        assert!(movetext.lines().count() > 1);
+        assert!(movetext.lines().all(|line| line.len() <= LINE_LENGTH));
+
+        let (parsed, _) = round_trip(&summary, &moves);
+        assert_eq!(parsed.moves.len(), 80);
+    }
+
+    #[test]
+    fn test_parse_annotations() {
+        let pgn = r#"[Event "Test"]
+[White "A \"quoted\" name"]
+
+1.e4 {best by test} e5 (1...c5 2.Nf3 (2.c3) d6) 2.Nf3 $1 Nc6!? ; a comment
+3.0-0 1/2-1/2
+
+[Event "Second"]
+
+1. d4 *"#;
+        let games = parse(pgn).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].tag("White"), Some(r#"A "quoted" name"#));
+        let moves: Vec<_> = games[0].moves.iter().map(San::to_string).collect();
+        assert_eq!(moves, ["e4", "e5", "Nf3", "Nc6", "O-O"]);
+        assert_eq!(games[1].tag("Event"), Some("Second"));
+        assert_eq!(games[1].moves.len(), 1);
+    }
+}
@@ -0,0 +1,257 @@
+//! Puzzles and the collectibles awarded to their first solvers.
+
+use crate::game::{Game, GameSetup};
+use alloy::primitives::{keccak256, Address, B256};
+use anyhow::{ensure, Context};
+use serde::{Deserialize, Serialize};
+
+/// The number of solvers of each puzzle who receive a collectible.
+pub const COLLECTIBLES: u32 = 10;
+
+/// The number of seconds in an hour.
+const HOUR: u64 = 60 * 60;
+
+/// The base value of a collectible.
+const BASE_VALUE: u64 = 100;
+
+/// The value of the collectible awarded to the `rank`th solver of a puzzle (starting from 1), who
+/// solved it `elapsed` seconds after it was posted.
+///
+/// The value grows with the logarithm of the time the puzzle has been available, doubling for a
+/// puzzle which goes unsolved for an hour, tripling after three hours, and so on, so that puzzles
+/// which stump players for longer are worth more. Collectibles are scarce: the value falls off
+/// linearly with rank, and solvers after the first [`COLLECTIBLES`] receive nothing.
+pub fn collectible_value(elapsed: u64, rank: u32) -> u64 {
+    if rank == 0 || rank > COLLECTIBLES {
+        return 0;
+    }
+    let hours = elapsed / HOUR + 1;
+    let time_factor = (u64::BITS - hours.leading_zeros()) as u64;
+    BASE_VALUE * time_factor * (COLLECTIBLES - rank + 1) as u64 / COLLECTIBLES as u64
+}
+
+/// The number of seconds after the author reveals the solution of a puzzle during which solvers
+/// can reveal theirs.
+pub const REVEAL_PERIOD: u64 = 24 * HOUR;
+
+/// The hash `player` commits to before revealing the line `moves` (in canonical SAN+).
+///
+/// Inputs are public, so solutions are never posted in the clear until they can no longer be
+/// copied. The hash covers the player's address, so that a commitment cannot be replayed by
+/// another player, and a secret `salt`, so that short lines cannot be recovered by trying every
+/// candidate.
+pub fn commitment(player: Address, salt: B256, moves: &[String]) -> B256 {
+    let mut bytes = player.to_vec();
+    bytes.extend(salt);
+    bytes.extend(moves.join(" ").as_bytes());
+    keccak256(bytes)
+}
+
+/// The author's commitment to the solution of a puzzle.
+///
+/// The length of the solution and whether it ends in checkmate are public, so solvers know what
+/// they are looking for, but the moves are hidden behind a salted [`commitment`] until the author
+/// reveals them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Commitment {
+    /// The hash of the solution, as computed by [`commitment`].
+    pub hash: B256,
+    /// The number of moves in the solution, for both sides.
+    pub length: u32,
+    /// Whether the solution ends in checkmate.
+    pub checkmate: bool,
+}
+
+impl Commitment {
+    /// Check that a solution with this commitment can be solved by the player to move.
+    ///
+    /// The solver plays the first move, so the solution must end with one of their moves.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            !self.length.is_multiple_of(2),
+            "solution must end with a move by the solver"
+        );
+        Ok(())
+    }
+
+    /// Check that `line`, revealed by `author` with `salt`, is the solution committed to.
+    pub fn verify(&self, author: Address, salt: B256, line: &Line) -> anyhow::Result<()> {
+        self.validate()?;
+        ensure!(
+            line.commitment(author, salt) == self.hash,
+            "solution does not match commitment"
+        );
+        ensure!(
+            line.moves.len() == self.length as usize && line.checkmate == self.checkmate,
+            "solution does not match its published length or checkmate"
+        );
+        Ok(())
+    }
+}
+
+/// A line of play from the starting position of a puzzle.
+#[derive(Clone, Debug)]
+pub struct Line {
+    /// The moves of the line, in canonical SAN+.
+    pub moves: Vec<String>,
+    /// The position (in FEN) after each move.
+    positions: Vec<String>,
+    /// Whether the line ends in checkmate.
+    pub checkmate: bool,
+}
+
+impl Line {
+    /// Play out `moves` (in SAN) from the starting position `setup`.
+    ///
+    /// Fails if any of the moves is illegal or the line is empty.
+    pub fn play(setup: &GameSetup, moves: &[String]) -> anyhow::Result<Self> {
+        ensure!(!moves.is_empty(), "line must contain at least one move");
+
+        let mut game = Game::with_setup(0.into(), Address::ZERO, Address::ZERO, setup.clone())?;
+        ensure!(
+            game.outcome().is_none(),
+            "the game is already over in the starting position"
+        );
+        let mut line = Self {
+            moves: vec![],
+            positions: vec![],
+            checkmate: false,
+        };
+        for san in moves {
+            ensure!(game.outcome().is_none(), "the game is over before {san}");
+            let m = game.play_next_move(san.parse().context(format!("invalid move {san}"))?)?;
+            line.moves.push(m.san());
+            line.positions.push(game.fen());
+        }
+        line.checkmate = line.moves.last().is_some_and(|san| san.ends_with('#'));
+        Ok(line)
+    }
+
+    /// The hash `player` commits to before revealing this line with `salt`.
+    pub fn commitment(&self, player: Address, salt: B256) -> B256 {
+        commitment(player, salt, &self.moves)
+    }
+
+    /// Commit to this line as the solution of a puzzle posted by `author`.
+    pub fn commit(&self, author: Address, salt: B256) -> anyhow::Result<Commitment> {
+        let commitment = Commitment {
+            hash: self.commitment(author, salt),
+            length: self.moves.len().try_into()?,
+            checkmate: self.checkmate,
+        };
+        commitment.validate()?;
+        Ok(commitment)
+    }
+
+    /// Whether this line solves a puzzle whose revealed solution is `solution`.
+    ///
+    /// A line solves the puzzle if it reaches the same positions as the solution, move for move.
+    /// When the solution ends in checkmate, the solver may instead finish with a different mate on
+    /// the same move, since mating puzzles often have more than one solution. Every other move,
+    /// including all of the defender's replies, must follow the solution.
+    pub fn solves(&self, solution: &Line) -> bool {
+        let n = solution.positions.len();
+        if self.positions.len() != n || n.is_multiple_of(2) {
+            return false;
+        }
+        self.positions == solution.positions
+            || (solution.checkmate
+                && self.checkmate
+                && self.positions[..n - 1] == solution.positions[..n - 1])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Variant;
+
+    /// A ladder mate in two, where the defender has two replies.
+    const LADDER: &str = "4k3/8/8/8/8/8/1R6/R3K3 w - - 0 1";
+    /// A back rank mate in one, which either rook can deliver.
+    const BACK_RANK: &str = "7k/6pp/8/8/8/8/5PPP/3RR1K1 w - - 0 1";
+
+    fn line(fen: &str, moves: &str) -> Line {
+        let setup = GameSetup::new(Variant::Standard, Some(fen)).unwrap();
+        let moves: Vec<_> = moves.split_whitespace().map(String::from).collect();
+        Line::play(&setup, &moves).unwrap()
+    }
+
+    const AUTHOR: Address = Address::repeat_byte(1);
+    const SALT: B256 = B256::repeat_byte(2);
+
+    #[test]
+    fn test_solution() {
+        let solution = line(LADDER, "Rb7 Kf8 Ra8");
+        let commitment = solution.commit(AUTHOR, SALT).unwrap();
+        assert!(commitment.checkmate);
+        assert_eq!(commitment.length, 3);
+        commitment.verify(AUTHOR, SALT, &solution).unwrap();
+        assert!(line(LADDER, "Rb7 Kf8 Ra8#").solves(&solution));
+    }
+
+    #[test]
+    fn test_commitment() {
+        let solution = line(LADDER, "Rb7 Kf8 Ra8");
+        let commitment = solution.commit(AUTHOR, SALT).unwrap();
+
+        // Moves are committed to in canonical form.
+        let annotated = line(LADDER, "Rb7 Kf8 Ra8#");
+        commitment.verify(AUTHOR, SALT, &annotated).unwrap();
+
+        // The commitment is bound to the author and the salt.
+        commitment
+            .verify(Address::repeat_byte(3), SALT, &solution)
+            .unwrap_err();
+        commitment
+            .verify(AUTHOR, B256::repeat_byte(3), &solution)
+            .unwrap_err();
+        commitment
+            .verify(AUTHOR, SALT, &line(LADDER, "Rb7 Kd8 Ra8"))
+            .unwrap_err();
+
+        // The published length and checkmate flag must match the solution.
+        let wrong_length = Commitment {
+            length: 5,
+            ..commitment
+        };
+        wrong_length.verify(AUTHOR, SALT, &solution).unwrap_err();
+        let wrong_checkmate = Commitment {
+            checkmate: false,
+            ..commitment
+        };
+        wrong_checkmate.verify(AUTHOR, SALT, &solution).unwrap_err();
+    }
+
+    #[test]
+    fn test_alternative_mate() {
+        let solution = line(BACK_RANK, "Re8");
+        assert!(line(BACK_RANK, "Rd8").solves(&solution));
+        assert!(!line(BACK_RANK, "Re7").solves(&solution));
+    }
+
+    #[test]
+    fn test_defender_must_follow_solution() {
+        // Both lines mate on the same move, but the solver does not get to choose the defender's
+        // reply.
+        let solution = line(LADDER, "Rb7 Kf8 Ra8");
+        assert!(!line(LADDER, "Rb7 Kd8 Ra8").solves(&solution));
+    }
+
+    #[test]
+    fn test_wrong_length() {
+        let solution = line(LADDER, "Rb7 Kf8 Ra8");
+        assert!(!line(LADDER, "Rb7").solves(&solution));
+        assert!(!line(LADDER, "Rb7 Kf8 Ra2 Kg8 Ra8").solves(&solution));
+    }
+
+    #[test]
+    fn test_solver_must_move_last() {
+        let solution = line(LADDER, "Rb7 Kf8");
+        solution.commit(AUTHOR, SALT).unwrap_err();
+        let mut commitment = line(LADDER, "Rb7 Kf8 Ra8").commit(AUTHOR, SALT).unwrap();
+        commitment.length = 2;
+        commitment.validate().unwrap_err();
+        assert!(!line(LADDER, "Rb7 Kf8").solves(&solution));
+    }
+}
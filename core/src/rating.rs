@@ -1,16 +1,275 @@
-use glicko2::{GameResult, Glicko2Rating};
+//! The Glicko-2 rating system.
 
-/// The system constant instantiating the Glicko2 rating system.
+use crate::{clock::TimeControl, game::Variant, message::Rating};
+use anyhow::{bail, Context};
+use derive_more::Display;
+use glicko2::{GameResult, Glicko2Rating, GlickoRating};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
+
+/// The default length of a rating period, in seconds.
+pub const DEFAULT_PERIOD: u64 = 24 * 60 * 60;
+
+/// The default system constant.
+pub const DEFAULT_SYSTEM_CONSTANT: f64 = 0.8;
+
+/// The rating deviation, on the Glicko scale, above which a rating is considered provisional.
+const PROVISIONAL_DEVIATION: f64 = 110.;
+
+/// How fast a game is played, for the purpose of rating.
+#[derive(Clone, Copy, Debug, Default, Display, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Speed {
+    #[display("bullet")]
+    Bullet,
+    #[display("blitz")]
+    Blitz,
+    #[display("rapid")]
+    Rapid,
+    #[display("classical")]
+    Classical,
+    /// Correspondence games, as well as untimed games.
+    #[default]
+    #[display("correspondence")]
+    Correspondence,
+}
+
+impl Speed {
+    /// The speed of games played with a given time control.
+    ///
+    /// Real-time games are classified by their estimated duration, assuming a game of 40 moves: the
+    /// base time plus 40 increments. Bullet games are estimated to last under 3 minutes, blitz
+    /// games under 8 minutes, and rapid games under 25 minutes.
+    pub fn of(time_control: Option<TimeControl>) -> Self {
+        match time_control {
+            Some(TimeControl::Clock { base, increment }) => {
+                match base.saturating_add(increment.saturating_mul(40)) {
+                    ..180 => Self::Bullet,
+                    180..480 => Self::Blitz,
+                    480..1500 => Self::Rapid,
+                    _ => Self::Classical,
+                }
+            }
+            Some(TimeControl::Correspondence { .. }) | None => Self::Correspondence,
+        }
+    }
+}
+
+impl FromStr for Speed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "bullet" => Ok(Self::Bullet),
+            "blitz" => Ok(Self::Blitz),
+            "rapid" => Ok(Self::Rapid),
+            "classical" => Ok(Self::Classical),
+            "correspondence" => Ok(Self::Correspondence),
+            _ => bail!("invalid speed {s}"),
+        }
+    }
+}
+
+/// A pool of games rated separately from all others.
+///
+/// Players have a separate rating for each combination of variant and speed, since strength in one
+/// need not carry over to another. Pools are written as `<variant>-<speed>`, e.g. `standard-blitz`.
+#[derive(Clone, Copy, Debug, Default, Display, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[display("{variant}-{speed}")]
+pub struct Pool {
+    pub variant: Variant,
+    pub speed: Speed,
+}
+
+impl Pool {
+    /// The pool for games of `variant` played with a given time control.
+    pub fn new(variant: Variant, time_control: Option<TimeControl>) -> Self {
+        Self {
+            variant,
+            speed: Speed::of(time_control),
+        }
+    }
+}
+
+impl FromStr for Pool {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (variant, speed) = s
+            .rsplit_once('-')
+            .context("rating pool must be of the form <variant>-<speed>")?;
+        Ok(Self {
+            variant: variant.parse()?,
+            speed: speed.parse()?,
+        })
+    }
+}
+
+impl Serialize for Pool {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.to_string().serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pool {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parameters instantiating the Glicko-2 rating system.
 ///
-/// Glicko2 is parameterized by a constant which controls how significantly ratings change with each
-/// result. Recommended parameters are in the range 0.3 to 1.2, with lower values causing less
-/// volatility.
-const SYSTEM_CONSTANT: f64 = 0.8;
+/// Glicko-2 rates players in batches: the results of all games finished during a rating period are
+/// applied together once the period is over, each rated against the opponent's rating as of the
+/// start of the period. Players who sit out a period become less certain of their rating, so their
+/// deviation grows. Periods are numbered by dividing input timestamps by the period length, so the
+/// parameters must not change once a dApp is deployed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RatingSystem {
+    /// The length of a rating period, in seconds.
+    pub period: u64,
+    /// The system constant, τ, which controls how significantly volatility changes with each
+    /// result. Recommended parameters are in the range 0.3 to 1.2, with lower values causing less
+    /// volatility.
+    pub system_constant: f64,
+}
+
+impl Default for RatingSystem {
+    fn default() -> Self {
+        Self {
+            period: DEFAULT_PERIOD,
+            system_constant: DEFAULT_SYSTEM_CONSTANT,
+        }
+    }
+}
+
+impl RatingSystem {
+    /// The rating period containing `timestamp`.
+    pub fn period_of(&self, timestamp: u64) -> u64 {
+        timestamp / self.period.max(1)
+    }
+
+    /// Apply the results of a rating period to a player's rating as of the start of the period.
+    pub fn update(&self, rating: Glicko2Rating, results: &[GameResult]) -> Glicko2Rating {
+        glicko2::new_rating(rating, results, self.system_constant)
+    }
 
-pub fn update(rating: Glicko2Rating, result: GameResult) -> Glicko2Rating {
-    glicko2::new_rating(rating, &[result], SYSTEM_CONSTANT)
+    /// A player's rating after sitting out `periods` rating periods.
+    ///
+    /// Each period without games increases the deviation from φ to √(φ² + σ²), up to the deviation
+    /// of an unrated player.
+    pub fn decay(&self, rating: Glicko2Rating, periods: u64) -> Glicko2Rating {
+        let max = unrated().deviation;
+        let variance =
+            rating.deviation * rating.deviation + periods as f64 * rating.volatility.powi(2);
+        Glicko2Rating {
+            deviation: variance.sqrt().min(max.max(rating.deviation)),
+            ..rating
+        }
+    }
 }
 
 pub fn unrated() -> Glicko2Rating {
     Glicko2Rating::unrated()
 }
+
+/// Convert a rating from the internal Glicko-2 scale to the familiar Glicko scale.
+pub fn glicko(rating: Glicko2Rating) -> Rating {
+    let GlickoRating { value, deviation } = rating.into();
+    Rating {
+        value,
+        deviation,
+        volatility: rating.volatility,
+    }
+}
+
+/// Whether a rating (on the Glicko scale) is still too uncertain to be meaningful.
+pub fn provisional(rating: &Rating) -> bool {
+    rating.deviation > PROVISIONAL_DEVIATION
+}
+
+/// Convert a rating deviation from the Glicko scale to the internal Glicko-2 scale.
+pub fn glicko2_deviation(deviation: f64) -> f64 {
+    Glicko2Rating::from(GlickoRating {
+        value: 1500.,
+        deviation,
+    })
+    .deviation
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rating(value: f64, deviation: f64) -> Glicko2Rating {
+        GlickoRating { value, deviation }.into()
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    /// The worked example from Glickman's "Example of the Glicko-2 system".
+    #[test]
+    fn test_update() {
+        let system = RatingSystem {
+            system_constant: 0.5,
+            ..Default::default()
+        };
+        let results = [
+            GameResult::win(rating(1400., 30.)),
+            GameResult::loss(rating(1550., 100.)),
+            GameResult::loss(rating(1700., 300.)),
+        ];
+        let updated = glicko(system.update(rating(1500., 200.), &results));
+        assert_close(updated.value, 1464.06, 0.01);
+        assert_close(updated.deviation, 151.52, 0.01);
+        assert_close(updated.volatility, 0.05999, 0.00001);
+    }
+
+    #[test]
+    fn test_decay() {
+        let system = RatingSystem::default();
+        let initial = rating(1500., 200.);
+        assert_eq!(system.decay(initial, 0).deviation, initial.deviation);
+
+        // φ' = √(φ² + σ²), as for a player who does not compete in a rating period.
+        let decayed = system.decay(initial, 1);
+        assert_close(
+            decayed.deviation,
+            (initial.deviation.powi(2) + 0.06f64.powi(2)).sqrt(),
+            1e-9,
+        );
+        assert_eq!(decayed.value, initial.value);
+        assert_eq!(decayed.volatility, initial.volatility);
+        assert_close(glicko(decayed).deviation, 200.27, 0.01);
+
+        // Decaying one period at a time is the same as decaying several at once.
+        let stepwise = (0..10).fold(initial, |rating, _| system.decay(rating, 1));
+        assert_close(
+            stepwise.deviation,
+            system.decay(initial, 10).deviation,
+            1e-9,
+        );
+
+        // The deviation never grows beyond that of an unrated player.
+        let decayed = glicko(system.decay(initial, 1_000_000));
+        assert_close(decayed.deviation, 350., 1e-9);
+        assert!(provisional(&decayed));
+    }
+
+    #[test]
+    fn test_period_of() {
+        let system = RatingSystem {
+            period: 100,
+            ..Default::default()
+        };
+        assert_eq!(system.period_of(0), 0);
+        assert_eq!(system.period_of(99), 0);
+        assert_eq!(system.period_of(100), 1);
+    }
+}
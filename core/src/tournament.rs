@@ -0,0 +1,501 @@
+//! Tournament pairings and standings.
+
+use crate::{
+    game::Score,
+    message::{Pairing, Standing},
+};
+use alloy::primitives::Address;
+use anyhow::bail;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+
+/// The maximum number of players in a tournament.
+pub const MAX_PLAYERS: u32 = 256;
+
+/// The maximum number of rounds in a Swiss tournament.
+pub const MAX_SWISS_ROUNDS: u32 = 20;
+
+/// The maximum number of candidate pairs to try when searching for Swiss pairings without
+/// rematches, so that pairing a round takes bounded time however the players' histories are
+/// arranged.
+const PAIRING_BUDGET: usize = 10_000;
+
+/// How players are paired in a tournament.
+#[derive(Clone, Copy, Debug, Display, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// Every player plays every other player once.
+    #[display("round-robin")]
+    RoundRobin,
+    /// Each round, players are paired against opponents with similar scores whom they have not
+    /// played yet.
+    #[display("swiss")]
+    Swiss,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "round-robin" => Ok(Self::RoundRobin),
+            "swiss" => Ok(Self::Swiss),
+            _ => bail!("invalid tournament format {s}"),
+        }
+    }
+}
+
+impl Format {
+    /// The default number of rounds for a tournament of `players` players.
+    ///
+    /// A round robin needs one round for each possible opponent (plus one for byes, if the number
+    /// of players is odd). A Swiss tournament defaults to enough rounds to determine a single
+    /// winner, ⌈log₂(players)⌉.
+    pub fn rounds(&self, players: usize) -> u32 {
+        match self {
+            Self::RoundRobin => (players + players % 2).saturating_sub(1) as u32,
+            Self::Swiss => (players.max(2) - 1).ilog2() + 1,
+        }
+    }
+}
+
+/// A pairing of two players for one round, with white first, or a bye (`None`) for one player.
+pub type Pair = (Address, Option<Address>);
+
+/// Pair the players of a round robin tournament for round `round` (starting from 1).
+///
+/// Players should be given in seeding order. Pairings follow the circle method: the first seat
+/// stays fixed while the others rotate one place each round. With an even number of players, the
+/// first seed takes the fixed seat and alternates colors each round. With an odd number, the fixed
+/// seat is a bye instead. Every other player has white in odd-numbered seats, so their colors
+/// alternate too except when they pass the fixed seat. Over the whole tournament, every player has
+/// white and black equally often, give or take one game.
+pub fn round_robin_pairings(players: &[Address], round: u32) -> Vec<Pair> {
+    let mut seats: Vec<Option<Address>> = players.iter().copied().map(Some).collect();
+    if seats.len() % 2 == 1 {
+        seats.insert(0, None);
+    }
+    let n = seats.len();
+    if n < 2 {
+        return vec![];
+    }
+    seats[1..].rotate_right((round as usize - 1) % (n - 1));
+
+    (0..n / 2)
+        .filter_map(|i| {
+            let (a, b) = (seats[i], seats[n - 1 - i]);
+            let a_white = if i == 0 {
+                !round.is_multiple_of(2)
+            } else {
+                !i.is_multiple_of(2)
+            };
+            let (white, black) = if a_white { (a, b) } else { (b, a) };
+            match (white, black) {
+                (Some(white), black) => Some((white, black)),
+                (None, Some(black)) => Some((black, None)),
+                (None, None) => None,
+            }
+        })
+        .collect()
+}
+
+/// A player's progress through a tournament, as relevant to Swiss pairings.
+#[derive(Clone, Debug)]
+struct Record {
+    player: Address,
+    rating: f64,
+    /// Score in half points.
+    score: u32,
+    opponents: Vec<Address>,
+    /// Games played as white minus games played as black.
+    color_balance: i32,
+    had_bye: bool,
+}
+
+/// Pair the players of a Swiss tournament for the next round.
+///
+/// `players` gives each player's rating, and `pairings` the pairings (and results) of all previous
+/// rounds. Players are ranked by score and then rating, and each is paired with the highest ranked
+/// player they have not yet played, backtracking where necessary to avoid rematches. The search is
+/// bounded: if it does not find a pairing without rematches after trying a fixed number of
+/// candidate pairs, each player is instead paired in rank order with the highest ranked remaining
+/// player they have not yet played, or the highest ranked remaining player if there is none. If
+/// the number of players is odd, the lowest ranked player who has not yet had a bye receives one.
+/// The player who has had white less often gets white.
+pub fn swiss_pairings(players: &[(Address, f64)], pairings: &[Pairing]) -> Vec<Pair> {
+    let mut records = records(players, pairings);
+    records.sort_by(|a, b| b.score.cmp(&a.score).then(b.rating.total_cmp(&a.rating)));
+
+    let mut result = vec![];
+    if records.len() % 2 == 1 {
+        let bye = records
+            .iter()
+            .rposition(|record| !record.had_bye)
+            .unwrap_or(records.len() - 1);
+        result.push((records.remove(bye).player, None));
+    }
+
+    let mut used = vec![false; records.len()];
+    let mut pairs = vec![];
+    let mut budget = PAIRING_BUDGET;
+    if !pair_swiss(&records, &mut used, &mut pairs, &mut budget) {
+        // Either there is no pairing without rematches, or it is too expensive to find one.
+        pairs = pair_greedy(&records);
+    }
+    for (a, b) in pairs {
+        let (a, b) = (&records[a], &records[b]);
+        let (white, black) = if a.color_balance <= b.color_balance {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        result.push((white.player, Some(black.player)));
+    }
+    result
+}
+
+/// Pair the highest ranked unpaired player, then recursively pair the rest, without rematches.
+///
+/// Gives up once `budget` candidate pairs have been tried.
+fn pair_swiss(
+    records: &[Record],
+    used: &mut [bool],
+    pairs: &mut Vec<(usize, usize)>,
+    budget: &mut usize,
+) -> bool {
+    let Some(a) = used.iter().position(|used| !used) else {
+        return true;
+    };
+    used[a] = true;
+    for b in a + 1..records.len() {
+        if used[b] || records[a].opponents.contains(&records[b].player) {
+            continue;
+        }
+        if *budget == 0 {
+            break;
+        }
+        *budget -= 1;
+        used[b] = true;
+        pairs.push((a, b));
+        if pair_swiss(records, used, pairs, budget) {
+            return true;
+        }
+        pairs.pop();
+        used[b] = false;
+    }
+    used[a] = false;
+    false
+}
+
+/// Pair players in rank order, each with the highest ranked remaining player they have not yet
+/// played, allowing a rematch only when there is no such player.
+fn pair_greedy(records: &[Record]) -> Vec<(usize, usize)> {
+    let mut unpaired: Vec<usize> = (0..records.len()).collect();
+    let mut pairs = vec![];
+    while unpaired.len() >= 2 {
+        let a = unpaired.remove(0);
+        let b = unpaired
+            .iter()
+            .position(|&b| !records[a].opponents.contains(&records[b].player))
+            .unwrap_or(0);
+        pairs.push((a, unpaired.remove(b)));
+    }
+    pairs
+}
+
+fn records(players: &[(Address, f64)], pairings: &[Pairing]) -> Vec<Record> {
+    let mut records: HashMap<Address, Record> = players
+        .iter()
+        .map(|&(player, rating)| {
+            (
+                player,
+                Record {
+                    player,
+                    rating,
+                    score: 0,
+                    opponents: vec![],
+                    color_balance: 0,
+                    had_bye: false,
+                },
+            )
+        })
+        .collect();
+    for pairing in pairings {
+        let (white_score, black_score) = points(pairing);
+        if let Some(record) = records.get_mut(&pairing.white) {
+            record.score += white_score;
+            match pairing.black {
+                Some(black) => {
+                    record.opponents.push(black);
+                    record.color_balance += 1;
+                }
+                None => record.had_bye = true,
+            }
+        }
+        if let Some(black) = pairing.black {
+            if let Some(record) = records.get_mut(&black) {
+                record.score += black_score;
+                record.opponents.push(pairing.white);
+                record.color_balance -= 1;
+            }
+        }
+    }
+    players
+        .iter()
+        .map(|(player, _)| records.remove(player).unwrap())
+        .collect()
+}
+
+/// The points, in half points, scored by white and black in a pairing.
+///
+/// A bye is worth a full point. Games which are unfinished or were abandoned are worth nothing.
+fn points(pairing: &Pairing) -> (u32, u32) {
+    if pairing.black.is_none() {
+        return (2, 0);
+    }
+    match pairing.result {
+        Some(Score::WhiteWins) => (2, 0),
+        Some(Score::BlackWins) => (0, 2),
+        Some(Score::Draw) => (1, 1),
+        None => (0, 0),
+    }
+}
+
+/// Compute the standings of a tournament.
+///
+/// Players are ranked by score, with ties broken by Buchholz score (the sum of the scores of each
+/// opponent), then by Sonneborn-Berger score (the sum of the scores of each opponent beaten, plus
+/// half the scores of each opponent drawn), and finally by rating.
+pub fn standings(players: &[(Address, f64)], pairings: &[Pairing]) -> Vec<Standing> {
+    let records = records(players, pairings);
+    let scores: HashMap<Address, u32> = records
+        .iter()
+        .map(|record| (record.player, record.score))
+        .collect();
+
+    // Tie breaks, in quarter points to account for halving drawn opponents' half point scores.
+    let mut buchholz: HashMap<Address, u32> = HashMap::new();
+    let mut sonneborn_berger: HashMap<Address, u32> = HashMap::new();
+    for pairing in pairings {
+        let Some(black) = pairing.black else {
+            continue;
+        };
+        let (white_points, black_points) = points(pairing);
+        let white_score = scores.get(&pairing.white).copied().unwrap_or_default();
+        let black_score = scores.get(&black).copied().unwrap_or_default();
+        if pairing.result.is_some() {
+            *buchholz.entry(pairing.white).or_default() += 2 * black_score;
+            *buchholz.entry(black).or_default() += 2 * white_score;
+        }
+        *sonneborn_berger.entry(pairing.white).or_default() += white_points * black_score;
+        *sonneborn_berger.entry(black).or_default() += black_points * white_score;
+    }
+
+    let mut standings: Vec<Standing> = records
+        .iter()
+        .map(|record| Standing {
+            player: record.player,
+            rating: record.rating,
+            score: record.score as f64 / 2.,
+            buchholz: buchholz.get(&record.player).copied().unwrap_or_default() as f64 / 4.,
+            sonneborn_berger: sonneborn_berger
+                .get(&record.player)
+                .copied()
+                .unwrap_or_default() as f64
+                / 4.,
+        })
+        .collect();
+    standings.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b.buchholz.total_cmp(&a.buchholz))
+            .then(b.sonneborn_berger.total_cmp(&a.sonneborn_berger))
+            .then(b.rating.total_cmp(&a.rating))
+    });
+    standings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn player(i: usize) -> Address {
+        Address::left_padding_from(&(i as u64 + 1).to_be_bytes())
+    }
+
+    /// `n` players, seeded in order of decreasing rating.
+    fn players(n: usize) -> Vec<(Address, f64)> {
+        (0..n).map(|i| (player(i), 2000. - i as f64)).collect()
+    }
+
+    fn pairing(
+        round: u32,
+        white: Address,
+        black: Option<Address>,
+        result: Option<Score>,
+    ) -> Pairing {
+        Pairing {
+            round,
+            game: None,
+            white,
+            black,
+            result,
+        }
+    }
+
+    fn rematches(pairs: &[Pair], history: &[Pairing]) -> usize {
+        let played: HashSet<_> = history
+            .iter()
+            .filter_map(|p| Some((p.white, p.black?)))
+            .flat_map(|(a, b)| [(a, b), (b, a)])
+            .collect();
+        pairs
+            .iter()
+            .filter(|(white, black)| black.is_some_and(|black| played.contains(&(*white, black))))
+            .count()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        for n in 2..=9 {
+            let players: Vec<_> = (0..n).map(player).collect();
+            let rounds = Format::RoundRobin.rounds(n);
+            let mut games = HashSet::new();
+            let mut whites: HashMap<Address, i32> = HashMap::new();
+            let mut byes = HashSet::new();
+            for round in 1..=rounds {
+                let pairs = round_robin_pairings(&players, round);
+                let paired: HashSet<_> = pairs
+                    .iter()
+                    .flat_map(|(white, black)| [Some(*white), *black])
+                    .flatten()
+                    .collect();
+                assert_eq!(paired.len(), n, "everyone plays once in round {round}");
+                for (white, black) in pairs {
+                    match black {
+                        Some(black) => {
+                            assert!(games.insert((white.min(black), white.max(black))));
+                            *whites.entry(white).or_default() += 1;
+                            *whites.entry(black).or_default() -= 1;
+                        }
+                        None => assert!(byes.insert(white)),
+                    }
+                }
+            }
+            // Everyone plays everyone exactly once, with colors as balanced as possible.
+            assert_eq!(games.len(), n * (n - 1) / 2);
+            assert_eq!(byes.len(), n % 2 * n);
+            assert!(whites.values().all(|balance| balance.abs() <= 1));
+        }
+    }
+
+    #[test]
+    fn test_standings_buchholz() {
+        let players = players(4);
+        let history = [
+            pairing(1, player(1), Some(player(2)), Some(Score::WhiteWins)),
+            pairing(1, player(0), Some(player(3)), Some(Score::WhiteWins)),
+            pairing(2, player(0), Some(player(1)), Some(Score::Draw)),
+            pairing(2, player(2), Some(player(3)), Some(Score::WhiteWins)),
+        ];
+        let standings = standings(&players, &history);
+        let order: Vec<_> = standings.iter().map(|standing| standing.player).collect();
+        // The first two players are tied on points, but player 1 has faced stronger opposition.
+        assert_eq!(order, [player(1), player(0), player(2), player(3)]);
+        assert_eq!(standings[0].score, 1.5);
+        assert_eq!(standings[0].buchholz, 2.5);
+        assert_eq!(standings[0].sonneborn_berger, 1.75);
+        assert_eq!(standings[1].buchholz, 1.5);
+    }
+
+    #[test]
+    fn test_standings_sonneborn_berger() {
+        let players = players(4);
+        let history = [
+            pairing(1, player(0), Some(player(1)), Some(Score::WhiteWins)),
+            pairing(1, player(2), Some(player(3)), Some(Score::WhiteWins)),
+            pairing(2, player(0), Some(player(2)), Some(Score::BlackWins)),
+            pairing(2, player(1), Some(player(3)), Some(Score::Draw)),
+            pairing(3, player(0), Some(player(3)), Some(Score::WhiteWins)),
+            pairing(3, player(1), Some(player(2)), Some(Score::WhiteWins)),
+        ];
+        let standings = standings(&players, &history);
+        let order: Vec<_> = standings.iter().map(|standing| standing.player).collect();
+        // The first two players are tied on points and Buchholz, but player 2 beat stronger
+        // opponents.
+        assert_eq!(order, [player(2), player(0), player(1), player(3)]);
+        assert_eq!(standings[0].buchholz, standings[1].buchholz);
+        assert_eq!(standings[0].sonneborn_berger, 2.5);
+        assert_eq!(standings[1].sonneborn_berger, 2.);
+        assert_eq!(standings[2].score, 1.5);
+        assert_eq!(standings[2].buchholz, 4.5);
+        assert_eq!(standings[2].sonneborn_berger, 2.25);
+    }
+
+    #[test]
+    fn test_standings_bye_and_unfinished_game() {
+        let players = players(3);
+        let history = [
+            pairing(1, player(0), Some(player(1)), None),
+            pairing(1, player(2), None, None),
+        ];
+        let standings = standings(&players, &history);
+        let order: Vec<_> = standings.iter().map(|standing| standing.player).collect();
+        // A bye is worth a point, while an unfinished game is worth nothing and doesn't count
+        // towards Buchholz scores. The rest are ranked by rating.
+        assert_eq!(order, [player(2), player(0), player(1)]);
+        assert_eq!(standings[0].score, 1.);
+        assert!(standings
+            .iter()
+            .all(|standing| standing.buchholz == 0. && standing.sonneborn_berger == 0.));
+    }
+
+    #[test]
+    fn test_swiss_avoids_rematches() {
+        let players = players(4);
+        let history = [
+            pairing(1, player(0), Some(player(1)), Some(Score::WhiteWins)),
+            pairing(1, player(2), Some(player(3)), Some(Score::WhiteWins)),
+        ];
+        let pairs = swiss_pairings(&players, &history);
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(rematches(&pairs, &history), 0);
+        // Winners meet winners and losers meet losers. Both players in each pair had the same
+        // color in the first round, so the higher ranked player gets white.
+        assert!(pairs.contains(&(player(0), Some(player(2)))));
+        assert!(pairs.contains(&(player(1), Some(player(3)))));
+    }
+
+    #[test]
+    fn test_swiss_bye() {
+        let players = players(3);
+        let history = [pairing(1, player(2), None, None)];
+        let pairs = swiss_pairings(&players, &history);
+        // The lowest ranked player has already had a bye, so the next lowest gets one.
+        assert!(pairs.contains(&(player(1), None)));
+    }
+
+    #[test]
+    fn test_swiss_no_rematch_free_pairing() {
+        // The three lowest ranked players have each played everyone else, so they can't all be
+        // paired among themselves, and some rematch is unavoidable. An exhaustive search would
+        // have to try every pairing of the other players before discovering that.
+        let players = players(40);
+        let mut history = vec![];
+        for i in 37..40 {
+            for j in 0..37 {
+                history.push(pairing(1, player(j), Some(player(i)), None));
+            }
+        }
+        let pairs = swiss_pairings(&players, &history);
+        assert_eq!(pairs.len(), 20);
+        let paired: HashSet<_> = pairs
+            .iter()
+            .flat_map(|(white, black)| [Some(*white), *black])
+            .collect();
+        assert_eq!(paired.len(), 40);
+        assert_eq!(rematches(&pairs, &history), 1);
+    }
+}
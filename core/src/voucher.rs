@@ -0,0 +1,25 @@
+use alloy::sol_types::sol;
+
+sol! {
+    #![sol(alloy_sol_types = alloy::sol_types)]
+
+    /// The ERC-721 contract which mints victory NFTs.
+    interface VictoryNft {
+        function mint(address to, uint256 tokenId, string metadata);
+    }
+
+    /// The ERC-721 contract which mints collectibles for the first solvers of puzzles.
+    interface PuzzleCollectible {
+        function mint(address to, uint256 tokenId, string metadata);
+    }
+
+    /// The Cartesi dApp contract, which holds deposited ether.
+    interface CartesiDApp {
+        function withdrawEther(address receiver, uint256 value);
+    }
+
+    /// An ERC-20 token, held by the dApp contract on behalf of its users.
+    interface Erc20 {
+        function transfer(address to, uint256 amount) returns (bool);
+    }
+}
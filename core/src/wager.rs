@@ -0,0 +1,97 @@
+//! Stakes wagered on games, and the deposits which fund them.
+
+use alloy::primitives::{Address, U256};
+use anyhow::{ensure, Context};
+use derive_more::Display;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// An asset which can be deposited and wagered.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq)]
+pub enum Asset {
+    #[display("ether")]
+    Ether,
+    /// An ERC-20 token, identified by its contract address.
+    #[display("{_0}")]
+    Erc20(Address),
+}
+
+/// Parse an asset: either `ether` (or `eth`) or the address of an ERC-20 token.
+impl FromStr for Asset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "ether" | "eth" => Ok(Self::Ether),
+            _ => Ok(Self::Erc20(
+                s.parse().context(format!("invalid asset {s}"))?,
+            )),
+        }
+    }
+}
+
+impl Serialize for Asset {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.to_string().serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Asset {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// An amount of some asset, wagered by each player in a game.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Stake {
+    pub asset: Asset,
+    /// The amount in base units (e.g. wei).
+    pub amount: U256,
+}
+
+impl Display for Stake {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.asset {
+            Asset::Ether => write!(f, "{} wei", self.amount),
+            Asset::Erc20(token) => write!(f, "{} of token {token}", self.amount),
+        }
+    }
+}
+
+/// A deposit into the dApp, made through one of the Cartesi portals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deposit {
+    /// The account to credit with the deposit.
+    pub sender: Address,
+    pub asset: Asset,
+    pub amount: U256,
+}
+
+impl Deposit {
+    /// Decode an input from the EtherPortal.
+    ///
+    /// The payload is `abi.encodePacked(sender, value, execLayerData)`.
+    pub fn ether(payload: &[u8]) -> anyhow::Result<Self> {
+        ensure!(payload.len() >= 52, "malformed ether deposit");
+        Ok(Self {
+            sender: Address::from_slice(&payload[..20]),
+            asset: Asset::Ether,
+            amount: U256::from_be_slice(&payload[20..52]),
+        })
+    }
+
+    /// Decode an input from the ERC20Portal.
+    ///
+    /// The payload is `abi.encodePacked(success, token, sender, amount, execLayerData)`. Fails if
+    /// the token transfer into the dApp did not succeed.
+    pub fn erc20(payload: &[u8]) -> anyhow::Result<Self> {
+        ensure!(payload.len() >= 73, "malformed ERC-20 deposit");
+        ensure!(payload[0] == 1, "ERC-20 deposit failed");
+        Ok(Self {
+            sender: Address::from_slice(&payload[21..41]),
+            asset: Asset::Erc20(Address::from_slice(&payload[1..21])),
+            amount: U256::from_be_slice(&payload[41..73]),
+        })
+    }
+}
@@ -1,22 +1,67 @@
-use alloy::{primitives::Bytes, sol_types::SolEvent};
+use alloy::{
+    primitives::{address, keccak256, Address, Bytes, U256},
+    sol_types::{SolCall, SolEvent},
+};
 use anyhow::{bail, ensure, Context};
 use chesspresso_core::{
+    clock::{Clock, TimeControl},
     db::Db,
-    game::{Game, Outcome},
-    message::{Advance, Metadata, Report, Status},
+    game::{Color, Game, GameHash, GameId, GameSetup, Outcome, Variant},
+    message::{
+        Advance, GameFilter, GameResult, GameState, Metadata, PuzzleSolve, Report, Seek, Status,
+        TournamentId,
+    },
     notice::{self},
+    puzzle::{self, Line},
+    rating::{self, RatingSystem},
+    tournament::{self, Format},
+    voucher::{CartesiDApp, Erc20, PuzzleCollectible, VictoryNft},
+    wager::{Asset, Deposit, Stake},
 };
 use futures::stream::TryStreamExt;
 use hyper::{client::connect::HttpConnector, Body, Response, StatusCode};
 use serde::Serialize;
 use serde_json::{json, Value};
-use std::env;
+use std::{env, str::FromStr};
 use tracing_subscriber::filter::EnvFilter;
 
+/// The time, in seconds, after which an unanswered challenge expires.
+const CHALLENGE_EXPIRY: u64 = 7 * 24 * 60 * 60;
+
+/// The default number of solvers included in puzzle rankings.
+const PUZZLE_RANKINGS: u32 = 20;
+
+/// The default number of players included in the leaderboard.
+const LEADERBOARD_SIZE: u32 = 20;
+
+/// The default number of finished games required to appear on the leaderboard.
+const LEADERBOARD_MIN_GAMES: u32 = 5;
+
+/// The default maximum rating deviation to appear on the leaderboard.
+///
+/// Players with a higher deviation have ratings which are still too uncertain to rank.
+const LEADERBOARD_MAX_DEVIATION: f64 = 110.;
+
+/// The default addresses of the Cartesi portals and relays.
+const ETHER_PORTAL: Address = address!("ffdbe43d4c855bf7e0f105c400a50857f53ab044");
+const ERC20_PORTAL: Address = address!("9c21aeb2093c32ddbc53eef24b873bdcd1ada1db");
+const DAPP_ADDRESS_RELAY: Address = address!("f5de34d6bbc0446e2a45719e718efebaae179dae");
+
 struct App {
     db: Db,
     client: hyper::Client<HttpConnector>,
     server_addr: String,
+    /// The ERC-721 contract used to mint victory NFTs, if any.
+    nft_address: Option<Address>,
+    /// The ERC-721 contract used to mint puzzle collectibles, if any.
+    puzzle_nft_address: Option<Address>,
+    /// The portals through which ether and ERC-20 tokens are deposited.
+    ether_portal: Address,
+    erc20_portal: Address,
+    /// The relay which informs the dApp of its own contract address.
+    dapp_address_relay: Address,
+    /// The address of the dApp contract, once it has been relayed.
+    dapp_address: Option<Address>,
 }
 
 impl App {
@@ -39,52 +84,266 @@ impl App {
         let message = message.strip_prefix("0x").unwrap_or(message);
         let bytes = hex::decode(message)?;
 
+        // Clean up challenges which can no longer be accepted.
+        self.db.expire_challenges(meta.timestamp).await?;
+        // Apply the results of any rating periods which have ended.
+        self.db.update_ratings(meta.timestamp).await?;
+        // Award collectibles for puzzles whose reveal period is over.
+        for solve in self.db.settle_puzzles(meta.timestamp).await? {
+            self.award_collectible(&solve).await?;
+        }
+
+        // Inputs from the portals and relays are not messages.
+        if meta.msg_sender == self.ether_portal || meta.msg_sender == self.erc20_portal {
+            let deposit = if meta.msg_sender == self.ether_portal {
+                Deposit::ether(&bytes)?
+            } else {
+                Deposit::erc20(&bytes)?
+            };
+            tracing::info!(?deposit, "deposit");
+            let stake = Stake {
+                asset: deposit.asset,
+                amount: deposit.amount,
+            };
+            self.db.credit(deposit.sender, stake).await?;
+            return Ok(());
+        }
+        if meta.msg_sender == self.dapp_address_relay {
+            ensure!(bytes.len() == 20, "malformed dApp address");
+            let address = Address::from_slice(&bytes);
+            tracing::info!(%address, "relayed dApp address");
+            self.dapp_address = Some(address);
+            return Ok(());
+        }
+
         match serde_json::from_slice(&bytes)? {
             Advance::Challenge {
                 opponent,
                 first_move,
+                time_control,
+                variant,
+                seed,
+                fen,
+                stake,
             } => {
-                tracing::info!(%opponent, ?first_move, "challenge");
-                let (white, black) = if first_move.is_some() {
-                    (meta.msg_sender, opponent)
+                tracing::info!(
+                    %opponent,
+                    ?first_move,
+                    ?time_control,
+                    ?variant,
+                    ?seed,
+                    ?fen,
+                    ?stake,
+                    "challenge"
+                );
+                ensure!(opponent != meta.msg_sender, "cannot challenge yourself");
+
+                let variant = variant.unwrap_or_default();
+                ensure!(
+                    seed.is_none() || (variant == Variant::Chess960 && fen.is_none()),
+                    "a start seed can only be given for Chess960 games without a FEN"
+                );
+                let setup = match (variant, fen) {
+                    (Variant::Chess960, None) => {
+                        GameSetup::chess960(seed.unwrap_or_else(|| chess960_seed(&meta)))
+                    }
+                    (variant, fen) => GameSetup::new(variant, fen.as_deref())?,
+                };
+
+                // Make sure the starting position is playable and the first move is legal now, so
+                // that the challenge cannot fail once it is accepted.
+                let mut game =
+                    Game::with_setup(0.into(), meta.msg_sender, opponent, setup.clone())?;
+                ensure!(
+                    game.outcome().is_none(),
+                    "the game is already over in the starting position"
+                );
+                if let Some(san) = &first_move {
+                    game.play_next_move(san.parse().context("invalid first move")?)?;
+                }
+
+                // Escrow the challenger's stake until the challenge is resolved.
+                if let Some(stake) = stake {
+                    ensure!(stake.amount > U256::ZERO, "stake must be positive");
+                    self.db.debit(meta.msg_sender, stake).await?;
+                }
+
+                let id = self
+                    .db
+                    .new_challenge(
+                        meta.msg_sender,
+                        opponent,
+                        first_move,
+                        time_control,
+                        &setup,
+                        meta.timestamp + CHALLENGE_EXPIRY,
+                    )
+                    .await?;
+                if let Some(stake) = stake {
+                    self.db.set_challenge_stake(id, stake).await?;
+                }
+                tracing::info!(%id, "created challenge");
+            }
+            Advance::AcceptChallenge { id } => {
+                tracing::info!(%id, "accept challenge");
+
+                let challenge = self.db.challenge(id).await?;
+                ensure!(
+                    challenge.opponent == meta.msg_sender,
+                    "challenge was not issued to this player"
+                );
+                self.db.delete_challenge(id).await?;
+
+                // The challenger plays the side to move if they have chosen a first move.
+                let (turn, _) = challenge.setup.starting_move()?;
+                let challenger_color = if challenge.first_move.is_some() {
+                    turn
                 } else {
-                    (opponent, meta.msg_sender)
+                    !turn
+                };
+                let (white, black) = match challenger_color {
+                    Color::White => (challenge.challenger, challenge.opponent),
+                    Color::Black => (challenge.opponent, challenge.challenger),
                 };
 
-                let mut game = self.db.new_game(white, black).await?;
-                if let Some(san) = first_move {
+                let mut clock = challenge
+                    .time_control
+                    .map(|tc| Clock::new(tc, meta.timestamp));
+                let mut game = self
+                    .db
+                    .new_game(white, black, challenge.setup, clock, meta.timestamp)
+                    .await?;
+                if let Some(stake) = challenge.stake {
+                    // The opponent matches the challenger's escrowed stake.
+                    self.db.debit(challenge.opponent, stake).await?;
+                    self.db.set_stake(game.id(), stake).await?;
+                }
+                let mut first_move = None;
+                if let Some(san) = challenge.first_move {
                     let m = game.play(
-                        meta.msg_sender,
+                        challenge.challenger,
                         game.hash(),
                         san.parse().context("invalid first move")?,
                     )?;
+                    first_move = Some(m.san());
                     self.db.record_move(game.id(), m).await?;
+                    if let Some(clock) = &mut clock {
+                        clock.punch(turn, meta.timestamp)?;
+                        self.db.set_clock(game.id(), clock).await?;
+                    }
                 }
+                self.game_started(&game, challenge.time_control, challenge.stake, first_move)
+                    .await?;
+            }
+            Advance::DeclineChallenge { id } => {
+                tracing::info!(%id, "decline challenge");
+
+                let challenge = self.db.challenge(id).await?;
+                ensure!(
+                    challenge.opponent == meta.msg_sender,
+                    "challenge was not issued to this player"
+                );
+                self.db.delete_challenge(id).await?;
+                if let Some(stake) = challenge.stake {
+                    self.db.credit(challenge.challenger, stake).await?;
+                }
+            }
+            Advance::CancelChallenge { id } => {
+                tracing::info!(%id, "cancel challenge");
+
+                let challenge = self.db.challenge(id).await?;
+                ensure!(
+                    challenge.challenger == meta.msg_sender,
+                    "challenge was not issued by this player"
+                );
+                self.db.delete_challenge(id).await?;
+                if let Some(stake) = challenge.stake {
+                    self.db.credit(challenge.challenger, stake).await?;
+                }
+            }
+            Advance::Seek {
+                min_rating,
+                max_rating,
+                color,
+                time_control,
+            } => {
+                tracing::info!(?min_rating, ?max_rating, ?color, ?time_control, "seek");
+
+                let seek = Seek {
+                    id: 0.into(),
+                    player: meta.msg_sender,
+                    min_rating,
+                    max_rating,
+                    color,
+                    time_control,
+                };
+                // Look for the oldest compatible seek to pair with.
+                let pairing = self.db.find_pairing(&seek).await?;
+
+                let Some(other) = pairing else {
+                    let id = self
+                        .db
+                        .new_seek(seek.player, min_rating, max_rating, color, time_control)
+                        .await?;
+                    tracing::info!(%id, "created seek");
+                    return Ok(());
+                };
+                self.db.delete_seek(other.id).await?;
+
+                let color = match (seek.color, other.color) {
+                    (Some(color), _) => color,
+                    (None, Some(color)) => !color,
+                    // If neither player has a preference, the player who has been waiting longer
+                    // plays white.
+                    (None, None) => Color::Black,
+                };
+                let (white, black) = match color {
+                    Color::White => (seek.player, other.player),
+                    Color::Black => (other.player, seek.player),
+                };
+                let clock = time_control.map(|tc| Clock::new(tc, meta.timestamp));
+                let game = self
+                    .db
+                    .new_game(white, black, GameSetup::default(), clock, meta.timestamp)
+                    .await?;
+                tracing::info!(id = %game.id(), seek = %other.id, "paired seeks");
+                self.game_started(&game, time_control, None, None).await?;
+            }
+            Advance::CancelSeek { id } => {
+                tracing::info!(%id, "cancel seek");
+
+                let seek = self.db.seek(id).await?;
+                ensure!(
+                    seek.player == meta.msg_sender,
+                    "seek was not posted by this player"
+                );
+                self.db.delete_seek(id).await?;
             }
             Advance::Move { id, hash, san } => {
                 tracing::info!(%id, san, "move");
-                let mut game = self.db.game(id).await?;
+                let mut game = self.db.active_game(id).await?;
+                let color = game.turn();
                 let m = game.play(meta.msg_sender, hash, san.parse().context("invalid move")?)?;
+
+                // Debit the time spent on this move from the player's clock. If they have already
+                // run out of time, the move is rejected, and the opponent may claim the game.
+                if let Some(mut clock) = self.db.clock(id).await? {
+                    clock.punch(color, meta.timestamp)?;
+                    self.db.set_clock(id, &clock).await?;
+                }
                 self.db.record_move(id, m).await?;
 
                 // Check for game over.
                 if let Some(outcome) = game.outcome() {
-                    self.end_game(&game, outcome).await?;
+                    self.end_game(&game, outcome, meta.timestamp).await?;
                 }
             }
             Advance::Resign { id, hash } => {
                 tracing::info!(%id, "resign");
 
-                let game = self.db.game(id).await?;
-                ensure!(
-                    game.hash() == hash,
-                    "game is not in the expected state to resign"
-                );
-                ensure!(game.outcome().is_none(), "game is already over");
-
-                let color = game
-                    .player_color(meta.msg_sender)
-                    .context("player is not in this game")?;
+                let (game, color) = self
+                    .player_game(id, hash, meta.msg_sender, "resign")
+                    .await?;
                 let opponent = game.player(!color);
 
                 self.end_game(
@@ -93,9 +352,266 @@ impl App {
                         winner: opponent,
                         loser: meta.msg_sender,
                     },
+                    meta.timestamp,
                 )
                 .await?;
             }
+            Advance::Abort { id, hash } => {
+                tracing::info!(%id, "abort");
+
+                let (game, _) = self.player_game(id, hash, meta.msg_sender, "abort").await?;
+                ensure!(
+                    game.half_move() < 2,
+                    "game cannot be aborted once both players have moved"
+                );
+
+                self.abort_game(&game, meta.timestamp).await?;
+            }
+            Advance::ClaimDraw { id, hash } => {
+                tracing::info!(%id, "claim draw");
+
+                let (game, _) = self
+                    .player_game(id, hash, meta.msg_sender, "claim a draw")
+                    .await?;
+                let outcome = game
+                    .claimable_draw()
+                    .context("no draw can be claimed in this position")?;
+
+                self.end_game(&game, outcome, meta.timestamp).await?;
+            }
+            Advance::OfferDraw { id, hash } => {
+                tracing::info!(%id, "offer draw");
+
+                self.player_game(id, hash, meta.msg_sender, "offer a draw")
+                    .await?;
+                match self.db.draw_offer(id).await? {
+                    Some(from) if from == meta.msg_sender => bail!("draw already offered"),
+                    Some(_) => bail!("opponent has already offered a draw"),
+                    None => {}
+                }
+
+                self.db.set_draw_offer(id, Some(meta.msg_sender)).await?;
+            }
+            Advance::AcceptDraw { id, hash } => {
+                tracing::info!(%id, "accept draw");
+
+                let (game, color) = self
+                    .player_game(id, hash, meta.msg_sender, "accept a draw")
+                    .await?;
+                ensure!(
+                    self.db.draw_offer(id).await? == Some(game.player(!color)),
+                    "opponent has not offered a draw"
+                );
+
+                self.end_game(&game, Outcome::Draw, meta.timestamp).await?;
+            }
+            Advance::DeclineDraw { id, hash } => {
+                tracing::info!(%id, "decline draw");
+
+                let (game, color) = self
+                    .player_game(id, hash, meta.msg_sender, "decline a draw")
+                    .await?;
+                ensure!(
+                    self.db.draw_offer(id).await? == Some(game.player(!color)),
+                    "opponent has not offered a draw"
+                );
+
+                self.db.set_draw_offer(id, None).await?;
+            }
+            Advance::RequestTakeback { id, hash } => {
+                tracing::info!(%id, "request takeback");
+
+                let (game, color) = self
+                    .player_game(id, hash, meta.msg_sender, "request a takeback")
+                    .await?;
+                ensure!(
+                    game.half_move() >= game.takeback_len(color),
+                    "no move to take back"
+                );
+                match self.db.takeback_request(id).await? {
+                    Some(from) if from == meta.msg_sender => bail!("takeback already requested"),
+                    Some(_) => bail!("opponent has already requested a takeback"),
+                    None => {}
+                }
+
+                self.db
+                    .set_takeback_request(id, Some(meta.msg_sender))
+                    .await?;
+            }
+            Advance::AcceptTakeback { id, hash } => {
+                tracing::info!(%id, "accept takeback");
+
+                let (mut game, color) = self
+                    .player_game(id, hash, meta.msg_sender, "accept a takeback")
+                    .await?;
+                ensure!(
+                    self.db.takeback_request(id).await? == Some(game.player(!color)),
+                    "opponent has not requested a takeback"
+                );
+
+                // Whoever is to move has been thinking since the last move. Stop their clock, so
+                // that time is not charged to the other player if the turn changes.
+                if let Some(mut clock) = self.db.clock(id).await? {
+                    clock.stop(game.turn(), meta.timestamp)?;
+                    self.db.set_clock(id, &clock).await?;
+                }
+                game.take_back(game.takeback_len(!color))?;
+                self.db.take_back(id, game.half_move()).await?;
+            }
+            Advance::ClaimTimeout { id, hash } => {
+                tracing::info!(%id, "claim timeout");
+
+                let (game, color) = self
+                    .player_game(id, hash, meta.msg_sender, "claim a timeout")
+                    .await?;
+                let clock = self.db.clock(id).await?.context("game is not timed")?;
+                ensure!(game.turn() == !color, "it is not the opponent's turn");
+                ensure!(
+                    clock.flagged(game.turn(), meta.timestamp),
+                    "opponent has not run out of time"
+                );
+
+                self.end_game(&game, game.timeout(game.turn()), meta.timestamp)
+                    .await?;
+            }
+            Advance::PostPuzzle {
+                variant,
+                fen,
+                solution,
+            } => {
+                tracing::info!(?variant, fen, "post puzzle");
+
+                let setup = GameSetup::new(variant.unwrap_or_default(), Some(&fen))?;
+                solution.validate()?;
+                let id = self
+                    .db
+                    .new_puzzle(meta.msg_sender, &setup, &solution, meta.timestamp)
+                    .await?;
+                tracing::info!(%id, "created puzzle");
+            }
+            Advance::CommitSolution { id, commitment } => {
+                tracing::info!(%id, %commitment, "commit solution");
+
+                let puzzle = self.db.puzzle(id).await?;
+                ensure!(
+                    puzzle.author != meta.msg_sender,
+                    "cannot solve your own puzzle"
+                );
+                ensure!(
+                    puzzle.revealed_at.is_none(),
+                    "the solution of puzzle {id} has already been revealed"
+                );
+                self.db
+                    .commit_solution(id, meta.msg_sender, commitment, meta.timestamp)
+                    .await?;
+            }
+            Advance::RevealPuzzle { id, salt, solution } => {
+                tracing::info!(%id, "reveal puzzle");
+
+                let puzzle = self.db.puzzle(id).await?;
+                ensure!(
+                    puzzle.author == meta.msg_sender,
+                    "puzzle was not posted by this player"
+                );
+                ensure!(
+                    puzzle.revealed_at.is_none(),
+                    "the solution of puzzle {id} has already been revealed"
+                );
+                let line = Line::play(&puzzle.setup, &solution)?;
+                self.db
+                    .puzzle_commitment(id)
+                    .await?
+                    .verify(meta.msg_sender, salt, &line)?;
+                self.db.reveal_puzzle(id, &line, meta.timestamp).await?;
+            }
+            Advance::SolvePuzzle { id, salt, solution } => {
+                tracing::info!(%id, "solve puzzle");
+
+                let puzzle = self.db.puzzle(id).await?;
+                let revealed_at = puzzle.revealed_at.context(format!(
+                    "the solution of puzzle {id} has not been revealed yet"
+                ))?;
+                ensure!(
+                    meta.timestamp < revealed_at + puzzle::REVEAL_PERIOD,
+                    "the reveal period of puzzle {id} is over"
+                );
+                let commitment = self.db.solution_commitment(id, meta.msg_sender).await?;
+                let line = Line::play(&puzzle.setup, &solution)?;
+                ensure!(
+                    line.commitment(meta.msg_sender, salt) == commitment,
+                    "solution does not match commitment"
+                );
+                let expected = self.db.puzzle_solution(id).await?;
+                let expected = Line::play(&puzzle.setup, &expected)?;
+                ensure!(line.solves(&expected), "incorrect solution");
+
+                self.db.solve_puzzle(id, meta.msg_sender).await?;
+                tracing::info!("solved puzzle");
+            }
+            Advance::Withdraw { stake } => {
+                tracing::info!(%stake, "withdraw");
+
+                self.db.debit(meta.msg_sender, stake).await?;
+                self.withdraw(meta.msg_sender, stake).await?;
+            }
+            Advance::CreateTournament {
+                name,
+                format,
+                rounds,
+                time_control,
+            } => {
+                tracing::info!(name, %format, ?rounds, ?time_control, "create tournament");
+
+                ensure!(!name.trim().is_empty(), "tournament name cannot be empty");
+                ensure!(
+                    rounds.is_none() || format == Format::Swiss,
+                    "the number of rounds can only be chosen for Swiss tournaments"
+                );
+                ensure!(
+                    rounds != Some(0),
+                    "a tournament must have at least one round"
+                );
+                ensure!(
+                    rounds.is_none_or(|rounds| rounds <= tournament::MAX_SWISS_ROUNDS),
+                    "a Swiss tournament can have at most {} rounds",
+                    tournament::MAX_SWISS_ROUNDS
+                );
+                let id = self
+                    .db
+                    .new_tournament(
+                        meta.msg_sender,
+                        &name,
+                        format,
+                        rounds,
+                        time_control,
+                        meta.timestamp,
+                    )
+                    .await?;
+                tracing::info!(%id, "created tournament");
+            }
+            Advance::JoinTournament { id } => {
+                tracing::info!(%id, "join tournament");
+                self.db.join_tournament(id, meta.msg_sender).await?;
+            }
+            Advance::StartTournament { id } => {
+                tracing::info!(%id, "start tournament");
+
+                let tournament = self.db.tournament(id).await?;
+                ensure!(
+                    tournament.organizer == meta.msg_sender,
+                    "tournament was not organized by this player"
+                );
+                ensure!(tournament.round.is_none(), "tournament has already started");
+                ensure!(
+                    tournament.players >= 2,
+                    "a tournament needs at least two players"
+                );
+                let rounds = tournament
+                    .rounds
+                    .unwrap_or_else(|| tournament.format.rounds(tournament.players as usize));
+                self.db.start_tournament(id, rounds).await?;
+                self.start_round(id, 1, meta.timestamp).await?;
+            }
         }
         Ok(())
     }
@@ -123,8 +639,15 @@ impl App {
                     .next()
                     .context("missing parameter address")?
                     .parse()?;
-                let after = segments.next().map(|after| after.parse()).transpose()?;
-                let games = self.db.games(address, after).try_collect().await?;
+                let mut after = None;
+                let mut filter = GameFilter::default();
+                for segment in segments {
+                    match segment.parse() {
+                        Ok(f) => filter = f,
+                        Err(_) => after = Some(segment.parse()?),
+                    }
+                }
+                let games = self.db.games(address, after, filter).try_collect().await?;
                 self.report(&Report::Games { games }).await?;
             }
             "moves" => {
@@ -132,9 +655,184 @@ impl App {
                     .next()
                     .context("missing parameter game ID")?
                     .parse()?;
-                let from = segments.next().context("missing parameter from")?.parse()?;
-                let moves = self.db.moves(id, from).try_collect().await?;
-                self.report(&Report::Moves { moves }).await?;
+                let mut from: u16 = segments.next().context("missing parameter from")?.parse()?;
+                let seen = segments.next().map(|seen| seen.parse()).transpose()?;
+                let result = self.db.game_info(id).await?.result;
+                let aborted = matches!(result, Some(GameResult { score: None, .. }));
+                if aborted {
+                    self.report(&Report::Aborted { id }).await?;
+                } else {
+                    let (takebacks, min) = self.db.takebacks(id, seen.unwrap_or_default()).await?;
+                    // Moves the client has seen may have been taken back since, in which case it
+                    // must rewind to the earliest takeback it doesn't know about.
+                    let taken_back_to = seen
+                        .and(min)
+                        .filter(|half_move| *half_move < from.saturating_sub(1));
+                    if let Some(half_move) = taken_back_to {
+                        from = half_move + 1;
+                    }
+                    let (moves, hashes) = self
+                        .db
+                        .moves_with_hashes(id, from)
+                        .await?
+                        .into_iter()
+                        .unzip();
+                    self.report(&Report::Moves {
+                        moves,
+                        hashes,
+                        takebacks,
+                        taken_back_to,
+                        over: result.is_some(),
+                    })
+                    .await?;
+                }
+            }
+            "challenges" => {
+                let direction = segments.next().context("missing parameter direction")?;
+                let address = segments
+                    .next()
+                    .context("missing parameter address")?
+                    .parse()?;
+                let challenges = match direction {
+                    "incoming" => self.db.incoming_challenges(address).try_collect().await?,
+                    "outgoing" => self.db.outgoing_challenges(address).try_collect().await?,
+                    _ => bail!("invalid challenge direction {direction}"),
+                };
+                self.report(&Report::Challenges { challenges }).await?;
+            }
+            "seeks" => {
+                let after = segments.next().map(|after| after.parse()).transpose()?;
+                let seeks = self.db.seeks(after).try_collect().await?;
+                self.report(&Report::Seeks { seeks }).await?;
+            }
+            "draw-offers" => {
+                let address = segments
+                    .next()
+                    .context("missing parameter address")?
+                    .parse()?;
+                let offers = self.db.draw_offers(address).try_collect().await?;
+                self.report(&Report::DrawOffers { offers }).await?;
+            }
+            "takeback-requests" => {
+                let address = segments
+                    .next()
+                    .context("missing parameter address")?
+                    .parse()?;
+                let requests = self.db.takeback_requests(address).try_collect().await?;
+                self.report(&Report::TakebackRequests { requests }).await?;
+            }
+            "clock" => {
+                let id = segments
+                    .next()
+                    .context("missing parameter game ID")?
+                    .parse()?;
+                let clock = self.db.clock(id).await?;
+                self.report(&Report::Clock { clock }).await?;
+            }
+            "state" => {
+                let id = segments
+                    .next()
+                    .context("missing parameter game ID")?
+                    .parse()?;
+                let game = self.db.game(id).await?;
+                let state = GameState {
+                    id,
+                    hash: game.hash(),
+                    half_move: game.half_move(),
+                };
+                self.report(&Report::State { state }).await?;
+            }
+            "pgn" => {
+                let id = segments
+                    .next()
+                    .context("missing parameter game ID")?
+                    .parse()?;
+                let pgn = self.db.pgn(id).await?;
+                self.report(&Report::Pgn { pgn }).await?;
+            }
+            "puzzles" => {
+                let after = segments.next().map(|after| after.parse()).transpose()?;
+                let puzzles = self.db.puzzles(after).try_collect().await?;
+                self.report(&Report::Puzzles { puzzles }).await?;
+            }
+            "puzzle-solvers" => {
+                let id = segments
+                    .next()
+                    .context("missing parameter puzzle ID")?
+                    .parse()?;
+                let solvers = self.db.puzzle_solvers(id).try_collect().await?;
+                self.report(&Report::PuzzleSolvers { solvers }).await?;
+            }
+            "puzzle-rankings" => {
+                let limit = segments
+                    .next()
+                    .map(|limit| limit.parse())
+                    .transpose()?
+                    .unwrap_or(PUZZLE_RANKINGS);
+                let rankings = self.db.puzzle_rankings(limit).try_collect().await?;
+                self.report(&Report::PuzzleRankings { rankings }).await?;
+            }
+            "balances" => {
+                let address = segments
+                    .next()
+                    .context("missing parameter address")?
+                    .parse()?;
+                let balances = self.db.balances(address).try_collect().await?;
+                self.report(&Report::Balances { balances }).await?;
+            }
+            "tournaments" => {
+                let after = segments.next().map(|after| after.parse()).transpose()?;
+                let tournaments = self.db.tournaments(after).try_collect().await?;
+                self.report(&Report::Tournaments { tournaments }).await?;
+            }
+            "pairings" => {
+                let id = segments
+                    .next()
+                    .context("missing parameter tournament ID")?
+                    .parse()?;
+                let round = segments.next().map(|round| round.parse()).transpose()?;
+                let pairings = self.db.pairings(id, round).try_collect().await?;
+                self.report(&Report::Pairings { pairings }).await?;
+            }
+            "standings" => {
+                let id = segments
+                    .next()
+                    .context("missing parameter tournament ID")?
+                    .parse()?;
+                let standings = self.db.standings(id).await?;
+                self.report(&Report::Standings { standings }).await?;
+            }
+            "rating-history" => {
+                let address = segments
+                    .next()
+                    .context("missing parameter address")?
+                    .parse()?;
+                let history = self.db.rating_history(address).try_collect().await?;
+                self.report(&Report::RatingHistory { history }).await?;
+            }
+            "leaderboard" => {
+                let pool = segments.next().context("missing parameter pool")?.parse()?;
+                let limit = segments
+                    .next()
+                    .map(|limit| limit.parse())
+                    .transpose()?
+                    .unwrap_or(LEADERBOARD_SIZE);
+                let min_games = segments
+                    .next()
+                    .map(|min_games| min_games.parse())
+                    .transpose()?
+                    .unwrap_or(LEADERBOARD_MIN_GAMES);
+                let max_deviation = segments
+                    .next()
+                    .map(|max_deviation| max_deviation.parse())
+                    .transpose()?
+                    .unwrap_or(LEADERBOARD_MAX_DEVIATION);
+                let leaderboard = self
+                    .db
+                    .leaderboard(pool, limit, min_games, max_deviation)
+                    .try_collect()
+                    .await?;
+                self.report(&Report::Leaderboard { leaderboard }).await?;
             }
             "stats" => {
                 let address = segments
@@ -152,10 +850,108 @@ impl App {
         Ok(())
     }
 
-    async fn end_game(&mut self, game: &Game, outcome: Outcome) -> anyhow::Result<()> {
+    /// Load an ongoing game in which `player` is taking some `action`.
+    ///
+    /// Fails if the game is over, if the game is not in the `expected_state`, or if `player` is not
+    /// playing in the game. On success, returns the game along with the color `player` controls.
+    async fn player_game(
+        &mut self,
+        id: GameId,
+        expected_state: GameHash,
+        player: Address,
+        action: &str,
+    ) -> anyhow::Result<(Game, Color)> {
+        let game = self.db.active_game(id).await?;
+        ensure!(
+            game.hash() == expected_state,
+            "game is not in the expected state to {action}"
+        );
+        ensure!(game.outcome().is_none(), "game is already over");
+
+        let color = game
+            .player_color(player)
+            .context("player is not in this game")?;
+        Ok((game, color))
+    }
+
+    /// Announce a solve of a puzzle, minting a collectible for the solver if it has any value.
+    async fn award_collectible(&mut self, solve: &PuzzleSolve) -> anyhow::Result<()> {
+        let id = solve.puzzle;
+        tracing::info!(%id, solver = %solve.solver, rank = solve.rank, value = solve.value, "puzzle solved");
+
+        if let (Some(address), true) = (self.puzzle_nft_address, solve.value > 0) {
+            let puzzle = self.db.puzzle(id).await?;
+            let metadata = json!({
+                "name": format!("Chesspresso puzzle {id} #{}", solve.rank),
+                "description": format!("Solver #{} of Chesspresso puzzle {id}", solve.rank),
+                "attributes": [
+                    {"trait_type": "Puzzle", "value": i32::from(id)},
+                    {"trait_type": "Rank", "value": solve.rank},
+                    {"trait_type": "Value", "value": solve.value},
+                    {"trait_type": "Variant", "value": puzzle.setup.variant},
+                ],
+                "fen": puzzle.setup.fen,
+            });
+            self.voucher(
+                address,
+                &PuzzleCollectible::mintCall {
+                    to: solve.solver,
+                    tokenId: (U256::from(i32::from(id)) << 32) | U256::from(solve.rank),
+                    metadata: metadata.to_string(),
+                },
+            )
+            .await?;
+        }
+        self.notice(&notice::PuzzleSolved {
+            id: id.into(),
+            solver: solve.solver,
+            rank: solve.rank,
+            value: solve.value,
+        })
+        .await
+    }
+
+    async fn end_game(
+        &mut self,
+        game: &Game,
+        outcome: Outcome,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
         let notation = self.db.game_notation(game.id()).await?;
 
+        // Settle the wager: the winner takes both stakes, and a draw refunds them.
+        if let Some(stake) = self.db.stake(game.id()).await? {
+            match outcome.winner_loser() {
+                Some((winner, _)) => {
+                    let pot = Stake {
+                        asset: stake.asset,
+                        amount: stake
+                            .amount
+                            .checked_mul(U256::from(2))
+                            .context("pot overflow")?,
+                    };
+                    self.pay_out(winner, pot).await?;
+                }
+                None => {
+                    self.db.credit(game.white(), stake).await?;
+                    self.db.credit(game.black(), stake).await?;
+                }
+            }
+        }
+
         if let Some((winner, loser)) = outcome.winner_loser() {
+            if let Some(nft_address) = self.nft_address {
+                let metadata = nft_metadata(game, &outcome, &notation);
+                self.voucher(
+                    nft_address,
+                    &VictoryNft::mintCall {
+                        to: winner,
+                        tokenId: U256::from(i32::from(game.id())),
+                        metadata: metadata.to_string(),
+                    },
+                )
+                .await?;
+            }
             self.notice(&notice::Victory {
                 id: game.id().into(),
                 winner,
@@ -173,10 +969,121 @@ impl App {
             .await?;
         }
 
-        self.db.end_game(game, Some(outcome)).await?;
+        self.db.end_game(game, Some(outcome), timestamp).await?;
+        if let Some(tournament) = self.db.game_tournament(game.id()).await? {
+            self.advance_tournament(tournament, timestamp).await?;
+        }
+        Ok(())
+    }
+
+    /// End a game without a result, refunding any stakes.
+    async fn abort_game(&mut self, game: &Game, timestamp: u64) -> anyhow::Result<()> {
+        if let Some(stake) = self.db.stake(game.id()).await? {
+            self.db.credit(game.white(), stake).await?;
+            self.db.credit(game.black(), stake).await?;
+        }
+        self.report(&Report::Aborted { id: game.id() }).await?;
+
+        self.db.end_game(game, None, timestamp).await?;
+        if let Some(tournament) = self.db.game_tournament(game.id()).await? {
+            self.advance_tournament(tournament, timestamp).await?;
+        }
+        Ok(())
+    }
+
+    /// Start the next round of a tournament, or finish it, if every game of the current round is
+    /// over.
+    async fn advance_tournament(&mut self, id: TournamentId, timestamp: u64) -> anyhow::Result<()> {
+        let tournament = self.db.tournament(id).await?;
+        let Some(round) = tournament.round else {
+            return Ok(());
+        };
+        if tournament.finished || !self.db.round_over(id, round).await? {
+            return Ok(());
+        }
+        if round < tournament.rounds.unwrap_or_default() {
+            self.start_round(id, round + 1, timestamp).await
+        } else {
+            tracing::info!(%id, "tournament finished");
+            self.db.finish_tournament(id, timestamp).await
+        }
+    }
+
+    /// Pair round `round` of a tournament and create a game for each pairing.
+    async fn start_round(
+        &mut self,
+        id: TournamentId,
+        round: u32,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        let tournament = self.db.tournament(id).await?;
+        let players = self.db.tournament_players(id).await?;
+        let pairs = match tournament.format {
+            Format::RoundRobin => {
+                let players: Vec<Address> = players.iter().map(|(player, _)| *player).collect();
+                tournament::round_robin_pairings(&players, round)
+            }
+            Format::Swiss => {
+                let pairings: Vec<_> = self.db.pairings(id, None).try_collect().await?;
+                tournament::swiss_pairings(&players, &pairings)
+            }
+        };
+        tracing::info!(%id, round, ?pairs, "starting tournament round");
+
+        self.db.set_tournament_round(id, round).await?;
+        for (white, black) in pairs {
+            let game = match black {
+                Some(black) => {
+                    let clock = tournament.time_control.map(|tc| Clock::new(tc, timestamp));
+                    let game = self
+                        .db
+                        .new_game(white, black, GameSetup::default(), clock, timestamp)
+                        .await?;
+                    self.game_started(&game, tournament.time_control, None, None)
+                        .await?;
+                    Some(game.id())
+                }
+                None => None,
+            };
+            self.db.add_pairing(id, round, white, black, game).await?;
+        }
+
         Ok(())
     }
 
+    /// Announce a new game, so that indexers can follow it using only finalized outputs.
+    async fn game_started(
+        &self,
+        game: &Game,
+        time_control: Option<TimeControl>,
+        stake: Option<Stake>,
+        first_move: Option<String>,
+    ) -> anyhow::Result<()> {
+        let (stake_asset, stake_amount) = match stake {
+            Some(Stake {
+                asset: Asset::Erc20(token),
+                amount,
+            }) => (token, amount),
+            Some(Stake {
+                asset: Asset::Ether,
+                amount,
+            }) => (Address::ZERO, amount),
+            None => (Address::ZERO, U256::ZERO),
+        };
+        self.notice(&notice::GameStarted {
+            id: game.id().into(),
+            white: game.white(),
+            black: game.black(),
+            variant: game.setup().variant.to_string(),
+            fen: game.setup().fen.clone().unwrap_or_default(),
+            timeControl: time_control.map(|tc| tc.to_string()).unwrap_or_default(),
+            stakeAsset: stake_asset,
+            stakeAmount: stake_amount,
+            firstMove: first_move.unwrap_or_default(),
+        })
+        .await
+    }
+
     async fn notice<T: SolEvent>(&self, payload: &T) -> anyhow::Result<()> {
         let mut data = T::SIGNATURE_HASH.0.to_vec();
         data.extend(Vec::from(payload.encode_log_data().data));
@@ -192,6 +1099,66 @@ impl App {
         Ok(())
     }
 
+    /// Pay winnings to `to`, through a withdrawal voucher.
+    ///
+    /// If a voucher cannot be issued because the dApp does not yet know its own address (needed to
+    /// withdraw ether), the winnings are credited to `to`'s balance instead, so that the game can
+    /// still end.
+    async fn pay_out(&mut self, to: Address, stake: Stake) -> anyhow::Result<()> {
+        if stake.asset == Asset::Ether && self.dapp_address.is_none() {
+            tracing::warn!(%to, %stake, "dApp address unknown, crediting winnings to balance");
+            return self.db.credit(to, stake).await;
+        }
+        self.withdraw(to, stake).await
+    }
+
+    /// Send funds held by the dApp to `to`, through a voucher.
+    async fn withdraw(&self, to: Address, stake: Stake) -> anyhow::Result<()> {
+        match stake.asset {
+            Asset::Ether => {
+                let dapp_address = self
+                    .dapp_address
+                    .context("dApp address has not been relayed")?;
+                self.voucher(
+                    dapp_address,
+                    &CartesiDApp::withdrawEtherCall {
+                        receiver: to,
+                        value: stake.amount,
+                    },
+                )
+                .await
+            }
+            Asset::Erc20(token) => {
+                self.voucher(
+                    token,
+                    &Erc20::transferCall {
+                        to,
+                        amount: stake.amount,
+                    },
+                )
+                .await
+            }
+        }
+    }
+
+    async fn voucher<T: SolCall>(&self, destination: Address, call: &T) -> anyhow::Result<()> {
+        let response = self
+            .post(
+                "voucher",
+                json!({
+                    "destination": destination,
+                    "payload": Bytes::from(call.abi_encode()),
+                }),
+            )
+            .await?;
+        ensure!(
+            response.status().is_success(),
+            "failed to post voucher: {}",
+            response.status()
+        );
+        Ok(())
+    }
+
     async fn report(&self, payload: &Report) -> anyhow::Result<()> {
         let data = serde_json::to_string(payload)?;
         let response = self
@@ -219,6 +1186,47 @@ impl App {
     }
 }
 
+/// The metadata of the NFT minted for the winner of `game`.
+fn nft_metadata(game: &Game, outcome: &Outcome, notation: &str) -> Value {
+    json!({
+        "name": format!("Chesspresso game {}", game.id()),
+        "description": outcome.to_string(),
+        "attributes": [
+            {"trait_type": "Game", "value": i32::from(game.id())},
+            {"trait_type": "White", "value": game.white()},
+            {"trait_type": "Black", "value": game.black()},
+            {"trait_type": "Variant", "value": game.setup().variant},
+            {"trait_type": "Termination", "value": outcome.termination()},
+            {"trait_type": "Half moves", "value": game.half_move()},
+        ],
+        "notation": notation.trim(),
+    })
+}
+
+/// Derive a Chess960 start position for a challenge which did not specify one.
+///
+/// The seed depends only on the challenge input, so every node replaying the rollup agrees on it.
+fn chess960_seed(meta: &Metadata) -> u16 {
+    let mut bytes = meta.msg_sender.to_vec();
+    bytes.extend(meta.input_index.to_le_bytes());
+    let hash = keccak256(bytes);
+    u16::from_le_bytes([hash[0], hash[1]]) % 960
+}
+
+/// Read an optional value from the environment variable `name`.
+fn env_var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => {
+            Ok(Some(value.parse().context(format!("invalid {name}"))?))
+        }
+        _ => Ok(None),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt()
@@ -226,10 +1234,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_ansi(true)
         .init();
 
+    let mut db = Db::memory().await?;
+    db.set_rating_system(RatingSystem {
+        period: env_var("CHESSPRESSO_RATING_PERIOD")?.unwrap_or(rating::DEFAULT_PERIOD),
+        system_constant: env_var("CHESSPRESSO_RATING_SYSTEM_CONSTANT")?
+            .unwrap_or(rating::DEFAULT_SYSTEM_CONSTANT),
+    });
+
     let mut app = App {
-        db: Db::memory().await?,
+        db,
         client: hyper::Client::new(),
         server_addr: env::var("ROLLUP_HTTP_SERVER_URL")?,
+        nft_address: env_var("CHESSPRESSO_NFT_ADDRESS")?,
+        puzzle_nft_address: env_var("CHESSPRESSO_PUZZLE_NFT_ADDRESS")?,
+        ether_portal: env_var("CHESSPRESSO_ETHER_PORTAL")?.unwrap_or(ETHER_PORTAL),
+        erc20_portal: env_var("CHESSPRESSO_ERC20_PORTAL")?.unwrap_or(ERC20_PORTAL),
+        dapp_address_relay: env_var("CHESSPRESSO_DAPP_ADDRESS_RELAY")?
+            .unwrap_or(DAPP_ADDRESS_RELAY),
+        dapp_address: None,
     };
 
     let mut status = Status::Accept;
@@ -0,0 +1,44 @@
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// An exponentially increasing delay, e.g. between polls of an idle node or attempts to reconnect.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub min: Duration,
+    pub max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            current: min,
+        }
+    }
+
+    /// The delay to wait now, doubling the delay for next time (up to the maximum).
+    pub fn next(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Return to the minimum delay, e.g. after activity or a successful connection.
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+
+    /// Wait before polling again.
+    ///
+    /// If the last poll found something new (`active`), more is likely to follow soon, so we poll
+    /// again right away. Otherwise, we wait for longer and longer while the node is idle.
+    pub async fn poll(&mut self, active: bool) {
+        if active {
+            self.reset();
+        } else {
+            sleep(self.next()).await;
+        }
+    }
+}
@@ -0,0 +1,1174 @@
+//! An indexer which follows games in real time through preconfirmations from Espresso.
+//!
+//! Inputs to the dApp are sequenced by Espresso long before the rollup node finalizes them. This
+//! indexer reads Chesspresso inputs from the availability API of an Espresso query service as soon
+//! as they are sequenced, and replays them locally to derive an optimistic view of each game. The
+//! preconfirmations are not trusted: like the dApp, we only apply an input if it was signed by a
+//! player in the game and was intended for the current [`GameHash`], and we check draw offers,
+//! takeback requests and clocks the same way the dApp does. This makes forgery much harder, but the
+//! optimistic view is still only an approximation of the dApp's: for example, clocks are driven by
+//! Espresso block timestamps, and games are only followed from their finalized state onwards, so
+//! an input may occasionally be applied optimistically which the dApp will reject.
+//!
+//! The optimistic state is checked against the finalized state, as reported by another indexer.
+//! Since the finalized state lags behind, it should always be a state the optimistic game has
+//! already passed through. If it is not, the two have diverged: the divergence is flagged, and the
+//! game is reset to the finalized state.
+//!
+//! Only the moves and state of games are followed optimistically. Games which are not being
+//! followed when their inputs are sequenced, and all other queries, are left to the finalized
+//! indexer.
+//!
+//! Transactions in the dApp's namespace are expected in the format used by Cartesi's Espresso
+//! integration: JSON objects holding EIP-712 typed data for a [`CartesiMessage`], whose `data` is
+//! the input payload, along with the sender's signature.
+
+use crate::{Backoff, Indexer, MoveUpdate, DEFAULT_MAX_POLLING_INTERVAL, DEFAULT_POLLING_INTERVAL};
+use alloy::{
+    primitives::{Address, Bytes, Signature, B256, U256},
+    sol_types::{sol, Eip712Domain, SolStruct},
+};
+use anyhow::{ensure, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chesspresso_core::{
+    clock::Clock,
+    game::{Game, GameHash, GameId, San},
+    message::{
+        self, Advance, Challenge, DrawOffer, GameFilter, GameState, LeaderboardEntry, Pairing,
+        Puzzle, PuzzleId, PuzzleRanking, PuzzleSolve, RatingChange, Seek, Standing,
+        TakebackRequest, Tournament, TournamentId, UserStats,
+    },
+    pgn,
+    rating::Pool,
+    wager::Stake,
+};
+use futures::stream::{self, Stream, StreamExt};
+use hyper::{client::connect::HttpConnector, Client, Method, Request, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Mutex;
+use url::Url;
+
+sol! {
+    #![sol(alloy_sol_types = alloy::sol_types)]
+
+    /// An input to a Cartesi application, signed by the sender and sequenced by Espresso.
+    #[derive(Debug)]
+    struct CartesiMessage {
+        address app;
+        uint64 nonce;
+        uint128 max_gas_price;
+        bytes data;
+    }
+}
+
+/// The name of the EIP-712 domain in which Cartesi inputs sequenced by Espresso are signed.
+const DOMAIN_NAME: &str = "Cartesi";
+
+/// The version of the EIP-712 domain in which Cartesi inputs sequenced by Espresso are signed.
+const DOMAIN_VERSION: &str = "0.1.0";
+
+#[derive(Clone, Debug)]
+pub struct EspressoIndexer<I> {
+    client: Client<HttpConnector>,
+    query_url: Url,
+    namespace: u64,
+    app: Address,
+    domain: Eip712Domain,
+    polling: Backoff,
+    finalized: I,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The next Espresso block to read, once we have started following the chain.
+    height: Option<u64>,
+    /// The next nonce expected from each sender we have seen an input from.
+    nonces: HashMap<Address, u64>,
+    /// The games being followed.
+    games: HashMap<GameId, Optimistic>,
+}
+
+/// The optimistic state of a game.
+#[derive(Debug)]
+struct Optimistic {
+    game: Game,
+    /// Every state the game has passed through, including ones since taken back.
+    history: HashSet<GameHash>,
+    /// The clocks, if the game is timed.
+    clock: Option<Clock>,
+    /// The player who has offered a draw, if there is a pending offer.
+    draw_offer: Option<Address>,
+    /// The player who has requested a takeback, if there is a pending request.
+    takeback_request: Option<Address>,
+    /// Whether the game has ended, as far as we know.
+    over: bool,
+    /// Whether the optimistic state has ever disagreed with the finalized state.
+    diverged: bool,
+}
+
+impl<I: Indexer + Clone + Send + Sync + 'static> EspressoIndexer<I> {
+    /// Follow inputs to the dApp at `app` in namespace `namespace` of the Espresso query service at
+    /// `query_url`, checking them against `finalized`.
+    ///
+    /// Inputs are only accepted if they are signed for the rollup on chain `chain_id`.
+    pub fn new(query_url: Url, namespace: u64, app: Address, chain_id: u64, finalized: I) -> Self {
+        Self {
+            client: Client::new(),
+            query_url,
+            namespace,
+            app,
+            domain: Eip712Domain::new(
+                Some(DOMAIN_NAME.into()),
+                Some(DOMAIN_VERSION.into()),
+                Some(U256::from(chain_id)),
+                Some(Address::ZERO),
+                None,
+            ),
+            polling: Backoff::new(DEFAULT_POLLING_INTERVAL, DEFAULT_MAX_POLLING_INTERVAL),
+            finalized,
+            state: Default::default(),
+        }
+    }
+
+    /// Poll for new Espresso blocks every `min` while there is activity, backing off to every
+    /// `max` while the chain is idle.
+    pub fn with_polling_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.polling = Backoff::new(min, max);
+        self
+    }
+
+    /// Whether the optimistic state of game `id` has ever disagreed with the finalized state.
+    pub async fn diverged(&self, id: GameId) -> bool {
+        let state = self.state.lock().await;
+        state.games.get(&id).is_some_and(|game| game.diverged)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<Option<T>> {
+        let url = format!("{}{path}", &self.query_url);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(Default::default())?;
+        let response = self.client.request(request).await?;
+        // The query service responds with 404 for blocks which do not exist yet.
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        ensure!(
+            response.status().is_success(),
+            "{url}: query service error: {}",
+            response.status()
+        );
+        let body = hyper::body::to_bytes(response).await?;
+        Ok(Some(serde_json::from_slice(&body)?))
+    }
+
+    /// Replay all inputs sequenced since the last sync.
+    ///
+    /// Returns the number of blocks read.
+    async fn sync(&self) -> anyhow::Result<u64> {
+        let mut state = self.state.lock().await;
+        let height: u64 = self
+            .get("v0/status/block-height")
+            .await?
+            .context("missing block height")?;
+        // Inputs sequenced before we started are left to the finalized indexer.
+        let mut next = *state.height.get_or_insert(height);
+        let start = next;
+        while next < height {
+            let Some(header) = self
+                .get::<Header>(&format!("v0/availability/header/{next}"))
+                .await?
+            else {
+                break;
+            };
+            let path = format!("v0/availability/block/{next}/namespace/{}", self.namespace);
+            let Some(block) = self.get::<NamespaceBlock>(&path).await? else {
+                break;
+            };
+            let timestamp = header.timestamp();
+            for tx in block.transactions {
+                if let Err(err) = state.replay(self.app, &self.domain, &tx.payload, timestamp) {
+                    tracing::debug!(block = next, "skipping transaction: {err:#}");
+                }
+            }
+            next += 1;
+            state.height = Some(next);
+        }
+        Ok(next - start)
+    }
+
+    /// Compare the optimistic state of game `id` with the finalized state, starting to follow the
+    /// game if we aren't already.
+    ///
+    /// Returns whether the game is over and the optimistic state has caught up with it.
+    async fn check(&self, id: GameId) -> anyhow::Result<bool> {
+        let pgn = self.finalized.pgn(id).await?;
+        let finalized = pgn::parse(&pgn)?
+            .into_iter()
+            .next()
+            .context("finalized indexer returned no game")?;
+        let over = finalized
+            .tag("Termination")
+            .is_some_and(|termination| termination != "unterminated");
+        let finalized_game = Game::from_pgn(&finalized)?;
+
+        let diverged = {
+            let mut state = self.state.lock().await;
+            match state.games.get_mut(&id) {
+                None => false,
+                Some(optimistic) if optimistic.history.contains(&finalized_game.hash()) => {
+                    if !over {
+                        return Ok(false);
+                    }
+                    if finalized_game.hash() == optimistic.game.hash() {
+                        optimistic.over = true;
+                        return Ok(true);
+                    }
+                    tracing::error!(%id, "moves were played after the game ended");
+                    true
+                }
+                Some(optimistic) if finalized.moves.starts_with(optimistic.game.moves()) => {
+                    // We have simply missed some inputs, such as ones sent directly to the rollup
+                    // rather than through Espresso. Catch up.
+                    tracing::info!(%id, "optimistic state is behind finalized state, catching up");
+                    optimistic.diverged
+                }
+                Some(optimistic) => {
+                    tracing::error!(
+                        %id,
+                        optimistic = %optimistic.game.hash(),
+                        finalized = %finalized_game.hash(),
+                        "optimistic state diverged from finalized state"
+                    );
+                    true
+                }
+            }
+        };
+
+        let mut optimistic = self.follow(finalized_game, over).await?;
+        optimistic.diverged = diverged;
+        self.state.lock().await.games.insert(id, optimistic);
+        Ok(over)
+    }
+
+    /// Start following `game` optimistically from its finalized state.
+    async fn follow(&self, game: Game, over: bool) -> anyhow::Result<Optimistic> {
+        let id = game.id();
+        let (clock, draw_offer, takeback_request) = if over {
+            (None, None, None)
+        } else {
+            let clock = self.finalized.clock(id).await?;
+            let draw_offer = self
+                .finalized
+                .draw_offers(game.white())
+                .await?
+                .into_iter()
+                .find(|offer| offer.id == id)
+                .map(|offer| offer.from);
+            let takeback_request = self
+                .finalized
+                .takeback_requests(game.white())
+                .await?
+                .into_iter()
+                .find(|request| request.id == id)
+                .map(|request| request.from);
+            (clock, draw_offer, takeback_request)
+        };
+        Ok(Optimistic {
+            history: history(&game),
+            game,
+            clock,
+            draw_offer,
+            takeback_request,
+            over,
+            diverged: false,
+        })
+    }
+
+    /// The optimistic moves of game `id`, each with the hash of the game state after the move,
+    /// after a poll for new inputs.
+    ///
+    /// Returns the moves and whether the game is over, as far as the finalized state is concerned.
+    async fn poll_moves(&self, id: GameId) -> anyhow::Result<(Vec<(San, GameHash)>, bool)> {
+        if let Err(err) = self.sync().await {
+            tracing::warn!("error reading Espresso blocks: {err:#}");
+        }
+        let over = self.check(id).await?;
+        let state = self.state.lock().await;
+        let game = &state.games.get(&id).context("game not followed")?.game;
+        Ok((hashed_moves(game), over))
+    }
+}
+
+impl State {
+    /// Apply a transaction sequenced at time `timestamp` to the games being followed.
+    ///
+    /// Like the rollup, we only accept transactions signed in `domain` with the next nonce of the
+    /// sender. We can't know the nonce of a sender until we have seen one of their transactions,
+    /// though, so the first transaction we see from each sender is trusted to have the right
+    /// nonce, and a replayed transaction may be accepted if it is the first one we see from its
+    /// sender.
+    fn replay(
+        &mut self,
+        app: Address,
+        domain: &Eip712Domain,
+        payload: &[u8],
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        let tx: SignedMessage = serde_json::from_slice(payload)?;
+        let message = CartesiMessage::from(tx.typed_data.message);
+        if message.app != app {
+            return Ok(());
+        }
+        ensure!(
+            Eip712Domain::from(tx.typed_data.domain) == *domain,
+            "transaction signed for the wrong domain"
+        );
+        let hash = message.eip712_signing_hash(domain);
+        let sender =
+            Signature::try_from(tx.signature.as_ref())?.recover_address_from_prehash(&hash)?;
+        let nonce = self.nonces.entry(sender).or_insert(message.nonce);
+        ensure!(
+            message.nonce == *nonce,
+            "expected nonce {nonce} from {sender}, but got {}",
+            message.nonce
+        );
+        *nonce += 1;
+
+        // Inputs which are not messages, or which have nothing to do with the games we are
+        // following, are ignored.
+        let Ok(advance) = serde_json::from_slice::<Advance>(&message.data) else {
+            return Ok(());
+        };
+        let (Advance::Move { id, hash, .. }
+        | Advance::Resign { id, hash }
+        | Advance::Abort { id, hash }
+        | Advance::ClaimDraw { id, hash }
+        | Advance::OfferDraw { id, hash }
+        | Advance::AcceptDraw { id, hash }
+        | Advance::DeclineDraw { id, hash }
+        | Advance::RequestTakeback { id, hash }
+        | Advance::AcceptTakeback { id, hash }
+        | Advance::ClaimTimeout { id, hash }) = &advance
+        else {
+            return Ok(());
+        };
+        let Some(optimistic) = self.games.get_mut(id) else {
+            return Ok(());
+        };
+        optimistic.advance(sender, *hash, advance, timestamp)
+    }
+}
+
+impl Optimistic {
+    /// Apply an input sent by `sender` at time `now`, as the dApp would.
+    fn advance(
+        &mut self,
+        sender: Address,
+        hash: GameHash,
+        advance: Advance,
+        now: u64,
+    ) -> anyhow::Result<()> {
+        ensure!(!self.over, "game is over");
+        ensure!(
+            hash == self.game.hash(),
+            "input intended for state {hash}, but game is in state {}",
+            self.game.hash()
+        );
+        let color = self
+            .game
+            .player_color(sender)
+            .context("sender is not a player in this game")?;
+        let opponent = self.game.player(!color);
+        match advance {
+            Advance::Move { san, .. } => {
+                ensure!(self.game.turn() == color, "it is not {color}'s turn");
+                // A player who has run out of time can no longer move.
+                let mut clock = self.clock;
+                if let Some(clock) = &mut clock {
+                    clock.punch(color, now)?;
+                }
+                self.game.play_next_move(san.parse()?)?;
+                self.clock = clock;
+                self.history.insert(self.game.hash());
+                self.over = self.game.outcome().is_some();
+                // Making a move implicitly declines any pending offer or request.
+                self.draw_offer = None;
+                self.takeback_request = None;
+            }
+            Advance::OfferDraw { .. } => {
+                ensure!(self.draw_offer.is_none(), "a draw has already been offered");
+                self.draw_offer = Some(sender);
+            }
+            Advance::AcceptDraw { .. } => {
+                ensure!(
+                    self.draw_offer == Some(opponent),
+                    "opponent has not offered a draw"
+                );
+                self.over = true;
+            }
+            Advance::DeclineDraw { .. } => {
+                ensure!(
+                    self.draw_offer == Some(opponent),
+                    "opponent has not offered a draw"
+                );
+                self.draw_offer = None;
+            }
+            Advance::RequestTakeback { .. } => {
+                ensure!(
+                    self.game.half_move() >= self.game.takeback_len(color),
+                    "no move to take back"
+                );
+                ensure!(
+                    self.takeback_request.is_none(),
+                    "a takeback has already been requested"
+                );
+                self.takeback_request = Some(sender);
+            }
+            Advance::AcceptTakeback { .. } => {
+                ensure!(
+                    self.takeback_request == Some(opponent),
+                    "opponent has not requested a takeback"
+                );
+                let mut clock = self.clock;
+                if let Some(clock) = &mut clock {
+                    clock.stop(self.game.turn(), now)?;
+                }
+                self.game.take_back(self.game.takeback_len(!color))?;
+                self.clock = clock;
+                self.draw_offer = None;
+                self.takeback_request = None;
+            }
+            Advance::ClaimTimeout { .. } => {
+                let clock = self.clock.context("game is not timed")?;
+                ensure!(self.game.turn() == !color, "it is not the opponent's turn");
+                ensure!(
+                    clock.flagged(self.game.turn(), now),
+                    "opponent has not run out of time"
+                );
+                self.over = true;
+            }
+            Advance::Abort { .. } => {
+                ensure!(self.game.half_move() < 2, "game can no longer be aborted");
+                self.over = true;
+            }
+            Advance::ClaimDraw { .. } => {
+                self.game
+                    .claimable_draw()
+                    .context("no draw can be claimed in this position")?;
+                self.over = true;
+            }
+            Advance::Resign { .. } => self.over = true,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl<I: Indexer + Clone + Send + Sync + 'static> Indexer for EspressoIndexer<I> {
+    fn games_with_user(
+        &self,
+        address: Address,
+        after: Option<GameId>,
+    ) -> impl Stream<Item = message::Game> + Unpin {
+        self.finalized.games_with_user(address, after)
+    }
+
+    async fn games(
+        &self,
+        address: Address,
+        filter: GameFilter,
+    ) -> anyhow::Result<Vec<message::Game>> {
+        self.finalized.games(address, filter).await
+    }
+
+    fn moves(&self, id: GameId, from: u16) -> impl Stream<Item = MoveUpdate> + Unpin {
+        let polling = self.polling;
+        stream::unfold(
+            Some((self.clone(), None::<Vec<GameHash>>, polling)),
+            move |state| async move {
+                let (indexer, sent, mut polling) = state?;
+                let (moves, over) = match indexer.poll_moves(id).await {
+                    Ok(moves) => moves,
+                    Err(err) => {
+                        tracing::warn!("error in moves stream: {err:#}");
+                        polling.poll(false).await;
+                        return Some((stream::iter(vec![]), Some((indexer, sent, polling))));
+                    }
+                };
+
+                // Until we know better, assume the client has the same moves before `from` as we
+                // do.
+                let mut sent = sent.unwrap_or_else(|| {
+                    moves[..moves.len().min(from.saturating_sub(1) as usize)]
+                        .iter()
+                        .map(|(_, hash)| *hash)
+                        .collect()
+                });
+                let mut updates = vec![];
+                // If the client has moves we no longer do, rewind to the last move we agree on.
+                let common = sent
+                    .iter()
+                    .zip(&moves)
+                    .take_while(|(sent, (_, hash))| *sent == hash)
+                    .count();
+                if common < sent.len() {
+                    updates.push(MoveUpdate::TakeBack(common as u16));
+                    sent.truncate(common);
+                }
+                for (san, hash) in &moves[sent.len()..] {
+                    updates.push(MoveUpdate::Move(san.clone(), Some(*hash)));
+                    sent.push(*hash);
+                }
+
+                if over {
+                    return Some((stream::iter(updates), None));
+                }
+                polling.poll(!updates.is_empty()).await;
+                Some((stream::iter(updates), Some((indexer, Some(sent), polling))))
+            },
+        )
+        .flatten()
+        .boxed()
+    }
+
+    async fn state(&self, id: GameId) -> anyhow::Result<GameState> {
+        if let Some(optimistic) = self.state.lock().await.games.get(&id) {
+            return Ok(GameState {
+                id,
+                hash: optimistic.game.hash(),
+                half_move: optimistic.game.half_move(),
+            });
+        }
+        self.finalized.state(id).await
+    }
+
+    async fn pgn(&self, id: GameId) -> anyhow::Result<String> {
+        self.finalized.pgn(id).await
+    }
+
+    async fn user_stats(&self, address: Address) -> anyhow::Result<UserStats> {
+        self.finalized.user_stats(address).await
+    }
+
+    async fn draw_offers(&self, address: Address) -> anyhow::Result<Vec<DrawOffer>> {
+        self.finalized.draw_offers(address).await
+    }
+
+    async fn takeback_requests(&self, address: Address) -> anyhow::Result<Vec<TakebackRequest>> {
+        self.finalized.takeback_requests(address).await
+    }
+
+    async fn clock(&self, id: GameId) -> anyhow::Result<Option<Clock>> {
+        self.finalized.clock(id).await
+    }
+
+    async fn seeks(&self) -> anyhow::Result<Vec<Seek>> {
+        self.finalized.seeks().await
+    }
+
+    async fn incoming_challenges(&self, address: Address) -> anyhow::Result<Vec<Challenge>> {
+        self.finalized.incoming_challenges(address).await
+    }
+
+    async fn outgoing_challenges(&self, address: Address) -> anyhow::Result<Vec<Challenge>> {
+        self.finalized.outgoing_challenges(address).await
+    }
+
+    async fn puzzles(&self) -> anyhow::Result<Vec<Puzzle>> {
+        self.finalized.puzzles().await
+    }
+
+    async fn puzzle_solvers(&self, id: PuzzleId) -> anyhow::Result<Vec<PuzzleSolve>> {
+        self.finalized.puzzle_solvers(id).await
+    }
+
+    async fn puzzle_rankings(&self) -> anyhow::Result<Vec<PuzzleRanking>> {
+        self.finalized.puzzle_rankings().await
+    }
+
+    async fn balances(&self, address: Address) -> anyhow::Result<Vec<Stake>> {
+        self.finalized.balances(address).await
+    }
+
+    async fn tournaments(&self) -> anyhow::Result<Vec<Tournament>> {
+        self.finalized.tournaments().await
+    }
+
+    async fn pairings(&self, id: TournamentId, round: Option<u32>) -> anyhow::Result<Vec<Pairing>> {
+        self.finalized.pairings(id, round).await
+    }
+
+    async fn standings(&self, id: TournamentId) -> anyhow::Result<Vec<Standing>> {
+        self.finalized.standings(id).await
+    }
+
+    async fn rating_history(&self, address: Address) -> anyhow::Result<Vec<RatingChange>> {
+        self.finalized.rating_history(address).await
+    }
+
+    async fn leaderboard(
+        &self,
+        pool: Pool,
+        limit: u32,
+        min_games: u32,
+        max_deviation: f64,
+    ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+        self.finalized
+            .leaderboard(pool, limit, min_games, max_deviation)
+            .await
+    }
+}
+
+/// Every state `game` has passed through, from the starting position.
+fn history(game: &Game) -> HashSet<GameHash> {
+    let start = Game::with_setup(game.id(), game.white(), game.black(), game.setup().clone())
+        .expect("setup of a valid game is valid");
+    hashed_moves(game)
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .chain([start.hash()])
+        .collect()
+}
+
+/// Replay the moves of `game`, pairing each with the hash of the game state after the move.
+fn hashed_moves(game: &Game) -> Vec<(San, GameHash)> {
+    let mut replay = Game::with_setup(game.id(), game.white(), game.black(), game.setup().clone())
+        .expect("setup of a valid game is valid");
+    game.moves()
+        .iter()
+        .map(|san| {
+            replay
+                .play_next_move(san.clone())
+                .expect("moves of a valid game are valid");
+            (san.clone(), replay.hash())
+        })
+        .collect()
+}
+
+/// The header of an Espresso block, of which we only need the timestamp.
+///
+/// Depending on the version of the header, its fields are either nested under `fields` or given
+/// directly.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Header {
+    Versioned { fields: HeaderFields },
+    Legacy(HeaderFields),
+}
+
+#[derive(Debug, Deserialize)]
+struct HeaderFields {
+    timestamp: u64,
+}
+
+impl Header {
+    /// The time at which the block was sequenced, which the dApp sees as the timestamp of the
+    /// inputs in it.
+    fn timestamp(&self) -> u64 {
+        match self {
+            Self::Versioned { fields } | Self::Legacy(fields) => fields.timestamp,
+        }
+    }
+}
+
+/// The transactions in one namespace of an Espresso block.
+#[derive(Debug, Deserialize)]
+struct NamespaceBlock {
+    #[serde(default)]
+    transactions: Vec<Transaction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Transaction {
+    #[serde(deserialize_with = "base64_bytes")]
+    payload: Vec<u8>,
+}
+
+/// A [`CartesiMessage`] signed by the sender of the input.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignedMessage {
+    typed_data: TypedData,
+    signature: Bytes,
+}
+
+#[derive(Debug, Deserialize)]
+struct TypedData {
+    domain: Domain,
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Domain {
+    name: Option<String>,
+    version: Option<String>,
+    #[serde(default, deserialize_with = "optional_number")]
+    chain_id: Option<u64>,
+    verifying_contract: Option<Address>,
+    salt: Option<B256>,
+}
+
+impl From<Domain> for Eip712Domain {
+    fn from(domain: Domain) -> Self {
+        Self::new(
+            domain.name.map(Into::into),
+            domain.version.map(Into::into),
+            domain.chain_id.map(U256::from),
+            domain.verifying_contract,
+            domain.salt,
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    app: Address,
+    #[serde(deserialize_with = "number")]
+    nonce: u64,
+    #[serde(deserialize_with = "number")]
+    max_gas_price: u128,
+    data: Bytes,
+}
+
+impl From<Message> for CartesiMessage {
+    fn from(message: Message) -> Self {
+        Self {
+            app: message.app,
+            nonce: message.nonce,
+            max_gas_price: message.max_gas_price,
+            data: message.data,
+        }
+    }
+}
+
+fn base64_bytes<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(d)?;
+    BASE64.decode(s).map_err(serde::de::Error::custom)
+}
+
+fn optional_number<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+    number(d).map(Some)
+}
+
+/// Deserialize an integer, which may be given as a string or a number.
+fn number<'de, D, T>(d: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Number<T> {
+        Number(T),
+        String(String),
+    }
+    match Number::deserialize(d)? {
+        Number::Number(n) => Ok(n),
+        Number::String(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloy::signers::{local::PrivateKeySigner, SignerSync};
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response, Server,
+    };
+    use serde_json::{json, Value};
+    use std::{convert::Infallible, net::TcpListener};
+
+    const APP: Address = Address::repeat_byte(0xaa);
+    const NAMESPACE: u64 = 10008;
+    const CHAIN_ID: u64 = 31337;
+
+    /// A mock Espresso query service, serving blocks of transactions in a single namespace.
+    #[derive(Clone, Default)]
+    struct MockQueryService {
+        blocks: Arc<std::sync::Mutex<Vec<Vec<Vec<u8>>>>>,
+    }
+
+    impl MockQueryService {
+        /// Serve the query service on a local port, returning its URL.
+        fn start(&self) -> Url {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/", listener.local_addr().unwrap());
+            let service = self.clone();
+            let make_service = make_service_fn(move |_| {
+                let service = service.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        let response = service.handle(req);
+                        async move { Ok::<_, Infallible>(response) }
+                    }))
+                }
+            });
+            tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+            url.parse().unwrap()
+        }
+
+        /// Sequence a new block containing `transactions`.
+        fn sequence(&self, transactions: impl IntoIterator<Item = Vec<u8>>) {
+            let mut blocks = self.blocks.lock().unwrap();
+            blocks.push(transactions.into_iter().collect());
+        }
+
+        fn handle(&self, req: Request<Body>) -> Response<Body> {
+            let blocks = self.blocks.lock().unwrap();
+            let block = |height: &str| height.parse().ok().and_then(|h: usize| blocks.get(h));
+            let path: Vec<_> = req.uri().path().split('/').skip(1).collect();
+            let body = match path.as_slice() {
+                ["v0", "status", "block-height"] => Some(json!(blocks.len())),
+                ["v0", "availability", "header", height] => block(height)
+                    .map(|_| json!({ "fields": { "timestamp": height.parse::<u64>().unwrap() } })),
+                ["v0", "availability", "block", height, "namespace", namespace] => block(height)
+                    .map(|transactions| {
+                        let transactions: Vec<_> = transactions
+                            .iter()
+                            .filter(|_| *namespace == NAMESPACE.to_string())
+                            .map(|payload| json!({ "payload": BASE64.encode(payload) }))
+                            .collect();
+                        json!({ "transactions": transactions })
+                    }),
+                _ => None,
+            };
+            match body {
+                Some(body) => Response::new(body.to_string().into()),
+                None => Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())
+                    .unwrap(),
+            }
+        }
+    }
+
+    /// A mock finalized indexer, which only knows the moves of a single game.
+    #[derive(Clone, Default)]
+    struct MockFinalized {
+        pgn: Arc<std::sync::Mutex<String>>,
+    }
+
+    impl MockFinalized {
+        /// Finalize the current state of `game`.
+        fn finalize(&self, game: &Game) {
+            let summary = message::Game {
+                id: game.id(),
+                white: game.white(),
+                black: game.black(),
+                time_control: None,
+                result: None,
+                created_at: None,
+                setup: game.setup().clone(),
+                stake: None,
+            };
+            *self.pgn.lock().unwrap() =
+                pgn::export(&summary, game.moves().iter().map(San::to_string));
+        }
+    }
+
+    impl Indexer for MockFinalized {
+        fn games_with_user(
+            &self,
+            _address: Address,
+            _after: Option<GameId>,
+        ) -> impl Stream<Item = message::Game> + Unpin {
+            stream::empty()
+        }
+
+        async fn games(
+            &self,
+            _address: Address,
+            _filter: GameFilter,
+        ) -> anyhow::Result<Vec<message::Game>> {
+            unimplemented!()
+        }
+
+        fn moves(&self, _id: GameId, _from: u16) -> impl Stream<Item = MoveUpdate> + Unpin {
+            stream::empty()
+        }
+
+        async fn state(&self, _id: GameId) -> anyhow::Result<GameState> {
+            unimplemented!()
+        }
+
+        async fn pgn(&self, _id: GameId) -> anyhow::Result<String> {
+            Ok(self.pgn.lock().unwrap().clone())
+        }
+
+        async fn user_stats(&self, _address: Address) -> anyhow::Result<UserStats> {
+            unimplemented!()
+        }
+
+        async fn draw_offers(&self, _address: Address) -> anyhow::Result<Vec<DrawOffer>> {
+            Ok(vec![])
+        }
+
+        async fn takeback_requests(
+            &self,
+            _address: Address,
+        ) -> anyhow::Result<Vec<TakebackRequest>> {
+            Ok(vec![])
+        }
+
+        async fn clock(&self, _id: GameId) -> anyhow::Result<Option<Clock>> {
+            Ok(None)
+        }
+
+        async fn seeks(&self) -> anyhow::Result<Vec<Seek>> {
+            unimplemented!()
+        }
+
+        async fn incoming_challenges(&self, _address: Address) -> anyhow::Result<Vec<Challenge>> {
+            unimplemented!()
+        }
+
+        async fn outgoing_challenges(&self, _address: Address) -> anyhow::Result<Vec<Challenge>> {
+            unimplemented!()
+        }
+
+        async fn puzzles(&self) -> anyhow::Result<Vec<Puzzle>> {
+            unimplemented!()
+        }
+
+        async fn puzzle_solvers(&self, _id: PuzzleId) -> anyhow::Result<Vec<PuzzleSolve>> {
+            unimplemented!()
+        }
+
+        async fn puzzle_rankings(&self) -> anyhow::Result<Vec<PuzzleRanking>> {
+            unimplemented!()
+        }
+
+        async fn balances(&self, _address: Address) -> anyhow::Result<Vec<Stake>> {
+            unimplemented!()
+        }
+
+        async fn tournaments(&self) -> anyhow::Result<Vec<Tournament>> {
+            unimplemented!()
+        }
+
+        async fn pairings(
+            &self,
+            _id: TournamentId,
+            _round: Option<u32>,
+        ) -> anyhow::Result<Vec<Pairing>> {
+            unimplemented!()
+        }
+
+        async fn standings(&self, _id: TournamentId) -> anyhow::Result<Vec<Standing>> {
+            unimplemented!()
+        }
+
+        async fn rating_history(&self, _address: Address) -> anyhow::Result<Vec<RatingChange>> {
+            unimplemented!()
+        }
+
+        async fn leaderboard(
+            &self,
+            _pool: Pool,
+            _limit: u32,
+            _min_games: u32,
+            _max_deviation: f64,
+        ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+            unimplemented!()
+        }
+    }
+
+    /// A game between two fresh players, followed through a mock query service.
+    struct Fixture {
+        white: PrivateKeySigner,
+        black: PrivateKeySigner,
+        game: Game,
+        service: MockQueryService,
+        finalized: MockFinalized,
+        indexer: EspressoIndexer<MockFinalized>,
+    }
+
+    impl Fixture {
+        async fn new() -> Self {
+            let white = PrivateKeySigner::random();
+            let black = PrivateKeySigner::random();
+            let game = Game::new(GameId::from(1), white.address(), black.address());
+            let service = MockQueryService::default();
+            let finalized = MockFinalized::default();
+            finalized.finalize(&game);
+            let indexer =
+                EspressoIndexer::new(service.start(), NAMESPACE, APP, CHAIN_ID, finalized.clone());
+            // Start following the chain and the game.
+            assert_eq!(
+                indexer.poll_moves(game.id()).await.unwrap(),
+                (vec![], false)
+            );
+            Self {
+                white,
+                black,
+                game,
+                service,
+                finalized,
+                indexer,
+            }
+        }
+
+        /// Play `san` in the local copy of the game, returning the input which plays it.
+        fn play(&mut self, san: &str) -> Advance {
+            let advance = Advance::Move {
+                id: self.game.id(),
+                hash: self.game.hash(),
+                san: san.into(),
+            };
+            self.game.play_next_move(san.parse().unwrap()).unwrap();
+            advance
+        }
+
+        async fn poll(&self) -> (Vec<(San, GameHash)>, bool) {
+            self.indexer.poll_moves(self.game.id()).await.unwrap()
+        }
+    }
+
+    /// A transaction carrying `advance` to the dApp, signed by `signer` for the rollup on chain
+    /// `chain_id`.
+    fn transaction(
+        signer: &PrivateKeySigner,
+        chain_id: u64,
+        nonce: u64,
+        advance: &Advance,
+    ) -> Vec<u8> {
+        let message = CartesiMessage {
+            app: APP,
+            nonce,
+            max_gas_price: 0,
+            data: serde_json::to_vec(advance).unwrap().into(),
+        };
+        let domain = Eip712Domain::new(
+            Some(DOMAIN_NAME.into()),
+            Some(DOMAIN_VERSION.into()),
+            Some(U256::from(chain_id)),
+            Some(Address::ZERO),
+            None,
+        );
+        let signature = signer
+            .sign_hash_sync(&message.eip712_signing_hash(&domain))
+            .unwrap();
+        let tx: Value = json!({
+            "typedData": {
+                "domain": {
+                    "name": DOMAIN_NAME,
+                    "version": DOMAIN_VERSION,
+                    "chainId": chain_id,
+                    "verifyingContract": Address::ZERO,
+                },
+                "message": {
+                    "app": APP,
+                    "nonce": nonce,
+                    "max_gas_price": "0",
+                    "data": message.data,
+                },
+            },
+            "signature": Bytes::from(signature.as_bytes()),
+        });
+        serde_json::to_vec(&tx).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_optimistic_replay() {
+        let mut fixture = Fixture::new().await;
+        let id = fixture.game.id();
+        let hash = fixture.game.hash();
+
+        let e4 = fixture.play("e4");
+        let e5 = fixture.play("e5");
+        fixture.service.sequence([
+            transaction(&fixture.white, CHAIN_ID, 0, &e4),
+            // Signed for another chain.
+            transaction(&fixture.black, 1, 0, &e5),
+            // Accepting a takeback which was never requested.
+            transaction(
+                &fixture.black,
+                CHAIN_ID,
+                0,
+                &Advance::AcceptTakeback {
+                    id,
+                    hash: fixture.game.hash(),
+                },
+            ),
+        ]);
+        fixture.service.sequence([
+            // Replaying the first move of the game.
+            transaction(
+                &fixture.white,
+                CHAIN_ID,
+                0,
+                &Advance::Move {
+                    id,
+                    hash,
+                    san: "e4".into(),
+                },
+            ),
+            transaction(&fixture.black, CHAIN_ID, 1, &e5),
+        ]);
+        let nf3 = fixture.play("Nf3");
+        fixture.service.sequence([
+            // Reusing a nonce.
+            transaction(&fixture.white, CHAIN_ID, 0, &nf3),
+        ]);
+
+        let (moves, over) = fixture.poll().await;
+        let mut expected = hashed_moves(&fixture.game);
+        expected.pop();
+        assert_eq!(moves, expected);
+        assert!(!over);
+        assert!(!fixture.indexer.diverged(id).await);
+    }
+
+    #[tokio::test]
+    async fn test_catch_up() {
+        let mut fixture = Fixture::new().await;
+        let id = fixture.game.id();
+
+        // Moves sent directly to the rollup only show up in the finalized state.
+        fixture.play("e4");
+        fixture.play("e5");
+        fixture.finalized.finalize(&fixture.game);
+        assert_eq!(fixture.poll().await, (hashed_moves(&fixture.game), false));
+
+        // Optimistic moves are applied on top of the finalized state.
+        let nf3 = fixture.play("Nf3");
+        fixture
+            .service
+            .sequence([transaction(&fixture.white, CHAIN_ID, 0, &nf3)]);
+        assert_eq!(fixture.poll().await, (hashed_moves(&fixture.game), false));
+        assert!(!fixture.indexer.diverged(id).await);
+    }
+
+    #[tokio::test]
+    async fn test_divergence() {
+        let mut fixture = Fixture::new().await;
+        let id = fixture.game.id();
+
+        let e4 = fixture.play("e4");
+        fixture
+            .service
+            .sequence([transaction(&fixture.white, CHAIN_ID, 0, &e4)]);
+        assert_eq!(fixture.poll().await, (hashed_moves(&fixture.game), false));
+        assert!(!fixture.indexer.diverged(id).await);
+
+        // The finalized state disagrees, and takes precedence.
+        let mut finalized = Game::new(id, fixture.white.address(), fixture.black.address());
+        finalized.play_next_move("d4".parse().unwrap()).unwrap();
+        fixture.finalized.finalize(&finalized);
+        assert_eq!(fixture.poll().await, (hashed_moves(&finalized), false));
+        assert!(fixture.indexer.diverged(id).await);
+    }
+}
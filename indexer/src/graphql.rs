@@ -0,0 +1,705 @@
+//! An indexer which follows finalized rollup outputs through the GraphQL API of a Cartesi node.
+//!
+//! Rather than asking the dApp about its current state, this indexer pages through every input
+//! processed by the node, along with the notices each input produced, and replays them into its
+//! own database. Games are discovered from [`GameStarted`] notices and decisive results checked
+//! against [`Victory`] notices, while moves, draws, takebacks and aborts are reconstructed by
+//! decoding the [`Advance`] payloads of accepted inputs. Games which started before the dApp began
+//! emitting [`GameStarted`] notices are not indexed.
+//!
+//! Only games and statistics can be reconstructed this way. Queries for anything else (challenges,
+//! seeks, puzzles and so on) are answered by inspecting the node, as with [`InspectIndexer`].
+
+use crate::{
+    subscription::MoveEvent, Backoff, Indexer, InspectIndexer, MoveUpdate,
+    DEFAULT_MAX_POLLING_INTERVAL, DEFAULT_POLLING_INTERVAL,
+};
+use alloy::{
+    primitives::{Address, U256},
+    sol_types::SolEvent,
+};
+use anyhow::{bail, ensure, Context};
+use chesspresso_core::{
+    clock::Clock,
+    db::Db,
+    game::{Game, GameHash, GameId, GameSetup, Outcome, Score},
+    message::{
+        self, Advance, Challenge, DrawOffer, GameFilter, GameState, LeaderboardEntry, Pairing,
+        Puzzle, PuzzleId, PuzzleRanking, PuzzleSolve, RatingChange, Seek, Standing,
+        TakebackRequest, Tournament, TournamentId, UserStats,
+    },
+    notice::{GameStarted, Victory},
+    rating::{Pool, RatingSystem},
+    wager::{Asset, Stake},
+};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use hyper::{client::connect::HttpConnector, Body, Client, Method, Request};
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use tokio::{spawn, sync::Mutex};
+use url::Url;
+
+/// The number of inputs to request per page.
+const PAGE_SIZE: u32 = 100;
+
+const INPUTS_QUERY: &str = "
+    query inputs($first: Int, $after: String) {
+        inputs(first: $first, after: $after) {
+            pageInfo { hasNextPage }
+            edges {
+                cursor
+                node {
+                    index
+                    status
+                    timestamp
+                    msgSender
+                    payload
+                    notices { edges { cursor node { payload } } }
+                }
+            }
+        }
+    }
+";
+
+#[derive(Clone, Debug)]
+pub struct GraphQlIndexer {
+    client: Client<HttpConnector>,
+    graphql_url: Url,
+    polling: Backoff,
+    inspect: InspectIndexer,
+    state: Arc<Mutex<State>>,
+    /// Whether the database is kept in sync by a background task, rather than before each query.
+    background: bool,
+}
+
+/// The games reconstructed so far.
+#[derive(Debug)]
+struct State {
+    db: Db,
+    /// The cursor of the last input replayed.
+    cursor: Option<String>,
+}
+
+impl GraphQlIndexer {
+    /// Follow the node at `node_url`.
+    ///
+    /// Ratings are recomputed from game results, so `rating_system` must match the one used by the
+    /// dApp.
+    pub async fn new(node_url: Url, rating_system: RatingSystem) -> anyhow::Result<Self> {
+        Self::with_db(node_url, Db::memory().await?, rating_system).await
+    }
+
+    /// Follow the node at `node_url`, reconstructing games in `db`.
+    ///
+    /// If `db` was used to follow the same node before, replay resumes from the last input replayed
+    /// into it, rather than from the first input.
+    pub async fn with_db(
+        node_url: Url,
+        mut db: Db,
+        rating_system: RatingSystem,
+    ) -> anyhow::Result<Self> {
+        db.set_rating_system(rating_system);
+        let cursor = db.graphql_cursor().await?;
+        if let Some(cursor) = &cursor {
+            tracing::info!(cursor, "resuming replay");
+        }
+        Ok(Self {
+            client: Client::new(),
+            graphql_url: node_url.join("graphql")?,
+            polling: Backoff::new(DEFAULT_POLLING_INTERVAL, DEFAULT_MAX_POLLING_INTERVAL),
+            inspect: InspectIndexer::new(node_url),
+            state: Arc::new(Mutex::new(State { db, cursor })),
+            background: false,
+        })
+    }
+
+    /// Poll for new inputs every `min` while there is activity, backing off to every `max` while
+    /// the node is idle.
+    pub fn with_polling_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.polling = Backoff::new(min, max);
+        self.inspect = self.inspect.with_polling_interval(min, max);
+        self
+    }
+
+    /// Keep up with the node in a background task, instead of syncing before each query.
+    ///
+    /// This is useful when many clients share one indexer, so that each query is answered from the
+    /// database without waiting for the node.
+    pub fn sync_in_background(mut self) -> Self {
+        self.background = true;
+        let indexer = self.clone();
+        spawn(async move {
+            let mut polling = indexer.polling;
+            loop {
+                let replayed = match indexer.replay_new_inputs().await {
+                    Ok(replayed) => replayed,
+                    Err(err) => {
+                        tracing::warn!("error syncing with node: {err:#}");
+                        0
+                    }
+                };
+                polling.poll(replayed > 0).await;
+            }
+        });
+        self
+    }
+
+    /// Make sure the database is up to date before answering a query.
+    async fn sync(&self) -> anyhow::Result<()> {
+        if !self.background {
+            self.replay_new_inputs().await?;
+        }
+        Ok(())
+    }
+
+    /// Replay all inputs processed by the node since the last sync.
+    ///
+    /// Returns the number of inputs replayed.
+    async fn replay_new_inputs(&self) -> anyhow::Result<usize> {
+        let mut state = self.state.lock().await;
+        let mut replayed = 0;
+        loop {
+            let inputs = self.inputs(state.cursor.as_deref()).await?;
+            for edge in inputs.edges {
+                let input = edge.node;
+                // Outputs are not available until the input has been processed.
+                if input.status == "UNPROCESSED" {
+                    return Ok(replayed);
+                }
+                // Apply each input atomically, along with the cursor, so that the database can be
+                // reused after a restart. If an input can't be replayed, stop rather than skip it,
+                // so that the reconstructed games never silently drift from the dApp's.
+                let index = input.index;
+                state.db.begin().await?;
+                let res = match state.replay(input).await {
+                    Ok(()) => state.db.set_graphql_cursor(&edge.cursor).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = res {
+                    state.db.rollback().await?;
+                    return Err(err.context(format!("replaying input {index}")));
+                }
+                state.db.commit().await?;
+                state.cursor = Some(edge.cursor);
+                replayed += 1;
+            }
+            if !inputs.page_info.has_next_page {
+                return Ok(replayed);
+            }
+        }
+    }
+
+    /// A game, as far as it has been reconstructed.
+    pub async fn game(&self, id: GameId) -> anyhow::Result<message::Game> {
+        self.sync().await?;
+        self.state.lock().await.db.game_info(id).await
+    }
+
+    /// The moves of game `id`, starting from half move `from`, each with the hash of the game state
+    /// after the move.
+    pub async fn move_list(
+        &self,
+        id: GameId,
+        from: u16,
+    ) -> anyhow::Result<Vec<(String, GameHash)>> {
+        self.sync().await?;
+        self.state.lock().await.db.moves_with_hashes(id, from).await
+    }
+
+    /// New games involving `address`, after game `after`.
+    pub fn game_events(
+        &self,
+        address: Address,
+        after: Option<GameId>,
+    ) -> impl Stream<Item = message::Game> + Send + Unpin {
+        let polling = self.polling;
+        stream::unfold(
+            (self.clone(), after, polling),
+            move |(indexer, after, mut polling)| async move {
+                let games = match indexer.games_after(address, after).await {
+                    Ok(games) => games,
+                    Err(err) => {
+                        tracing::warn!("error in games stream: {err:#}");
+                        vec![]
+                    }
+                };
+                // Only wait for new inputs once we have caught up with the node.
+                polling.poll(!games.is_empty()).await;
+                let after = games.last().map(|game| Some(game.id)).unwrap_or(after);
+
+                Some((stream::iter(games), (indexer, after, polling)))
+            },
+        )
+        .flatten()
+        .boxed()
+    }
+
+    /// Changes to the moves of game `id`, for a client which has seen the moves before `from` and
+    /// knows about `takebacks` takebacks.
+    ///
+    /// The stream ends with [`MoveEvent::GameOver`] once the game is over.
+    pub fn move_events(
+        &self,
+        id: GameId,
+        from: u16,
+        takebacks: u32,
+    ) -> impl Stream<Item = MoveEvent> + Send + Unpin {
+        let polling = self.polling;
+        stream::unfold(
+            Some((self.clone(), from, takebacks, polling)),
+            move |state| async move {
+                let (indexer, from, takebacks, mut polling) = state?;
+                let (mut events, over, from, takebacks) =
+                    match indexer.move_updates(id, from, takebacks).await {
+                        Ok(updates) => updates,
+                        Err(err) => {
+                            tracing::warn!("error in moves stream: {err:#}");
+                            (vec![], false, from, takebacks)
+                        }
+                    };
+                if over {
+                    events.push(MoveEvent::GameOver);
+                    return Some((stream::iter(events), None));
+                }
+                polling.poll(!events.is_empty()).await;
+                Some((
+                    stream::iter(events),
+                    Some((indexer, from, takebacks, polling)),
+                ))
+            },
+        )
+        .flatten()
+        .boxed()
+    }
+
+    async fn games_after(
+        &self,
+        address: Address,
+        after: Option<GameId>,
+    ) -> anyhow::Result<Vec<message::Game>> {
+        self.sync().await?;
+        let mut state = self.state.lock().await;
+        state
+            .db
+            .games(address, after, GameFilter::All)
+            .try_collect()
+            .await
+    }
+
+    /// Changes to the moves of game `id`, for a client which has seen the moves before `from` and
+    /// knows about `takebacks` takebacks.
+    ///
+    /// Returns the updates, whether the game is over, and the new values of `from` and `takebacks`.
+    async fn move_updates(
+        &self,
+        id: GameId,
+        mut from: u16,
+        takebacks: u32,
+    ) -> anyhow::Result<(Vec<MoveEvent>, bool, u16, u32)> {
+        self.sync().await?;
+        let mut state = self.state.lock().await;
+
+        let mut updates = vec![];
+        let (takebacks, min) = state.db.takebacks(id, takebacks).await?;
+        if let Some(half_move) = min.filter(|half_move| *half_move < from.saturating_sub(1)) {
+            updates.push(MoveEvent::TakeBack {
+                half_move,
+                takebacks,
+            });
+            from = half_move + 1;
+        }
+        let moves = state.db.moves_with_hashes(id, from).await?;
+        from += moves.len() as u16;
+        updates.extend(moves.into_iter().map(|(san, hash)| MoveEvent::Move {
+            san,
+            hash: Some(hash),
+        }));
+
+        // The game may not have started yet, as far as we know.
+        let over = match state.db.game_info(id).await {
+            Ok(game) => game.result.is_some(),
+            Err(_) => false,
+        };
+        Ok((updates, over, from, takebacks))
+    }
+
+    /// Fetch a page of inputs after `cursor`.
+    async fn inputs(&self, cursor: Option<&str>) -> anyhow::Result<Connection<Input>> {
+        let body = json!({
+            "query": INPUTS_QUERY,
+            "variables": { "first": PAGE_SIZE, "after": cursor },
+        });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.graphql_url.as_str())
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))?;
+        let response = self.client.request(request).await?;
+        ensure!(
+            response.status().is_success(),
+            "{}: GraphQL error: {}",
+            self.graphql_url,
+            response.status()
+        );
+
+        let body = hyper::body::to_bytes(response).await?;
+        let response: Response<InputsData> = serde_json::from_slice(&body)?;
+        if let Some(err) = response.errors.first() {
+            bail!("GraphQL error: {}", err.message);
+        }
+        Ok(response.data.context("missing data")?.inputs)
+    }
+}
+
+impl State {
+    /// Apply a processed input to the reconstructed games.
+    async fn replay(&mut self, input: Input) -> anyhow::Result<()> {
+        // A rejected input reverts all of its effects on the dApp state.
+        if input.status != "ACCEPTED" {
+            return Ok(());
+        }
+        // The dApp applies finished rating periods before handling each input.
+        self.db.update_ratings(input.timestamp).await?;
+
+        // Inputs which are not messages, such as deposits from the portals, are ignored.
+        let payload = hex::decode(strip_hex_prefix(&input.payload))?;
+        if let Ok(advance) = serde_json::from_slice(&payload) {
+            self.advance(input.msg_sender, input.timestamp, advance)
+                .await?;
+        }
+        for edge in input.notices.edges {
+            let payload = hex::decode(strip_hex_prefix(&edge.node.payload))?;
+            self.notice(input.timestamp, &payload).await?;
+        }
+        Ok(())
+    }
+
+    async fn advance(
+        &mut self,
+        sender: Address,
+        timestamp: u64,
+        advance: Advance,
+    ) -> anyhow::Result<()> {
+        let id = match &advance {
+            Advance::Move { id, .. }
+            | Advance::Resign { id, .. }
+            | Advance::Abort { id, .. }
+            | Advance::ClaimDraw { id, .. }
+            | Advance::AcceptDraw { id, .. }
+            | Advance::AcceptTakeback { id, .. }
+            | Advance::ClaimTimeout { id, .. } => *id,
+            _ => return Ok(()),
+        };
+        // Skip games which started before they could be indexed.
+        let Ok(mut game) = self.db.active_game(id).await else {
+            return Ok(());
+        };
+        let color = game
+            .player_color(sender)
+            .context("player is not in this game")?;
+
+        let outcome = match advance {
+            Advance::Move { san, .. } => {
+                let m = game.play_next_move(san.parse()?)?;
+                self.db.record_move(id, m).await?;
+                game.outcome()
+            }
+            Advance::Resign { .. } => Some(Outcome::Resignation {
+                winner: game.player(!color),
+                loser: sender,
+            }),
+            Advance::Abort { .. } => {
+                self.db.end_game(&game, None, timestamp).await?;
+                return Ok(());
+            }
+            Advance::ClaimDraw { .. } => Some(
+                game.claimable_draw()
+                    .context("no draw can be claimed in this position")?,
+            ),
+            Advance::AcceptDraw { .. } => Some(Outcome::Draw),
+            Advance::AcceptTakeback { .. } => {
+                game.take_back(game.takeback_len(!color))?;
+                self.db.take_back(id, game.half_move()).await?;
+                None
+            }
+            Advance::ClaimTimeout { .. } => Some(game.timeout(game.turn())),
+            _ => None,
+        };
+        if let Some(outcome) = outcome {
+            self.db.end_game(&game, Some(outcome), timestamp).await?;
+        }
+        Ok(())
+    }
+
+    async fn notice(&mut self, timestamp: u64, payload: &[u8]) -> anyhow::Result<()> {
+        ensure!(payload.len() >= 32, "malformed notice");
+        let (selector, data) = payload.split_at(32);
+
+        if selector == GameStarted::SIGNATURE_HASH.as_slice() {
+            let started = GameStarted::decode_raw_log([GameStarted::SIGNATURE_HASH], data, true)?;
+            self.start_game(started, timestamp).await
+        } else if selector == Victory::SIGNATURE_HASH.as_slice() {
+            let victory = Victory::decode_raw_log([Victory::SIGNATURE_HASH], data, true)?;
+            let id = victory.id.into();
+            let Ok(game) = self.db.game_info(id).await else {
+                return Ok(());
+            };
+            // The result should already have been reconstructed from the input which ended the
+            // game.
+            let winner = match game.result.and_then(|result| result.score) {
+                Some(Score::WhiteWins) => Some(game.white),
+                Some(Score::BlackWins) => Some(game.black),
+                _ => None,
+            };
+            if winner != Some(victory.winner) {
+                tracing::warn!(
+                    %id,
+                    ?winner,
+                    %victory.winner,
+                    "reconstructed result disagrees with victory notice"
+                );
+            }
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn start_game(&mut self, started: GameStarted, timestamp: u64) -> anyhow::Result<()> {
+        let id = GameId::from(started.id);
+        let setup = GameSetup {
+            variant: started.variant.parse()?,
+            fen: Some(started.fen).filter(|fen| !fen.is_empty()),
+        };
+        let time_control = Some(started.timeControl)
+            .filter(|tc| !tc.is_empty())
+            .map(|tc| tc.parse())
+            .transpose()?;
+        let clock = time_control.map(|tc| Clock::new(tc, timestamp));
+        let mut game: Game = self
+            .db
+            .new_game_with_id(id, started.white, started.black, setup, clock, timestamp)
+            .await?;
+
+        if started.stakeAmount != U256::ZERO {
+            let asset = if started.stakeAsset == Address::ZERO {
+                Asset::Ether
+            } else {
+                Asset::Erc20(started.stakeAsset)
+            };
+            self.db
+                .set_stake(
+                    id,
+                    Stake {
+                        asset,
+                        amount: started.stakeAmount,
+                    },
+                )
+                .await?;
+        }
+        if !started.firstMove.is_empty() {
+            let m = game.play_next_move(started.firstMove.parse()?)?;
+            self.db.record_move(id, m).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Indexer for GraphQlIndexer {
+    fn games_with_user(
+        &self,
+        address: Address,
+        after: Option<GameId>,
+    ) -> impl Stream<Item = message::Game> + Unpin {
+        self.game_events(address, after)
+    }
+
+    async fn games(
+        &self,
+        address: Address,
+        filter: GameFilter,
+    ) -> anyhow::Result<Vec<message::Game>> {
+        self.sync().await?;
+        let mut state = self.state.lock().await;
+        state.db.games(address, None, filter).try_collect().await
+    }
+
+    fn moves(&self, id: GameId, from: u16) -> impl Stream<Item = MoveUpdate> + Unpin {
+        self.move_events(id, from, 0)
+            .filter_map(|event| async move {
+                match event {
+                    MoveEvent::Move { san, hash } => match san.parse() {
+                        Ok(san) => Some(MoveUpdate::Move(san, hash)),
+                        Err(err) => {
+                            tracing::warn!(san, "error parsing move: {err:#}");
+                            None
+                        }
+                    },
+                    MoveEvent::TakeBack { half_move, .. } => Some(MoveUpdate::TakeBack(half_move)),
+                    MoveEvent::GameOver => None,
+                }
+            })
+            .boxed()
+    }
+
+    async fn state(&self, id: GameId) -> anyhow::Result<GameState> {
+        self.sync().await?;
+        let game = self.state.lock().await.db.game(id).await?;
+        Ok(GameState {
+            id,
+            hash: game.hash(),
+            half_move: game.half_move(),
+        })
+    }
+
+    async fn pgn(&self, id: GameId) -> anyhow::Result<String> {
+        self.sync().await?;
+        self.state.lock().await.db.pgn(id).await
+    }
+
+    async fn user_stats(&self, address: Address) -> anyhow::Result<UserStats> {
+        self.sync().await?;
+        self.state.lock().await.db.user_stats(address).await
+    }
+
+    async fn draw_offers(&self, address: Address) -> anyhow::Result<Vec<DrawOffer>> {
+        self.inspect.draw_offers(address).await
+    }
+
+    async fn takeback_requests(&self, address: Address) -> anyhow::Result<Vec<TakebackRequest>> {
+        self.inspect.takeback_requests(address).await
+    }
+
+    async fn clock(&self, id: GameId) -> anyhow::Result<Option<Clock>> {
+        self.inspect.clock(id).await
+    }
+
+    async fn seeks(&self) -> anyhow::Result<Vec<Seek>> {
+        self.inspect.seeks().await
+    }
+
+    async fn incoming_challenges(&self, address: Address) -> anyhow::Result<Vec<Challenge>> {
+        self.inspect.incoming_challenges(address).await
+    }
+
+    async fn outgoing_challenges(&self, address: Address) -> anyhow::Result<Vec<Challenge>> {
+        self.inspect.outgoing_challenges(address).await
+    }
+
+    async fn puzzles(&self) -> anyhow::Result<Vec<Puzzle>> {
+        self.inspect.puzzles().await
+    }
+
+    async fn puzzle_solvers(&self, id: PuzzleId) -> anyhow::Result<Vec<PuzzleSolve>> {
+        self.inspect.puzzle_solvers(id).await
+    }
+
+    async fn puzzle_rankings(&self) -> anyhow::Result<Vec<PuzzleRanking>> {
+        self.inspect.puzzle_rankings().await
+    }
+
+    async fn balances(&self, address: Address) -> anyhow::Result<Vec<Stake>> {
+        self.inspect.balances(address).await
+    }
+
+    async fn tournaments(&self) -> anyhow::Result<Vec<Tournament>> {
+        self.inspect.tournaments().await
+    }
+
+    async fn pairings(&self, id: TournamentId, round: Option<u32>) -> anyhow::Result<Vec<Pairing>> {
+        self.inspect.pairings(id, round).await
+    }
+
+    async fn standings(&self, id: TournamentId) -> anyhow::Result<Vec<Standing>> {
+        self.inspect.standings(id).await
+    }
+
+    async fn rating_history(&self, address: Address) -> anyhow::Result<Vec<RatingChange>> {
+        self.inspect.rating_history(address).await
+    }
+
+    async fn leaderboard(
+        &self,
+        pool: Pool,
+        limit: u32,
+        min_games: u32,
+        max_deviation: f64,
+    ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+        self.inspect
+            .leaderboard(pool, limit, min_games, max_deviation)
+            .await
+    }
+}
+
+fn strip_hex_prefix(s: &str) -> &str {
+    s.strip_prefix("0x").unwrap_or(s)
+}
+
+#[derive(Debug, Deserialize)]
+struct Response<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct InputsData {
+    inputs: Connection<Input>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection<T> {
+    #[serde(default)]
+    page_info: PageInfo,
+    edges: Vec<Edge<T>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Edge<T> {
+    cursor: String,
+    node: T,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Input {
+    index: u64,
+    status: String,
+    #[serde(deserialize_with = "big_int")]
+    timestamp: u64,
+    msg_sender: Address,
+    payload: String,
+    notices: Connection<Notice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Notice {
+    payload: String,
+}
+
+/// Deserialize a GraphQL `BigInt`, which may be given as a string or a number.
+fn big_int<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BigInt {
+        Number(u64),
+        String(String),
+    }
+    match BigInt::deserialize(d)? {
+        BigInt::Number(n) => Ok(n),
+        BigInt::String(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
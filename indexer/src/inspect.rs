@@ -1,22 +1,28 @@
-use crate::Indexer;
+use crate::{Backoff, Indexer, MoveUpdate, DEFAULT_MAX_POLLING_INTERVAL, DEFAULT_POLLING_INTERVAL};
 use alloy::primitives::Address;
 use anyhow::{bail, ensure, Context};
 use chesspresso_core::{
+    clock::Clock,
     game::{GameId, San},
-    message::{Game, Report, UserStats},
+    message::{
+        Challenge, DrawOffer, Game, GameFilter, GameState, LeaderboardEntry, Pairing, Puzzle,
+        PuzzleId, PuzzleRanking, PuzzleSolve, RatingChange, Report, Seek, Standing,
+        TakebackRequest, Tournament, TournamentId, UserStats,
+    },
+    rating::Pool,
+    wager::Stake,
 };
 use futures::stream::{self, Stream, StreamExt};
 use hyper::{client::connect::HttpConnector, Client, Method, Request};
 use serde_json::{Map, Value};
 use std::time::Duration;
-use tokio::time::sleep;
 use url::Url;
 
 #[derive(Clone, Debug)]
 pub struct InspectIndexer {
     client: Client<HttpConnector>,
     node_url: Url,
-    polling_interval: Duration,
+    polling: Backoff,
 }
 
 impl InspectIndexer {
@@ -24,10 +30,17 @@ impl InspectIndexer {
         Self {
             client: Client::new(),
             node_url,
-            polling_interval: Duration::from_secs(2),
+            polling: Backoff::new(DEFAULT_POLLING_INTERVAL, DEFAULT_MAX_POLLING_INTERVAL),
         }
     }
 
+    /// Poll for new games and moves every `min` while there is activity, backing off to every `max`
+    /// while the node is idle.
+    pub fn with_polling_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.polling = Backoff::new(min, max);
+        self
+    }
+
     async fn inspect(&self, endpoint: &str) -> anyhow::Result<Report> {
         let url = format!("{}inspect/{endpoint}", &self.node_url);
         let request = Request::builder()
@@ -70,65 +83,267 @@ impl Indexer for InspectIndexer {
         address: Address,
         after: Option<GameId>,
     ) -> impl Stream<Item = Game> + Unpin {
-        stream::unfold((self.clone(), after), move |(indexer, after)| async move {
-            sleep(indexer.polling_interval).await;
-
-            let mut request = format!("games/{address}");
-            if let Some(after) = after {
-                request = format!("{request}/{after}");
-            }
-            let games = match indexer.inspect(&request).await {
-                Ok(Report::Games { games }) => games,
-                Ok(report) => {
-                    tracing::warn!(?report, "unexpected report, expected games");
-                    return Some((stream::iter(vec![]), (indexer, after)));
+        let polling = self.polling;
+        stream::unfold(
+            (self.clone(), after, polling),
+            move |(indexer, after, mut polling)| async move {
+                let mut request = format!("games/{address}");
+                if let Some(after) = after {
+                    request = format!("{request}/{after}");
                 }
-                Err(err) => {
-                    tracing::warn!("error in games stream: {err:#}");
-                    return Some((stream::iter(vec![]), (indexer, after)));
-                }
-            };
-            let after = games.last().map(|game| Some(game.id)).unwrap_or(after);
+                let games = match indexer.inspect(&request).await {
+                    Ok(Report::Games { games }) => games,
+                    Ok(report) => {
+                        tracing::warn!(?report, "unexpected report, expected games");
+                        vec![]
+                    }
+                    Err(err) => {
+                        tracing::warn!("error in games stream: {err:#}");
+                        vec![]
+                    }
+                };
+                polling.poll(!games.is_empty()).await;
+                let after = games.last().map(|game| Some(game.id)).unwrap_or(after);
 
-            Some((stream::iter(games), (indexer, after)))
-        })
+                Some((stream::iter(games), (indexer, after, polling)))
+            },
+        )
         .flatten()
         .boxed()
     }
 
-    fn moves(&self, id: GameId, from: u16) -> impl Stream<Item = San> + Unpin {
-        stream::unfold((self.clone(), from), move |(indexer, from)| async move {
-            sleep(indexer.polling_interval).await;
+    async fn games(&self, address: Address, filter: GameFilter) -> anyhow::Result<Vec<Game>> {
+        match self.inspect(&format!("games/{address}/{filter}")).await? {
+            Report::Games { games } => Ok(games),
+            report => bail!("unexpected report, expected games: {report:?}"),
+        }
+    }
+
+    fn moves(&self, id: GameId, from: u16) -> impl Stream<Item = MoveUpdate> + Unpin {
+        let polling = self.polling;
+        // The stream ends once the game is over, after yielding its last moves.
+        stream::unfold(
+            Some((self.clone(), from, 0, polling)),
+            move |state| async move {
+                let (indexer, from, takebacks, mut polling) = state?;
+                let request = format!("moves/{id}/{from}/{takebacks}");
+                let (moves, hashes, new_takebacks, taken_back_to, over) =
+                    match indexer.inspect(&request).await {
+                        Ok(Report::Moves {
+                            moves,
+                            hashes,
+                            takebacks,
+                            taken_back_to,
+                            over,
+                        }) => (moves, hashes, takebacks, taken_back_to, over),
+                        Ok(Report::Aborted { .. }) => {
+                            tracing::info!(%id, "game aborted");
+                            return None;
+                        }
+                        Ok(report) => {
+                            tracing::warn!(?report, "unexpected report, expected moves");
+                            polling.poll(false).await;
+                            return Some((
+                                stream::iter(vec![]),
+                                Some((indexer, from, takebacks, polling)),
+                            ));
+                        }
+                        Err(err) => {
+                            tracing::warn!("error in moves stream: {err:#}");
+                            polling.poll(false).await;
+                            return Some((
+                                stream::iter(vec![]),
+                                Some((indexer, from, takebacks, polling)),
+                            ));
+                        }
+                    };
+                let moves: Vec<San> = match moves.into_iter().map(|san| san.parse()).collect() {
+                    Ok(moves) => moves,
+                    Err(err) => {
+                        tracing::warn!("error parsing moves: {err:#}");
+                        polling.poll(false).await;
+                        return Some((
+                            stream::iter(vec![]),
+                            Some((indexer, from, takebacks, polling)),
+                        ));
+                    }
+                };
 
-            let moves = match indexer.inspect(&format!("moves/{id}/{from}")).await {
-                Ok(Report::Moves { moves }) => moves,
-                Ok(report) => {
-                    tracing::warn!(?report, "unexpected report, expected moves");
-                    return Some((stream::iter(vec![]), (indexer, from)));
+                let mut updates = vec![];
+                let mut from = from;
+                if let Some(half_move) = taken_back_to {
+                    updates.push(MoveUpdate::TakeBack(half_move));
+                    from = half_move + 1;
                 }
-                Err(err) => {
-                    tracing::warn!("error in moves stream: {err:#}");
-                    return Some((stream::iter(vec![]), (indexer, from)));
+                from += moves.len() as u16;
+                updates.extend(
+                    moves
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, san)| MoveUpdate::Move(san, hashes.get(i).copied())),
+                );
+                if over {
+                    tracing::info!(%id, "game over");
+                    return Some((stream::iter(updates), None));
                 }
-            };
-            let moves: Vec<San> = match moves.into_iter().map(|san| san.parse()).collect() {
-                Ok(moves) => moves,
-                Err(err) => {
-                    tracing::warn!("error parsing moves: {err:#}");
-                    return Some((stream::iter(vec![]), (indexer, from)));
-                }
-            };
-            let from = from + (moves.len() as u16);
-            Some((stream::iter(moves), (indexer, from)))
-        })
+                polling.poll(!updates.is_empty()).await;
+                Some((
+                    stream::iter(updates),
+                    Some((indexer, from, new_takebacks, polling)),
+                ))
+            },
+        )
         .flatten()
         .boxed()
     }
 
+    async fn state(&self, id: GameId) -> anyhow::Result<GameState> {
+        match self.inspect(&format!("state/{id}")).await? {
+            Report::State { state } => Ok(state),
+            report => bail!("unexpected report, expected game state: {report:?}"),
+        }
+    }
+
+    async fn pgn(&self, id: GameId) -> anyhow::Result<String> {
+        match self.inspect(&format!("pgn/{id}")).await? {
+            Report::Pgn { pgn } => Ok(pgn),
+            report => bail!("unexpected report, expected PGN: {report:?}"),
+        }
+    }
+
     async fn user_stats(&self, address: Address) -> anyhow::Result<UserStats> {
         match self.inspect(&format!("stats/{address}")).await? {
             Report::UserStats { stats } => Ok(stats),
             report => bail!("unexpected report, expected user stats: {report:?}"),
         }
     }
+
+    async fn draw_offers(&self, address: Address) -> anyhow::Result<Vec<DrawOffer>> {
+        match self.inspect(&format!("draw-offers/{address}")).await? {
+            Report::DrawOffers { offers } => Ok(offers),
+            report => bail!("unexpected report, expected draw offers: {report:?}"),
+        }
+    }
+
+    async fn takeback_requests(&self, address: Address) -> anyhow::Result<Vec<TakebackRequest>> {
+        match self
+            .inspect(&format!("takeback-requests/{address}"))
+            .await?
+        {
+            Report::TakebackRequests { requests } => Ok(requests),
+            report => bail!("unexpected report, expected takeback requests: {report:?}"),
+        }
+    }
+
+    async fn clock(&self, id: GameId) -> anyhow::Result<Option<Clock>> {
+        match self.inspect(&format!("clock/{id}")).await? {
+            Report::Clock { clock } => Ok(clock),
+            report => bail!("unexpected report, expected clock: {report:?}"),
+        }
+    }
+
+    async fn seeks(&self) -> anyhow::Result<Vec<Seek>> {
+        match self.inspect("seeks").await? {
+            Report::Seeks { seeks } => Ok(seeks),
+            report => bail!("unexpected report, expected seeks: {report:?}"),
+        }
+    }
+
+    async fn incoming_challenges(&self, address: Address) -> anyhow::Result<Vec<Challenge>> {
+        match self
+            .inspect(&format!("challenges/incoming/{address}"))
+            .await?
+        {
+            Report::Challenges { challenges } => Ok(challenges),
+            report => bail!("unexpected report, expected challenges: {report:?}"),
+        }
+    }
+
+    async fn outgoing_challenges(&self, address: Address) -> anyhow::Result<Vec<Challenge>> {
+        match self
+            .inspect(&format!("challenges/outgoing/{address}"))
+            .await?
+        {
+            Report::Challenges { challenges } => Ok(challenges),
+            report => bail!("unexpected report, expected challenges: {report:?}"),
+        }
+    }
+
+    async fn puzzles(&self) -> anyhow::Result<Vec<Puzzle>> {
+        match self.inspect("puzzles").await? {
+            Report::Puzzles { puzzles } => Ok(puzzles),
+            report => bail!("unexpected report, expected puzzles: {report:?}"),
+        }
+    }
+
+    async fn puzzle_solvers(&self, id: PuzzleId) -> anyhow::Result<Vec<PuzzleSolve>> {
+        match self.inspect(&format!("puzzle-solvers/{id}")).await? {
+            Report::PuzzleSolvers { solvers } => Ok(solvers),
+            report => bail!("unexpected report, expected puzzle solvers: {report:?}"),
+        }
+    }
+
+    async fn puzzle_rankings(&self) -> anyhow::Result<Vec<PuzzleRanking>> {
+        match self.inspect("puzzle-rankings").await? {
+            Report::PuzzleRankings { rankings } => Ok(rankings),
+            report => bail!("unexpected report, expected puzzle rankings: {report:?}"),
+        }
+    }
+
+    async fn balances(&self, address: Address) -> anyhow::Result<Vec<Stake>> {
+        match self.inspect(&format!("balances/{address}")).await? {
+            Report::Balances { balances } => Ok(balances),
+            report => bail!("unexpected report, expected balances: {report:?}"),
+        }
+    }
+
+    async fn tournaments(&self) -> anyhow::Result<Vec<Tournament>> {
+        match self.inspect("tournaments").await? {
+            Report::Tournaments { tournaments } => Ok(tournaments),
+            report => bail!("unexpected report, expected tournaments: {report:?}"),
+        }
+    }
+
+    async fn pairings(&self, id: TournamentId, round: Option<u32>) -> anyhow::Result<Vec<Pairing>> {
+        let route = match round {
+            Some(round) => format!("pairings/{id}/{round}"),
+            None => format!("pairings/{id}"),
+        };
+        match self.inspect(&route).await? {
+            Report::Pairings { pairings } => Ok(pairings),
+            report => bail!("unexpected report, expected pairings: {report:?}"),
+        }
+    }
+
+    async fn standings(&self, id: TournamentId) -> anyhow::Result<Vec<Standing>> {
+        match self.inspect(&format!("standings/{id}")).await? {
+            Report::Standings { standings } => Ok(standings),
+            report => bail!("unexpected report, expected standings: {report:?}"),
+        }
+    }
+
+    async fn rating_history(&self, address: Address) -> anyhow::Result<Vec<RatingChange>> {
+        match self.inspect(&format!("rating-history/{address}")).await? {
+            Report::RatingHistory { history } => Ok(history),
+            report => bail!("unexpected report, expected rating history: {report:?}"),
+        }
+    }
+
+    async fn leaderboard(
+        &self,
+        pool: Pool,
+        limit: u32,
+        min_games: u32,
+        max_deviation: f64,
+    ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+        match self
+            .inspect(&format!(
+                "leaderboard/{pool}/{limit}/{min_games}/{max_deviation}"
+            ))
+            .await?
+        {
+            Report::Leaderboard { leaderboard } => Ok(leaderboard),
+            report => bail!("unexpected report, expected leaderboard: {report:?}"),
+        }
+    }
 }
@@ -1,13 +1,49 @@
 use alloy::primitives::Address;
 use chesspresso_core::{
-    game::{GameId, San},
-    message::{Game, UserStats},
+    clock::Clock,
+    game::{GameHash, GameId, San},
+    message::{
+        Challenge, DrawOffer, Game, GameFilter, GameState, LeaderboardEntry, Pairing, Puzzle,
+        PuzzleId, PuzzleRanking, PuzzleSolve, RatingChange, Seek, Standing, TakebackRequest,
+        Tournament, TournamentId, UserStats,
+    },
+    rating::Pool,
+    wager::Stake,
 };
 use futures::{future::Future, stream::Stream};
+use std::time::Duration;
 
+mod backoff;
+pub mod espresso;
+pub mod graphql;
 pub mod inspect;
+pub mod server;
+pub mod subscription;
 
-pub use self::inspect::InspectIndexer;
+pub use self::{
+    espresso::EspressoIndexer, graphql::GraphQlIndexer, inspect::InspectIndexer,
+    subscription::SubscriptionIndexer,
+};
+
+use self::backoff::Backoff;
+
+/// The default interval between polls while there is activity.
+pub const DEFAULT_POLLING_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The default interval between polls once the node has been idle for a while.
+pub const DEFAULT_MAX_POLLING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A change to the moves of a game.
+#[derive(Clone, Debug)]
+pub enum MoveUpdate {
+    /// A new move was played, along with the hash of the game state after the move.
+    ///
+    /// The hash is [`None`] if the indexer failed to report it, in which case the move cannot be
+    /// verified and should not be trusted.
+    Move(San, Option<GameHash>),
+    /// Moves were taken back, leaving only the first `half_move` half moves.
+    TakeBack(u16),
+}
 
 pub trait Indexer {
     fn games_with_user(
@@ -15,9 +51,63 @@ pub trait Indexer {
         address: Address,
         after: Option<GameId>,
     ) -> impl Stream<Item = Game> + Send + Unpin;
-    fn moves(&self, id: GameId, from: u16) -> impl Stream<Item = San> + Send + Unpin;
+    fn games(
+        &self,
+        address: Address,
+        filter: GameFilter,
+    ) -> impl Future<Output = anyhow::Result<Vec<Game>>> + Send;
+    fn moves(&self, id: GameId, from: u16) -> impl Stream<Item = MoveUpdate> + Send + Unpin;
+    fn state(&self, id: GameId) -> impl Future<Output = anyhow::Result<GameState>> + Send;
+    fn pgn(&self, id: GameId) -> impl Future<Output = anyhow::Result<String>> + Send;
     fn user_stats(
         &self,
         address: Address,
     ) -> impl Future<Output = anyhow::Result<UserStats>> + Send;
+    fn draw_offers(
+        &self,
+        address: Address,
+    ) -> impl Future<Output = anyhow::Result<Vec<DrawOffer>>> + Send;
+    fn takeback_requests(
+        &self,
+        address: Address,
+    ) -> impl Future<Output = anyhow::Result<Vec<TakebackRequest>>> + Send;
+    fn clock(&self, id: GameId) -> impl Future<Output = anyhow::Result<Option<Clock>>> + Send;
+    fn seeks(&self) -> impl Future<Output = anyhow::Result<Vec<Seek>>> + Send;
+    fn incoming_challenges(
+        &self,
+        address: Address,
+    ) -> impl Future<Output = anyhow::Result<Vec<Challenge>>> + Send;
+    fn outgoing_challenges(
+        &self,
+        address: Address,
+    ) -> impl Future<Output = anyhow::Result<Vec<Challenge>>> + Send;
+    fn puzzles(&self) -> impl Future<Output = anyhow::Result<Vec<Puzzle>>> + Send;
+    fn puzzle_solvers(
+        &self,
+        id: PuzzleId,
+    ) -> impl Future<Output = anyhow::Result<Vec<PuzzleSolve>>> + Send;
+    fn puzzle_rankings(&self) -> impl Future<Output = anyhow::Result<Vec<PuzzleRanking>>> + Send;
+    fn balances(&self, address: Address)
+        -> impl Future<Output = anyhow::Result<Vec<Stake>>> + Send;
+    fn tournaments(&self) -> impl Future<Output = anyhow::Result<Vec<Tournament>>> + Send;
+    fn pairings(
+        &self,
+        id: TournamentId,
+        round: Option<u32>,
+    ) -> impl Future<Output = anyhow::Result<Vec<Pairing>>> + Send;
+    fn standings(
+        &self,
+        id: TournamentId,
+    ) -> impl Future<Output = anyhow::Result<Vec<Standing>>> + Send;
+    fn rating_history(
+        &self,
+        address: Address,
+    ) -> impl Future<Output = anyhow::Result<Vec<RatingChange>>> + Send;
+    fn leaderboard(
+        &self,
+        pool: Pool,
+        limit: u32,
+        min_games: u32,
+        max_deviation: f64,
+    ) -> impl Future<Output = anyhow::Result<Vec<LeaderboardEntry>>> + Send;
 }
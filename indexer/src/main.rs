@@ -0,0 +1,80 @@
+use chesspresso_core::{
+    db::Db,
+    rating::{self, RatingSystem},
+};
+use chesspresso_indexer::{server, GraphQlIndexer};
+use clap::Parser;
+use std::{path::PathBuf, time::Duration};
+use tracing_subscriber::EnvFilter;
+use url::Url;
+
+/// Indexer service for Chesspresso.
+///
+/// Follows finalized inputs and notices through the node's GraphQL API and serves games, moves and
+/// statistics to clients over HTTP.
+#[derive(Parser)]
+struct Options {
+    #[clap(short = 'u', long, env = "CHESSPRESSO_NODE_URL")]
+    node_url: Url,
+
+    /// Database file in which to keep the reconstructed games.
+    ///
+    /// The indexer resumes from where it left off when restarted with the same database. If not
+    /// given, games are kept in memory, and all inputs are replayed from the start on every restart.
+    #[clap(long, env = "CHESSPRESSO_INDEXER_DB")]
+    db: Option<PathBuf>,
+
+    /// The port on which to serve the indexer API.
+    #[clap(short, long, env = "CHESSPRESSO_INDEXER_PORT", default_value = "8081")]
+    port: u16,
+
+    /// How often to poll the node for new inputs while there is activity, in milliseconds.
+    #[clap(long, env = "CHESSPRESSO_POLLING_INTERVAL", default_value = "500")]
+    polling_interval: u64,
+
+    /// How often to poll the node once it has been idle for a while, in milliseconds.
+    #[clap(
+        long,
+        env = "CHESSPRESSO_MAX_POLLING_INTERVAL",
+        default_value = "10000"
+    )]
+    max_polling_interval: u64,
+
+    /// The length of a rating period configured in the dApp, in seconds.
+    #[clap(long, env = "CHESSPRESSO_RATING_PERIOD", default_value_t = rating::DEFAULT_PERIOD)]
+    rating_period: u64,
+
+    /// The Glicko-2 system constant configured in the dApp.
+    #[clap(
+        long,
+        env = "CHESSPRESSO_RATING_SYSTEM_CONSTANT",
+        default_value_t = rating::DEFAULT_SYSTEM_CONSTANT
+    )]
+    rating_system_constant: f64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_ansi(true)
+        .init();
+    let opt = Options::parse();
+
+    let rating_system = RatingSystem {
+        period: opt.rating_period,
+        system_constant: opt.rating_system_constant,
+    };
+    let db = match &opt.db {
+        Some(path) => Db::open(path).await?,
+        None => Db::memory().await?,
+    };
+    let indexer = GraphQlIndexer::with_db(opt.node_url, db, rating_system)
+        .await?
+        .with_polling_interval(
+            Duration::from_millis(opt.polling_interval),
+            Duration::from_millis(opt.max_polling_interval),
+        )
+        .sync_in_background();
+    server::serve(indexer, opt.port).await
+}
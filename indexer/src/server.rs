@@ -0,0 +1,215 @@
+//! A standalone indexer service, which many clients can share instead of each querying the node.
+//!
+//! The service follows the node with a [`GraphQlIndexer`], keeping its own database of games in
+//! sync in the background, and serves a versioned REST API from that database:
+//!
+//! * `GET /v1/users/<address>/games[?filter=all|active|finished]`: the games of a user.
+//! * `GET /v1/users/<address>/stats`: a user's statistics.
+//! * `GET /v1/games/<id>`: a game.
+//! * `GET /v1/games/<id>/state`: the current state of a game.
+//! * `GET /v1/games/<id>/moves[?from=<half-move>]`: the moves of a game, in SAN, each with the
+//!   hash of the game state after the move.
+//! * `GET /v1/games/<id>/pgn`: a game in PGN.
+//!
+//! Responses are JSON, except for PGN. The service also serves the server-sent event streams
+//! described in [`subscription`](crate::subscription).
+
+use crate::{GraphQlIndexer, Indexer};
+use alloy::primitives::Address;
+use anyhow::Context;
+use chesspresso_core::game::{GameHash, GameId};
+use futures::stream::{self, Stream, StreamExt};
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap, convert::Infallible, net::SocketAddr, str::FromStr, time::Duration,
+};
+use tokio::time::sleep;
+
+/// How often to send a comment on an idle event stream, so the connection is not dropped.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A move, with the hash of the game state after it.
+#[derive(Serialize)]
+struct HashedMove {
+    san: String,
+    hash: GameHash,
+}
+
+/// A failed request, with the status to respond with.
+type Error = (StatusCode, anyhow::Error);
+
+/// Serve the indexer API on `port`, answering queries from `indexer`.
+pub async fn serve(indexer: GraphQlIndexer, port: u16) -> anyhow::Result<()> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_| {
+        let indexer = indexer.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let indexer = indexer.clone();
+                async move { Ok::<_, Infallible>(handle(indexer, req).await) }
+            }))
+        }
+    });
+    tracing::info!(%addr, "serving indexer API");
+    Server::bind(&addr).serve(make_service).await?;
+    Ok(())
+}
+
+async fn handle(indexer: GraphQlIndexer, req: Request<Body>) -> Response<Body> {
+    tracing::debug!(uri = %req.uri(), "request");
+    if req.method() != Method::GET {
+        return error(StatusCode::METHOD_NOT_ALLOWED, "only GET is supported");
+    }
+    let segments: Vec<&str> = req.uri().path().trim_matches('/').split('/').collect();
+    let params = query_params(req.uri().query());
+    match route(&indexer, &segments, &params).await {
+        Ok(response) => response,
+        Err((status, err)) => {
+            tracing::warn!(uri = %req.uri(), %status, "error handling request: {err:#}");
+            error(status, format!("{err:#}"))
+        }
+    }
+}
+
+async fn route(
+    indexer: &GraphQlIndexer,
+    segments: &[&str],
+    params: &HashMap<&str, &str>,
+) -> Result<Response<Body>, Error> {
+    match segments {
+        ["v1", "users", address, "games"] => {
+            let address: Address = parse(address, "address")?;
+            let filter = param(params, "filter")?.unwrap_or_default();
+            json(&indexer.games(address, filter).await.map_err(internal)?)
+        }
+        ["v1", "users", address, "games", "events"] => {
+            let address: Address = parse(address, "address")?;
+            let after: Option<GameId> = param(params, "after")?;
+            Ok(events(indexer.game_events(address, after)))
+        }
+        ["v1", "users", address, "stats"] => {
+            let address: Address = parse(address, "address")?;
+            json(&indexer.user_stats(address).await.map_err(internal)?)
+        }
+        ["v1", "games", id] => {
+            let id: GameId = parse(id, "game ID")?;
+            json(&indexer.game(id).await.map_err(not_found)?)
+        }
+        ["v1", "games", id, "state"] => {
+            let id: GameId = parse(id, "game ID")?;
+            json(&indexer.state(id).await.map_err(not_found)?)
+        }
+        ["v1", "games", id, "moves"] => {
+            let id: GameId = parse(id, "game ID")?;
+            let from = param(params, "from")?.unwrap_or(1);
+            let moves = indexer.move_list(id, from).await.map_err(internal)?;
+            let moves: Vec<_> = moves
+                .into_iter()
+                .map(|(san, hash)| HashedMove { san, hash })
+                .collect();
+            json(&moves)
+        }
+        ["v1", "games", id, "moves", "events"] => {
+            let id: GameId = parse(id, "game ID")?;
+            let from = param(params, "from")?.unwrap_or(1);
+            let takebacks = param(params, "takebacks")?.unwrap_or(0);
+            Ok(events(indexer.move_events(id, from, takebacks)))
+        }
+        ["v1", "games", id, "pgn"] => {
+            let id: GameId = parse(id, "game ID")?;
+            let pgn = indexer.pgn(id).await.map_err(not_found)?;
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, "application/x-chess-pgn")
+                .body(pgn.into())
+                .unwrap())
+        }
+        _ => Err((StatusCode::NOT_FOUND, anyhow::anyhow!("no such endpoint"))),
+    }
+}
+
+/// Respond with a stream of server-sent events, one for each item of `stream`.
+///
+/// If the stream ends, e.g. with [`MoveEvent::GameOver`](crate::subscription::MoveEvent), so does
+/// the response.
+fn events<T: Serialize + Send + 'static>(
+    stream: impl Stream<Item = T> + Send + 'static,
+) -> Response<Body> {
+    let events = stream
+        .filter_map(|item| async move {
+            match serde_json::to_string(&item) {
+                Ok(data) => Some(Some(format!("data: {data}\n\n"))),
+                Err(err) => {
+                    tracing::error!("error serializing event: {err:#}");
+                    None
+                }
+            }
+        })
+        // Mark the end of the stream, so we stop sending keep-alives.
+        .chain(stream::once(async { None }));
+    let keep_alive = stream::unfold((), |()| async {
+        sleep(KEEP_ALIVE_INTERVAL).await;
+        Some((Some(": keep-alive\n\n".to_string()), ()))
+    });
+    let body = stream::select(events, keep_alive)
+        .take_while(|event| futures::future::ready(event.is_some()))
+        .map(|event| Ok::<_, Infallible>(event.unwrap_or_default()));
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(body))
+        .unwrap()
+}
+
+fn json(body: &impl Serialize) -> Result<Response<Body>, Error> {
+    let body = serde_json::to_string(body).map_err(internal)?;
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .unwrap())
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(message.into().into())
+        .unwrap()
+}
+
+fn internal(err: impl Into<anyhow::Error>) -> Error {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.into())
+}
+
+fn not_found(err: impl Into<anyhow::Error>) -> Error {
+    (StatusCode::NOT_FOUND, err.into())
+}
+
+fn parse<T: FromStr>(s: &str, name: &str) -> Result<T, Error>
+where
+    T::Err: Into<anyhow::Error>,
+{
+    s.parse()
+        .map_err(Into::into)
+        .with_context(|| format!("invalid {name} {s}"))
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))
+}
+
+fn param<T: FromStr>(params: &HashMap<&str, &str>, name: &str) -> Result<Option<T>, Error>
+where
+    T::Err: Into<anyhow::Error>,
+{
+    params.get(name).map(|value| parse(value, name)).transpose()
+}
+
+fn query_params(query: Option<&str>) -> HashMap<&str, &str> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .collect()
+}
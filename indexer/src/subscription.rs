@@ -0,0 +1,403 @@
+//! An indexer which streams new games and moves from a standalone indexer service.
+//!
+//! Instead of polling, streams subscribe to server-sent events from the service:
+//!
+//! * `GET /v1/users/<address>/games/events[?after=<id>]` sends each new game involving `address`
+//!   (after game `after`), with a [`Game`] as the data of each event.
+//! * `GET /v1/games/<id>/moves/events?from=<half-move>&takebacks=<n>` sends each change to the
+//!   moves of a game, starting from half move `from`, for a client which knows about `n`
+//!   takebacks. The data of each event is a [`MoveEvent`].
+//!
+//! If a subscription drops, it is resumed from where it left off, reconnecting with exponential
+//! backoff. If the service remains unreachable, the stream falls back to polling another indexer.
+//!
+//! Queries for games, statistics and PGN are answered by the REST API of the service (see
+//! [`server`](crate::server)), or by the fallback indexer if the service fails. All other queries
+//! are answered by the fallback indexer.
+
+use crate::{Backoff, Indexer, MoveUpdate};
+use alloy::primitives::Address;
+use anyhow::{ensure, Context};
+use chesspresso_core::{
+    clock::Clock,
+    game::{GameHash, GameId},
+    message::{
+        Challenge, DrawOffer, Game, GameFilter, GameState, LeaderboardEntry, Pairing, Puzzle,
+        PuzzleId, PuzzleRanking, PuzzleSolve, RatingChange, Seek, Standing, TakebackRequest,
+        Tournament, TournamentId, UserStats,
+    },
+    rating::Pool,
+    wager::Stake,
+};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    stream::{self, Stream, StreamExt},
+};
+use hyper::{body::HttpBody, client::connect::HttpConnector, Body, Client, Method, Request};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+use tokio::{spawn, time::sleep};
+use url::Url;
+
+/// The number of consecutive failed attempts to subscribe before falling back to polling.
+const MAX_RECONNECTS: u32 = 5;
+
+/// A change to the moves of a game, as sent by the indexer service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum MoveEvent {
+    /// A new move was played. `hash` is the hash of the game state after the move.
+    Move {
+        san: String,
+        #[serde(default)]
+        hash: Option<GameHash>,
+    },
+    /// Moves were taken back, leaving only the first `half_move` half moves. `takebacks` is the
+    /// total number of takebacks in the game so far.
+    TakeBack { half_move: u16, takebacks: u32 },
+    /// The game is over, and there will be no more events.
+    GameOver,
+}
+
+#[derive(Clone, Debug)]
+pub struct SubscriptionIndexer<I> {
+    client: Client<HttpConnector>,
+    service_url: Url,
+    reconnect: Backoff,
+    fallback: I,
+}
+
+impl<I: Indexer + Clone + Send + Sync + 'static> SubscriptionIndexer<I> {
+    /// Subscribe to the indexer service at `service_url`, falling back to `fallback`.
+    pub fn new(service_url: Url, fallback: I) -> Self {
+        Self {
+            client: Client::new(),
+            service_url,
+            reconnect: Backoff::new(Duration::from_millis(500), Duration::from_secs(30)),
+            fallback,
+        }
+    }
+
+    /// Query the REST API of the service.
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        let body = self.get_raw(path).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn get_raw(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let url = format!("{}{path}", &self.service_url);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(Default::default())?;
+        let response = self.client.request(request).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response).await?;
+        ensure!(
+            status.is_success(),
+            "{url}: indexer error: {status}: {}",
+            String::from_utf8_lossy(&body)
+        );
+        Ok(body.into())
+    }
+
+    /// Subscribe to the events at `path`, returning the data of each event.
+    async fn subscribe(
+        &self,
+        path: &str,
+    ) -> anyhow::Result<impl Stream<Item = anyhow::Result<String>> + Send + Unpin> {
+        let url = format!("{}{path}", &self.service_url);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .header(hyper::header::ACCEPT, "text/event-stream")
+            .body(Default::default())?;
+        let response = self.client.request(request).await?;
+        ensure!(
+            response.status().is_success(),
+            "{url}: subscription error: {}",
+            response.status()
+        );
+        Ok(events(response.into_body()))
+    }
+
+    async fn follow_games(
+        self,
+        address: Address,
+        mut after: Option<GameId>,
+        tx: UnboundedSender<Game>,
+    ) {
+        let mut reconnect = self.reconnect;
+        let mut failures = 0;
+        while failures < MAX_RECONNECTS {
+            let mut path = format!("v1/users/{address}/games/events");
+            if let Some(after) = after {
+                path = format!("{path}?after={after}");
+            }
+            match self.subscribe(&path).await {
+                Ok(mut events) => {
+                    reconnect.reset();
+                    failures = 0;
+                    while let Some(event) = events.next().await {
+                        let game: Game =
+                            match event.and_then(|data| Ok(serde_json::from_str(&data)?)) {
+                                Ok(game) => game,
+                                Err(err) => {
+                                    tracing::warn!("error in games subscription: {err:#}");
+                                    break;
+                                }
+                            };
+                        after = Some(game.id);
+                        if tx.unbounded_send(game).is_err() {
+                            return;
+                        }
+                    }
+                    tracing::info!(%address, "games subscription closed");
+                }
+                Err(err) => {
+                    failures += 1;
+                    tracing::warn!(%address, failures, "error subscribing to games: {err:#}");
+                }
+            }
+            if tx.is_closed() {
+                return;
+            }
+            sleep(reconnect.next()).await;
+        }
+
+        tracing::warn!(%address, "indexer service unavailable, falling back to polling for games");
+        let mut games = self.fallback.games_with_user(address, after);
+        while let Some(game) = games.next().await {
+            if tx.unbounded_send(game).is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn follow_moves(self, id: GameId, mut from: u16, tx: UnboundedSender<MoveUpdate>) {
+        let mut reconnect = self.reconnect;
+        let mut failures = 0;
+        let mut takebacks = 0;
+        while failures < MAX_RECONNECTS {
+            let path = format!("v1/games/{id}/moves/events?from={from}&takebacks={takebacks}");
+            match self.subscribe(&path).await {
+                Ok(mut events) => {
+                    reconnect.reset();
+                    failures = 0;
+                    while let Some(event) = events.next().await {
+                        let event = match event.and_then(|data| Ok(serde_json::from_str(&data)?)) {
+                            Ok(event) => event,
+                            Err(err) => {
+                                tracing::warn!("error in moves subscription: {err:#}");
+                                break;
+                            }
+                        };
+                        let update = match event {
+                            MoveEvent::Move { san, hash } => match san.parse() {
+                                Ok(san) => {
+                                    from += 1;
+                                    MoveUpdate::Move(san, hash)
+                                }
+                                Err(err) => {
+                                    tracing::warn!(san, "error parsing move: {err:#}");
+                                    break;
+                                }
+                            },
+                            MoveEvent::TakeBack {
+                                half_move,
+                                takebacks: total,
+                            } => {
+                                from = half_move + 1;
+                                takebacks = total;
+                                MoveUpdate::TakeBack(half_move)
+                            }
+                            MoveEvent::GameOver => return,
+                        };
+                        if tx.unbounded_send(update).is_err() {
+                            return;
+                        }
+                    }
+                    tracing::info!(%id, "moves subscription closed");
+                }
+                Err(err) => {
+                    failures += 1;
+                    tracing::warn!(%id, failures, "error subscribing to moves: {err:#}");
+                }
+            }
+            if tx.is_closed() {
+                return;
+            }
+            sleep(reconnect.next()).await;
+        }
+
+        tracing::warn!(%id, "indexer service unavailable, falling back to polling for moves");
+        let mut moves = self.fallback.moves(id, from);
+        while let Some(update) = moves.next().await {
+            if tx.unbounded_send(update).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl<I: Indexer + Clone + Send + Sync + 'static> Indexer for SubscriptionIndexer<I> {
+    fn games_with_user(
+        &self,
+        address: Address,
+        after: Option<GameId>,
+    ) -> impl Stream<Item = Game> + Unpin {
+        let (tx, rx) = mpsc::unbounded();
+        spawn(self.clone().follow_games(address, after, tx));
+        rx
+    }
+
+    async fn games(&self, address: Address, filter: GameFilter) -> anyhow::Result<Vec<Game>> {
+        match self
+            .get(&format!("v1/users/{address}/games?filter={filter}"))
+            .await
+        {
+            Ok(games) => Ok(games),
+            Err(err) => {
+                tracing::warn!(%address, "error querying games from indexer service: {err:#}");
+                self.fallback.games(address, filter).await
+            }
+        }
+    }
+
+    fn moves(&self, id: GameId, from: u16) -> impl Stream<Item = MoveUpdate> + Unpin {
+        let (tx, rx) = mpsc::unbounded();
+        spawn(self.clone().follow_moves(id, from, tx));
+        rx
+    }
+
+    async fn state(&self, id: GameId) -> anyhow::Result<GameState> {
+        match self.get(&format!("v1/games/{id}/state")).await {
+            Ok(state) => Ok(state),
+            Err(err) => {
+                tracing::warn!(%id, "error querying state from indexer service: {err:#}");
+                self.fallback.state(id).await
+            }
+        }
+    }
+
+    async fn pgn(&self, id: GameId) -> anyhow::Result<String> {
+        let pgn = self
+            .get_raw(&format!("v1/games/{id}/pgn"))
+            .await
+            .and_then(|pgn| String::from_utf8(pgn).context("PGN is not UTF-8"));
+        match pgn {
+            Ok(pgn) => Ok(pgn),
+            Err(err) => {
+                tracing::warn!(%id, "error querying PGN from indexer service: {err:#}");
+                self.fallback.pgn(id).await
+            }
+        }
+    }
+
+    async fn user_stats(&self, address: Address) -> anyhow::Result<UserStats> {
+        match self.get(&format!("v1/users/{address}/stats")).await {
+            Ok(stats) => Ok(stats),
+            Err(err) => {
+                tracing::warn!(%address, "error querying stats from indexer service: {err:#}");
+                self.fallback.user_stats(address).await
+            }
+        }
+    }
+
+    async fn draw_offers(&self, address: Address) -> anyhow::Result<Vec<DrawOffer>> {
+        self.fallback.draw_offers(address).await
+    }
+
+    async fn takeback_requests(&self, address: Address) -> anyhow::Result<Vec<TakebackRequest>> {
+        self.fallback.takeback_requests(address).await
+    }
+
+    async fn clock(&self, id: GameId) -> anyhow::Result<Option<Clock>> {
+        self.fallback.clock(id).await
+    }
+
+    async fn seeks(&self) -> anyhow::Result<Vec<Seek>> {
+        self.fallback.seeks().await
+    }
+
+    async fn incoming_challenges(&self, address: Address) -> anyhow::Result<Vec<Challenge>> {
+        self.fallback.incoming_challenges(address).await
+    }
+
+    async fn outgoing_challenges(&self, address: Address) -> anyhow::Result<Vec<Challenge>> {
+        self.fallback.outgoing_challenges(address).await
+    }
+
+    async fn puzzles(&self) -> anyhow::Result<Vec<Puzzle>> {
+        self.fallback.puzzles().await
+    }
+
+    async fn puzzle_solvers(&self, id: PuzzleId) -> anyhow::Result<Vec<PuzzleSolve>> {
+        self.fallback.puzzle_solvers(id).await
+    }
+
+    async fn puzzle_rankings(&self) -> anyhow::Result<Vec<PuzzleRanking>> {
+        self.fallback.puzzle_rankings().await
+    }
+
+    async fn balances(&self, address: Address) -> anyhow::Result<Vec<Stake>> {
+        self.fallback.balances(address).await
+    }
+
+    async fn tournaments(&self) -> anyhow::Result<Vec<Tournament>> {
+        self.fallback.tournaments().await
+    }
+
+    async fn pairings(&self, id: TournamentId, round: Option<u32>) -> anyhow::Result<Vec<Pairing>> {
+        self.fallback.pairings(id, round).await
+    }
+
+    async fn standings(&self, id: TournamentId) -> anyhow::Result<Vec<Standing>> {
+        self.fallback.standings(id).await
+    }
+
+    async fn rating_history(&self, address: Address) -> anyhow::Result<Vec<RatingChange>> {
+        self.fallback.rating_history(address).await
+    }
+
+    async fn leaderboard(
+        &self,
+        pool: Pool,
+        limit: u32,
+        min_games: u32,
+        max_deviation: f64,
+    ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+        self.fallback
+            .leaderboard(pool, limit, min_games, max_deviation)
+            .await
+    }
+}
+
+/// Split a `text/event-stream` body into the data of each event.
+///
+/// Events are separated by blank lines. Lines other than `data` fields, such as comments sent to
+/// keep the connection alive, are ignored, as are events without data.
+fn events(body: Body) -> impl Stream<Item = anyhow::Result<String>> + Send + Unpin {
+    stream::unfold((body, vec![]), |(mut body, mut buf)| async move {
+        loop {
+            if let Some(end) = buf.windows(2).position(|window| window == b"\n\n") {
+                let event: Vec<u8> = buf.drain(..end + 2).collect();
+                let data = String::from_utf8_lossy(&event)
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data:"))
+                    .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if data.is_empty() {
+                    continue;
+                }
+                return Some((Ok(data), (body, buf)));
+            }
+            match body.data().await? {
+                Ok(chunk) => buf.extend(chunk.iter().filter(|byte| **byte != b'\r')),
+                Err(err) => return Some((Err(err.into()), (body, buf))),
+            }
+        }
+    })
+    .boxed()
+}